@@ -0,0 +1,59 @@
+#version 410
+out vec4 FragColor;
+
+in vec2 texCoords;
+
+uniform float time;
+uniform vec2 resolution;
+uniform float frame_nb;
+
+uniform sampler2D pathbuffer;
+uniform sampler2D moments;
+
+// 0: off, 1: relative error of each pixel, 2: how many of the frames each
+// pixel got a sample in.
+uniform float debug_view;
+uniform float adaptive_threshold;
+
+#define LUMA vec3(0.2126, 0.7152, 0.0722)
+#define ERROR_BIAS 0.01
+
+float relative_error(vec4 acc, vec4 mom) {
+	if (acc.a < 2.0) {
+		return 1e9;
+	}
+	float mean = dot(acc.rgb, LUMA) / acc.a;
+	float variance = max(mom.x / acc.a - mean * mean, 0.0);
+	return sqrt(variance / acc.a) / (mean + ERROR_BIAS);
+}
+
+// Blue for 0, green for 0.5, red for 1.
+vec3 heat(float t) {
+	t = clamp(t, 0.0, 1.0);
+	return vec3(clamp(2.0 * t - 1.0, 0.0, 1.0), 1.0 - abs(2.0 * t - 1.0), clamp(1.0 - 2.0 * t, 0.0, 1.0));
+}
+
+void main()
+{
+	if (debug_view < 0.5) {
+		discard;
+	}
+
+	ivec2 coord = ivec2(texCoords * resolution.xy);
+	vec4 acc = texelFetch(pathbuffer, coord, 0);
+	vec4 mom = texelFetch(moments, coord, 0);
+
+	if (debug_view < 1.5) {
+		// From 1% error and below in blue, to 100% and above in red.
+		float error = relative_error(acc, mom);
+		vec3 col = heat((log(error) / log(10.0) + 2.0) / 2.0);
+		if (adaptive_threshold > 0.0 && error < adaptive_threshold) {
+			// Not getting samples anymore.
+			col *= 0.3;
+		}
+		FragColor = vec4(col, 1.0);
+	} else {
+		// The path tracer already ran this frame.
+		FragColor = vec4(heat(acc.a / (frame_nb + 1.0)), 1.0);
+	}
+}
@@ -1,13 +1,42 @@
 #version 410
-out vec4 FragColor;
+layout(location = 0) out vec4 FragColor;
+// Sum of the squared luminance of the samples in x.
+layout(location = 1) out vec4 Moments;
 
 in vec2 TexCoords;
 
 uniform float time;
 uniform vec2 resolution;
 uniform float frame_nb;
+// Different for every sample: the bits of the time, or a count from a fixed
+// seed.
+uniform uint seed;
 //uniform sampler2D backbuffer;
 //uniform sampler2D scenebuffer;
+uniform sampler2D previous_frame;
+uniform sampler2D previous_moments;
+
+// Pixels whose relative error is below this stop getting samples, once they
+// have at least adaptive_min_samples. 0 disables adaptive sampling.
+uniform float adaptive_threshold;
+uniform float adaptive_min_samples;
+
+// Equirectangular environment lighting the scene, see set_environment.
+// env_enabled is 0 without one, and the procedural sky is used instead.
+uniform sampler2D env_map;
+uniform sampler2D env_marginal;
+uniform sampler2D env_conditional;
+uniform float env_enabled;
+uniform vec2 env_size;
+uniform float env_total;
+// Around the vertical axis, in radians.
+uniform float env_rotation;
+uniform float env_intensity;
+
+// The objects and materials of the scene, see peglrs/src/world. Row 0 holds
+// the materials and row 1 the objects, 3 texels each.
+uniform sampler2D world;
+uniform float world_objects;
 
 uniform vec3 in_eye;
 uniform vec3 in_target;
@@ -28,8 +57,16 @@ uniform float in_aperture;
 #define VOLUME 3
 #define EMISSIVE 99
 
+#define SPHERE 0
+#define RECT 1
+#define BOX 2
+
 #define SAMPLING 4
 
+#define LUMA vec3(0.2126, 0.7152, 0.0722)
+// Must match ERROR_BIAS in peglrs/src/noise.
+#define ERROR_BIAS 0.01
+
 #define L_POS vec3(0.0, 5.0, 3.0)
 
 float g_seed = 0.0;
@@ -59,6 +96,12 @@ vec3 random_in_unit_sphere(inout float seed) {
   return r * vec3(sqrt(1. - h.x * h.x) * vec2(sin(phi), cos(phi)), h.x);
 }
 
+vec3 random_unit_vector(inout float seed) {
+  vec2 h = hash2(seed) * vec2(2., 6.28318530718) - vec2(1, 0);
+  float r = sqrt(1. - h.x * h.x);
+  return vec3(r * vec2(sin(h.y), cos(h.y)), h.x);
+}
+
 vec3 random_in_unit_disk() {
     while (true) {
         vec3 p = vec3(hash1(g_seed), hash1(g_seed), 0);
@@ -91,6 +134,7 @@ struct material {
   vec3 emission;
   float fuzz;
   float refraction;
+  float density;
   int type;
 };
 
@@ -108,9 +152,17 @@ struct sphere {
   material m;
 };
 
+// In the plane of the two axes other than `axis`, at k along it.
 struct rect {
   vec4 pos;
   float k;
+  int axis;
+  material m;
+};
+
+struct box {
+  vec3 bmin;
+  vec3 bmax;
   material m;
 };
 
@@ -137,6 +189,7 @@ material new_material(vec3 a, int t, float f, float r) {
   m.type = t;
   m.fuzz = f;
   m.refraction = r;
+  m.density = 0.0;
   return m;
 }
 
@@ -185,20 +238,24 @@ void set_face_normal(inout hit h, in ray r, in vec3 outward_normal) {
   h.normal = h.front_face ? outward_normal : -outward_normal;
 }
 
-bool hit_rect_xy(in rect m_rect,  in ray r, in float t_min, in float t_max, out hit h) {
-  float t = (m_rect.k - r.origin.z) / r.direction.z;
+bool hit_rect(in rect m_rect,  in ray r, in float t_min, in float t_max, out hit h) {
+  int n = m_rect.axis;
+  int u = n == 0 ? 1 : 0;
+  int v = n == 2 ? 1 : 2;
+  float t = (m_rect.k - r.origin[n]) / r.direction[n];
   if(t < t_min || t > t_max) {
     return false;
   }
 
-  float x = r.origin.x + t*r.direction.x;
-  float y = r.origin.y + t*r.direction.y;
+  float x = r.origin[u] + t*r.direction[u];
+  float y = r.origin[v] + t*r.direction[v];
   if(x < m_rect.pos.x || x > m_rect.pos.y || y < m_rect.pos.z || y > m_rect.pos.w) {
     return false;
   }
 
   h.t = t;
-  vec3 outward_normal = vec3(0.0, 0.0, 1.0);
+  vec3 outward_normal = vec3(0.0);
+  outward_normal[n] = 1.0;
   set_face_normal(h, r, outward_normal);
   h.m = m_rect.m;
   h.p = point_at(r, t);
@@ -206,6 +263,41 @@ bool hit_rect_xy(in rect m_rect,  in ray r, in float t_min, in float t_max, out
   return true;
 }
 
+bool hit_box(in box b, in ray r, in float t_min, in float t_max, out hit h) {
+  vec3 t0 = (b.bmin - r.origin) / r.direction;
+  vec3 t1 = (b.bmax - r.origin) / r.direction;
+  vec3 t_near = min(t0, t1);
+  vec3 t_far = max(t0, t1);
+  float enter = max(max(t_near.x, t_near.y), t_near.z);
+  float leave = min(min(t_far.x, t_far.y), t_far.z);
+  if(enter > leave) {
+    return false;
+  }
+
+  // From inside, the ray leaves through the far side.
+  float t = enter;
+  if(t < t_min || t > t_max) {
+    t = leave;
+    if(t < t_min || t > t_max) {
+      return false;
+    }
+  }
+
+  h.t = t;
+  h.p = point_at(r, t);
+  // The face hit is the one the point is the furthest along.
+  vec3 center = (b.bmin + b.bmax) * 0.5;
+  vec3 d = (h.p - center) / max(b.bmax - b.bmin, vec3(1e-6));
+  vec3 a = abs(d);
+  vec3 outward_normal = vec3(0.0);
+  int n = a.x > a.y ? (a.x > a.z ? 0 : 2) : (a.y > a.z ? 1 : 2);
+  outward_normal[n] = sign(d[n]);
+  set_face_normal(h, r, outward_normal);
+  h.m = b.m;
+
+  return true;
+}
+
 bool hit_sphere(in sphere s, in ray r, in float t_min, in float t_max, out hit h) {
   vec3 oc = r.origin - s.center;
   float a = dot(r.direction, r.direction);
@@ -234,13 +326,48 @@ bool hit_sphere(in sphere s, in ray r, in float t_min, in float t_max, out hit h
   return true;
 }
 
-bool volume_sphere_hit(in sphere m_sphere, in ray r, in float t_min, in float t_max, out hit h, float neg_inv_density) {
+material world_material(int idx) {
+  vec4 a = texelFetch(world, ivec2(idx * 3, 0), 0);
+  vec4 b = texelFetch(world, ivec2(idx * 3 + 1, 0), 0);
+  vec4 c = texelFetch(world, ivec2(idx * 3 + 2, 0), 0);
+  material m = new_material(a.rgb, int(a.w), b.w, c.x);
+  m.emission = b.rgb;
+  m.density = c.y;
+  return m;
+}
+
+// The surface of object idx. h.m is left for the caller to fill in.
+bool hit_object(int idx, in ray r, in float t_min, in float t_max, out hit h) {
+  vec4 a = texelFetch(world, ivec2(idx * 3, 1), 0);
+  vec4 b = texelFetch(world, ivec2(idx * 3 + 1, 1), 0);
+  vec4 c = texelFetch(world, ivec2(idx * 3 + 2, 1), 0);
+  material m;
+  int kind = int(a.x);
+  if (kind == SPHERE) {
+    return hit_sphere(new_sphere(b.xyz, b.w, m), r, t_min, t_max, h);
+  } else if (kind == RECT) {
+    rect re;
+    re.pos = b;
+    re.k = c.x;
+    re.axis = int(c.y);
+    re.m = m;
+    return hit_rect(re, r, t_min, t_max, h);
+  }
+  box bx;
+  bx.bmin = b.xyz;
+  bx.bmax = c.xyz;
+  bx.m = m;
+  return hit_box(bx, r, t_min, t_max, h);
+}
+
+// A random point in the fog filling object idx, further for a thinner fog.
+bool hit_volume(int idx, in ray r, in float t_min, in float t_max, out hit h, float density) {
   hit r1;
   hit r2;
-  if(!hit_sphere(m_sphere, r, -1e6, 1e6, r1)) {
+  if(!hit_object(idx, r, -1e6, 1e6, r1)) {
     return false;
   }
-  if(!hit_sphere(m_sphere, r, r1.t+0.001, 1e6, r2)) {
+  if(!hit_object(idx, r, r1.t+0.001, 1e6, r2)) {
     return false;
   }
 
@@ -261,7 +388,7 @@ bool volume_sphere_hit(in sphere m_sphere, in ray r, in float t_min, in float t_
 
   float len = length(r.direction);
   float dist_in_bound = (r2.t - r1.t)*len;
-  float hit_dist = -(1.0/neg_inv_density) * log(hash1(g_seed));
+  float hit_dist = -(1.0/density) * log(hash1(g_seed));
   if(hit_dist > dist_in_bound) {
     return false;
   }
@@ -270,107 +397,119 @@ bool volume_sphere_hit(in sphere m_sphere, in ray r, in float t_min, in float t_
   h.p = point_at(r, h.t);
   h.normal = vec3(1.0, 0.0, 0.0);
   h.front_face = true;
-  h.m = m_sphere.m;
 
   return true;
 }
 
 bool hit_scene(in ray r, in float t_min, in float t_max, out hit h) {
-  sphere s1 =
-      new_sphere(vec3(0.0, -100.5, 0.0), 100.0,
-                 new_material(vec3(1.0), LAMBERTIAN, 0.0, 0.0));
-  
-  sphere s2 = new_sphere(L_POS, 0.4, new_material(vec3(0.0), EMISSIVE, 0.0, 0.0));
-  s2.m.emission = vec3(8.0);
+  hit tmp_hit;
+  float closest = t_max;
+  int closest_material = -1;
+
+  for (int i = 0; i < int(world_objects); ++i) {
+    int idx = int(texelFetch(world, ivec2(i * 3, 1), 0).y);
+    vec4 m = texelFetch(world, ivec2(idx * 3, 0), 0);
+    bool got_hit;
+    if (int(m.w) == VOLUME) {
+      float density = texelFetch(world, ivec2(idx * 3 + 2, 0), 0).y;
+      got_hit = hit_volume(i, r, t_min, closest, tmp_hit, density);
+    } else {
+      got_hit = hit_object(i, r, t_min, closest, tmp_hit);
+    }
+    if (got_hit) {
+      closest = tmp_hit.t;
+      closest_material = idx;
+      h = tmp_hit;
+    }
+  }
 
-  sphere s3 = new_sphere(vec3(-1.0, 0.4, -3.8), 0.4, new_material(vec3(0.1, 0.2, 0.5), METAL, 0.05, 0.0));
-  // s3.m.emission = vec3(10.0);
+  // Only the material of the closest hit is needed.
+  if (closest_material < 0) {
+    return false;
+  }
+  h.m = world_material(closest_material);
+  return true;
+}
 
-  sphere s4 = new_sphere(vec3(2.0, 1.0, -1.0), 1.0,
-                         new_material(vec3(0.8, 0.6, 0.2), DIELECTRIC, 0.00, 1.2));
-  
-  sphere s6 = new_sphere(vec3(2.0, 1.2, -1.0), -1.5,
-                         new_material(vec3(0.0), DIELECTRIC, 0.0, 0.95));
+// Environment coordinates of a direction: u goes around the vertical axis,
+// v from straight down (0) to straight up (1).
+vec2 env_uv(vec3 d) {
+  d = normalize(d);
+  float u = fract((atan(d.z, d.x) + env_rotation) / (2.0 * PI));
+  float v = 1.0 - acos(clamp(d.y, -1.0, 1.0)) / PI;
+  return vec2(u, v);
+}
 
+vec3 env_dir(vec2 uv) {
+  float phi = uv.x * 2.0 * PI - env_rotation;
+  float theta = (1.0 - uv.y) * PI;
+  return vec3(sin(theta) * cos(phi), cos(theta), sin(theta) * sin(phi));
+}
 
-  sphere s5 = new_sphere(vec3(-1.0, 0.4, -3.8), 1.0,
-                         new_material(vec3(0.0), DIELECTRIC, 0.0, 0.4));
+ivec2 env_texel(vec2 uv) {
+  return clamp(ivec2(uv * env_size), ivec2(0), ivec2(env_size) - 1);
+}
 
-  sphere s7 =
-      new_sphere(vec3(-34.0, 1.0, 0.0), 32,
-                 new_material(vec3(1.0, 0.5, 0.4), METAL, 0.0, 0.0));
-  
-  sphere s8 = new_sphere(vec3(0.0, 0.0, 0.0), 30, new_material(vec3(1.0), VOLUME, 0.0, 0.0));
+vec3 env_radiance(vec3 d) {
+  return texelFetch(env_map, env_texel(env_uv(d)), 0).rgb * env_intensity;
+}
 
-  rect r1;
-  r1.pos = vec4(-2.0, 2.0, -1.0, 3.0);
-  r1.k = -5.0;
-  r1.m = new_material(vec3(0.0), EMISSIVE, 0.0, 0.0);
-  r1.m.emission = vec3(0.8, 0.2, 0.4)*3.0;
+// Density over solid angle of env_sample picking d. Must match the CDFs
+// built in peglrs/src/texture/envmap.rs.
+float env_pdf(vec3 d) {
+  vec2 uv = env_uv(d);
+  ivec2 texel = env_texel(uv);
+  float luma = max(dot(texelFetch(env_map, texel, 0).rgb, LUMA), 0.0);
+  float sin_center = sin(PI * (1.0 - (float(texel.y) + 0.5) / env_size.y));
+  float sin_theta = max(sin((1.0 - uv.y) * PI), 1e-6);
+  return luma * sin_center / sin_theta * env_size.x * env_size.y / (2.0 * PI * PI * env_total);
+}
 
-  hit tmp_hit;
-  float closest = t_max;
-  bool got_hit = false;
-
-  if(volume_sphere_hit(s8, r, t_min, closest, tmp_hit, 0.06)) {
-    closest = tmp_hit.t;
-    got_hit = true;
-    h = tmp_hit;
-  }
-  if (hit_sphere(s1, r, t_min, closest, tmp_hit)) {
-    closest = tmp_hit.t;
-    got_hit = true;
-    h = tmp_hit;
-  } 
-  if (hit_sphere(s2, r, t_min, closest, tmp_hit)) {
-    closest = tmp_hit.t;
-    got_hit = true;
-    h = tmp_hit;
-  }  
-  if (hit_sphere(s3, r, t_min, closest, tmp_hit)) {
-    closest = tmp_hit.t;
-    got_hit = true;
-    h = tmp_hit;
-  }  
-  if (hit_sphere(s4, r, t_min, closest, tmp_hit)) {
-    closest = tmp_hit.t;
-    got_hit = true;
-    h = tmp_hit;
-  }  
-  if (hit_sphere(s5, r, t_min, closest, tmp_hit)) {
-    closest = tmp_hit.t;
-    got_hit = true;
-    h = tmp_hit;
-  }  
-  if (hit_sphere(s6, r, t_min, closest, tmp_hit)) {
-    closest = tmp_hit.t;
-    got_hit = true;
-    h = tmp_hit;
-  }  
-  if (hit_sphere(s7, r, t_min, closest, tmp_hit)) {
-    closest = tmp_hit.t;
-    got_hit = true;
-    h = tmp_hit;
-  } 
-  if (hit_rect_xy(r1, r, t_min, closest, tmp_hit)) {
-    closest = tmp_hit.t;
-    got_hit = true;
-    h = tmp_hit;
+// First entry of a CDF at least u. The marginal CDF for row < 0, else the
+// CDF of the row.
+int cdf_search(int row, int count, float u) {
+  int lo = 0;
+  int hi = count - 1;
+  while (lo < hi) {
+    int mid = (lo + hi) / 2;
+    float v = row < 0 ? texelFetch(env_marginal, ivec2(mid, 0), 0).r
+                      : texelFetch(env_conditional, ivec2(mid, row), 0).r;
+    if (v < u) {
+      lo = mid + 1;
+    } else {
+      hi = mid;
+    }
   }
-  
+  return lo;
+}
 
-  return got_hit;
+// Pick a direction in proportion to the light coming from it.
+vec3 env_sample(out float pdf) {
+  vec2 r = hash2(g_seed);
+  int y = cdf_search(-1, int(env_size.y), r.x);
+  int x = cdf_search(y, int(env_size.x), r.y);
+  vec3 d = env_dir((vec2(x, y) + hash2(g_seed)) / env_size);
+  pdf = env_pdf(d);
+  return d;
+}
+
+float power_heuristic(float pdf, float other_pdf) {
+  return pdf * pdf / (pdf * pdf + other_pdf * other_pdf);
 }
 
 vec3 sky(in ray r) {
   vec3 unit_dir = normalize(r.direction);
   float t = 0.5 * (unit_dir.y + 1.0);
   //return ((1.0 - t) * vec3(0.95, 0.5, 0.5) + t * vec3(0.8, 0.5, 0.6)) * 0.25;
+  if (env_enabled > 0.5) {
+    return env_radiance(r.direction);
+  }
   return vec3(0.00);
 }
 
+// Cosine weighted, so the density of a direction is cos(theta) / PI.
 bool lambertian_scatter(in ray r, in hit h, out vec3 attenuation, out ray scattered) {
-  vec3 new_dir = h.normal + random_in_hemisphere(h.normal);
+  vec3 new_dir = h.normal + random_unit_vector(g_seed);
   if(near_zero(new_dir)) {
     new_dir = h.normal;
   }
@@ -462,17 +601,40 @@ vec3 emitted_light(hit h) {
   return vec3(0.0);
 }
 
+// Light from the environment reaching a diffuse hit directly, weighted
+// against finding it by bouncing.
+vec3 sample_env_light(in hit h) {
+  float light_pdf;
+  vec3 dir = env_sample(light_pdf);
+  float cosine = dot(h.normal, dir);
+  if (cosine <= 0.0 || light_pdf <= 0.0) {
+    return vec3(0.0);
+  }
+
+  hit shadow;
+  if (hit_scene(new_ray(h.p, dir), T_MIN, T_MAX, shadow)) {
+    return vec3(0.0);
+  }
+
+  float weight = power_heuristic(light_pdf, cosine / PI);
+  return h.m.albedo / PI * cosine * env_radiance(dir) * weight / light_pdf;
+}
+
 vec3 color(in ray r) {
   vec3 col = vec3(0.0);
   vec3 emitted = vec3(0.0);
   hit h;
+  // Density of the direction of r if a diffuse bounce picked it. 0 when the
+  // environment can't be sampled from there (camera, mirrors, glass).
+  float bsdf_pdf = 0.0;
 
   for (int i = 0; i < MAX_BOUNCE; ++i) {
+    vec3 throughput = i == 0 ? vec3(1.0) : col;
     if (hit_scene(r, T_MIN, T_MAX, h)) {
       ray scattered;
       vec3 attenuation;
       vec3 emit = emitted_light(h);
-      emitted += i == 0 ? emit : col * emit;
+      emitted += throughput * emit;
 
       if (material_scatter(r, h, attenuation, scattered)) {
         //float russian = max(0.05, 3.0 - length(attenuation));
@@ -480,8 +642,14 @@ vec3 color(in ray r) {
         //  break;
         //}
         
-        col = i == 0 ? attenuation : col * attenuation;
+        bool diffuse = h.m.type == LAMBERTIAN;
+        if (env_enabled > 0.5 && diffuse) {
+          emitted += throughput * sample_env_light(h);
+        }
+
+        col = throughput * attenuation;
         r = scattered;
+        bsdf_pdf = diffuse ? max(dot(h.normal, normalize(r.direction)), 0.0) / PI : 0.0;
 
         /*
         vec3 ldir = getConeSample(L_POS-h.p, 1e-5);
@@ -498,7 +666,11 @@ vec3 color(in ray r) {
         return emitted;
       }
     } else {
-      return emitted;
+      float weight = 1.0;
+      if (env_enabled > 0.5 && bsdf_pdf > 0.0) {
+        weight = power_heuristic(bsdf_pdf, env_pdf(r.direction));
+      }
+      return emitted + throughput * sky(r) * weight;
     }
   }
 
@@ -526,12 +698,33 @@ ray get_cam_ray(float vfov, float aspect, vec2 uv, float focus_dist, float apert
   return new_ray(in_eye + offset, lower_left_corner + (uv.x + jitter.x) * horizontal + (uv.y + jitter.y) * vertical - in_eye - offset);
 }
 
+// Relative standard error of the mean of a pixel.
+// Must match relative_error in peglrs/src/noise.
+float relative_error(vec4 acc, vec4 moments) {
+  if (acc.a < 2.0) {
+    return 1e9;
+  }
+  float mean = dot(acc.rgb, LUMA) / acc.a;
+  float variance = max(moments.x / acc.a - mean * mean, 0.0);
+  return sqrt(variance / acc.a) / (mean + ERROR_BIAS);
+}
+
 void main() {
+  vec4 previous = texelFetch(previous_frame, ivec2(gl_FragCoord.xy), 0);
+  vec4 previous_mom = texelFetch(previous_moments, ivec2(gl_FragCoord.xy), 0);
+  if (adaptive_threshold > 0.0 && previous.a >= adaptive_min_samples
+      && relative_error(previous, previous_mom) < adaptive_threshold) {
+    // Converged enough, keep what we have.
+    FragColor = previous;
+    Moments = previous_mom;
+    return;
+  }
+
   vec2 uv = gl_FragCoord.xy / resolution.xy;
   float aspect = resolution.x / resolution.y;
 
   // Init the seed. Make it different for each pixel + frame.
-  g_seed = float(base_hash(floatBitsToUint(gl_FragCoord.xy))) / float(0xffffffffU) + time;
+  g_seed = float(base_hash(floatBitsToUint(gl_FragCoord.xy) ^ seed)) / float(0xffffffffU);
 
   // dist to target
   ray r = get_cam_ray(90.0, aspect, in_focus_pos, 1.0, 0.0);
@@ -546,5 +739,8 @@ void main() {
 
   col /= float(SAMPLING);
 
-  FragColor = vec4(col, 1.0);
+  // Accumulate on top of what we rendered last frame.
+  float luma = dot(col, LUMA);
+  FragColor = previous + vec4(col, 1.0);
+  Moments = previous_mom + vec4(luma * luma, 0.0, 0.0, 0.0);
 }
\ No newline at end of file
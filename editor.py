@@ -1,35 +1,34 @@
-import sys
+import math
 import random
+import sys
+import time
 
-from ctypes import cdll, c_double, c_float, c_uint
-from sys import platform
+import peglrs_py
 
 from PySide2.QtGui import QWindow, QOpenGLContext, QSurface, QSurfaceFormat, QExposeEvent
 from PySide2.QtWidgets import QApplication, QOpenGLWidget
 from PySide2.QtCore import QSize, QEvent, Signal, Slot, Qt
 
-if platform == 'darwin':
-    prefix = 'lib'
-    ext = 'dylib'
-elif platform == 'win32':
-    prefix = ''
-    ext = 'dll'
-else:
-    prefix = 'lib'
-    ext = 'so'
+# Build the module with `maturin develop -m peglrs_py/Cargo.toml` first.
 
-lib_path = './target/debug/{}peglrs.{}'.format(prefix, ext)
+MOUSE_SPEED = 0.01
 
-print(lib_path)
-lib = cdll.LoadLibrary(lib_path)
 
-load_gl_symbol = lib.load_gl_symbol
-init_gl = lib.init_gl
-init_scene = lib.init_scene
-display_loop = lib.display_loop
-print_gl_info = lib.print_gl_info
-resize_window = lib.resize_window
-handle_mouse = lib.handle_mouse
+def init_scene(width, height, dpi_ratio):
+    scene = peglrs_py.Scene(width, height, dpi_ratio)
+    peglrs_py.print_gl_info()
+
+    # Same starting point as glutin_backend.
+    hangle = -2.770002
+    vangle = -0.0999
+    eye = (1.9259938, 1.0516713, 2.5938892)
+    direction = (math.cos(vangle) * math.sin(hangle),
+                 math.sin(vangle),
+                 math.cos(vangle) * math.cos(hangle))
+    target = tuple(e + d for e, d in zip(eye, direction))
+    scene.set_camera(eye, target, (0.0, 1.0, 0.0), (0.43140244, 0.5055225), 0.2)
+    scene.on_reload(lambda: print('Shaders reloaded'))
+    return scene
 
 
 class GLWidget(QOpenGLWidget):
@@ -44,24 +43,25 @@ class GLWidget(QOpenGLWidget):
         self.mouse_y = 0
         self.mouse_init = False
         self.mouse_pressed = False
+        self.scene = None
+        self.start_time = time.monotonic()
 
     def paintGL(self):
-        display_loop(c_double(0.0), c_uint(self.defaultFramebufferObject()))
+        fbo = self.defaultFramebufferObject()
+        self.scene.render(time.monotonic() - self.start_time, fbo)
+        self.update()
 
     def resizeGL(self, width, height):
-        width = c_double(self.size().width())
-        height = c_double(self.size().height())
-        dpi_ratio = c_double(self.devicePixelRatio())
-        resize_window(width, height, dpi_ratio)
+        width = self.size().width()
+        height = self.size().height()
+        dpi_ratio = self.devicePixelRatio()
+        self.scene.resize(width, height, dpi_ratio)
 
     def initializeGL(self):
-        width = c_double(self.size().width())
-        height = c_double(self.size().height())
-        dpi_ratio = c_double(self.devicePixelRatio())
-        load_gl_symbol()
-        init_gl(width, height, dpi_ratio)
-        print_gl_info()
-        init_scene(width, height, dpi_ratio)
+        width = self.size().width()
+        height = self.size().height()
+        dpi_ratio = self.devicePixelRatio()
+        self.scene = init_scene(width, height, dpi_ratio)
 
     def mousePressEvent(self, ev):
         if ev.button() == Qt.LeftButton:
@@ -84,7 +84,10 @@ class GLWidget(QOpenGLWidget):
                 dy = self.mouse_y - pos.y()
                 self.mouse_x = pos.x()
                 self.mouse_y = pos.y()
-                handle_mouse(c_float(dx), c_float(dy), c_float(0.001))
+                self.makeCurrent()
+                self.scene.handle_mouse(dx, dy, MOUSE_SPEED)
+                self.scene.reset(self.defaultFramebufferObject())
+                self.doneCurrent()
                 self.update()
 
 
@@ -108,6 +111,8 @@ class GLWin(QWindow):
         self.requestRender.connect(self.requestUpdate)
         # self.setMouseGrabEnabled(True)
         self.mouse_pressed = False
+        self.scene = None
+        self.start_time = time.monotonic()
 
     def mousePressEvent(self, ev):
         if ev.button() == Qt.LeftButton:
@@ -130,14 +135,15 @@ class GLWin(QWindow):
                 dy = self.mouse_y - pos.y()
                 self.mouse_x = pos.x()
                 self.mouse_y = pos.y()
-                handle_mouse(c_float(dx), c_float(dy), c_float(0.001))
+                self.scene.handle_mouse(dx, dy, MOUSE_SPEED)
+                self.scene.reset()
 
     def start(self):
         self.animating = True
         self.renderLater()
 
     def render(self):
-        display_loop(c_double(0.0))
+        self.scene.render(time.monotonic() - self.start_time)
 
     def renderLater(self):
         self.requestRender.emit()
@@ -159,21 +165,17 @@ class GLWin(QWindow):
             print("Context made current !")
 
     def init_scene(self):
-        width = c_double(self.size().width())
-        height = c_double(self.size().height())
-        dpi_ratio = c_double(self.devicePixelRatio())
-
-        load_gl_symbol()
-        init_gl(width, height, dpi_ratio)
-        print_gl_info()
-        init_scene(width, height, dpi_ratio)
+        width = self.size().width()
+        height = self.size().height()
+        dpi_ratio = self.devicePixelRatio()
+        self.scene = init_scene(width, height, dpi_ratio)
 
     def resize(self):
-        if self.isExposed():
-            width = c_double(self.size().width())
-            height = c_double(self.size().height())
-            dpi_ratio = c_double(self.devicePixelRatio())
-            resize_window(width, height, dpi_ratio)
+        if self.isExposed() and self.scene is not None:
+            width = self.size().width()
+            height = self.size().height()
+            dpi_ratio = self.devicePixelRatio()
+            self.scene.resize(width, height, dpi_ratio)
 
     def event(self, ev):
         if ev.type() == QEvent.UpdateRequest:
@@ -1,18 +1,158 @@
 extern crate glutin;
 extern crate peglrs;
 
-use std::{ops::Add, time::{Duration, Instant}};
+mod session;
+
+use std::{ops::Add, path::PathBuf, process, time::{Duration, Instant}};
 
 use cgmath::{InnerSpace, Vector2, Vector3};
 use glutin::{event::{ElementState, Event, KeyboardInput, MouseButton, VirtualKeyCode, WindowEvent}, event_loop::ControlFlow};
 
 use glutin::event::MouseScrollDelta;
 
+use session::Session;
+
+// Relative error under which pixels stop getting samples when adaptive
+// sampling is on.
+const ADAPTIVE_THRESHOLD: f32 = 0.05;
+
+// Tiles used when tiled rendering is on, small enough to keep the window
+// responsive with heavy shaders.
+const TILING: peglrs::TileConfig = peglrs::TileConfig {
+    width: 256,
+    height: 256,
+    per_frame: 4,
+};
+
+// How far the timeline made from a recording may stray from it, in world
+// units for the eye and radians for the direction.
+const RECORD_TOLERANCE: f32 = 0.01;
+
+// How often the GPU time of the passes is printed when profiling.
+const PROFILE_LOG_INTERVAL: Duration = Duration::from_secs(2);
+
+// How often an idle window looks for remote commands and OSC messages.
+const LISTEN_INTERVAL: Duration = Duration::from_millis(33);
+
+const USAGE: &str = "usage: glutin_backend [options]
+
+  --samples <n>           stop accumulating after n samples
+  --time-budget <s>       stop accumulating after s seconds
+  --noise <threshold>     stop accumulating once the noise estimate is below threshold
+  --export <file>         save the image when rendering stops
+  --env <file>            light the scene with an equirectangular .hdr or .exr environment map
+  --scene <file>          spheres, rects, boxes and materials to render, reloaded when it changes
+  --timeline <file>       keyframes of the camera and uniforms, played with L
+  --record <file>         where C saves the camera path it records (default: camera_path.toml), its
+                          simplified timeline going next to it in a .timeline.toml file
+  --replay <file>         camera path replayed with K
+  --session <file>        where the camera, pipeline, uniforms and window size are restored from at
+                          startup and saved to on exit or with F5 (default: session.toml)
+  --pipeline <file>       pipeline to render instead of the one of the session
+  --remote <addr>         accept JSON-RPC commands on a loopback address like 127.0.0.1:9000
+  --remote-export <dir>   directory the remote export command saves images into, off without it
+  --osc <addr>            listen for OSC messages on a UDP address like 0.0.0.0:9001
+  --osc-map <file>        how the values of the OSC addresses are scaled and smoothed";
+
+struct Args {
+    // None if no goal is given.
+    goal: Option<peglrs::RenderGoal>,
+    environment: Option<PathBuf>,
+    world: Option<PathBuf>,
+    timeline: Option<PathBuf>,
+    record: PathBuf,
+    replay: Option<PathBuf>,
+    session: PathBuf,
+    pipeline: Option<String>,
+    remote: Option<String>,
+    remote_export: Option<PathBuf>,
+    osc: Option<String>,
+    osc_map: Option<PathBuf>,
+}
+
+fn parse_args(args: &[String]) -> Result<Args, String> {
+    let mut goal = peglrs::RenderGoal::default();
+    let mut environment = None;
+    let mut world = None;
+    let mut timeline = None;
+    let mut record = PathBuf::from("camera_path.toml");
+    let mut replay = None;
+    let mut session = PathBuf::from("session.toml");
+    let mut pipeline = None;
+    let mut remote = None;
+    let mut remote_export = None;
+    let mut osc = None;
+    let mut osc_map = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let value = args.next().ok_or_else(|| format!("missing value for {}", arg))?;
+        let invalid = || format!("invalid value for {}: {}", arg, value);
+        match arg.as_str() {
+            "--samples" => goal.samples = Some(value.parse().map_err(|_| invalid())?),
+            "--time-budget" => {
+                let secs: f32 = value.parse().map_err(|_| invalid())?;
+                goal.time = Some(Duration::from_secs_f32(secs.max(0.0)));
+            }
+            "--noise" => goal.noise = Some(value.parse().map_err(|_| invalid())?),
+            "--export" => goal.export = Some(PathBuf::from(value)),
+            "--env" => environment = Some(PathBuf::from(value)),
+            "--scene" => world = Some(PathBuf::from(value)),
+            "--timeline" => timeline = Some(PathBuf::from(value)),
+            "--record" => record = PathBuf::from(value),
+            "--replay" => replay = Some(PathBuf::from(value)),
+            "--session" => session = PathBuf::from(value),
+            "--pipeline" => pipeline = Some(value.clone()),
+            "--remote" => remote = Some(value.clone()),
+            "--remote-export" => remote_export = Some(PathBuf::from(value)),
+            "--osc" => osc = Some(value.clone()),
+            "--osc-map" => osc_map = Some(PathBuf::from(value)),
+            _ => return Err(format!("unknown option {}", arg)),
+        }
+    }
+
+    let goal = if goal == peglrs::RenderGoal::default() {
+        None
+    } else {
+        Some(goal)
+    };
+    Ok(Args {
+        goal,
+        environment,
+        world,
+        timeline,
+        record,
+        replay,
+        session,
+        pipeline,
+        remote,
+        remote_export,
+        osc,
+        osc_map,
+    })
+}
+
 fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let args = match parse_args(&args) {
+        Ok(args) => args,
+        Err(err) => {
+            eprintln!("[ERR] {}\n\n{}", err, USAGE);
+            process::exit(2);
+        }
+    };
+
+    let session_path = args.session;
+    let session = if session_path.exists() {
+        println!("[NFO] Restoring session {}", session_path.display());
+        Session::load(&session_path).unwrap_or_default()
+    } else {
+        Session::default()
+    };
+
     let events_loop = glutin::event_loop::EventLoop::new();
     let window = glutin::window::WindowBuilder::new()
         .with_title("Stuffy (ESC)")
-        .with_inner_size(glutin::dpi::LogicalSize::new(1280.0, 700.0));
+        .with_inner_size(glutin::dpi::LogicalSize::new(session.window_size[0], session.window_size[1]));
     let window_context = glutin::ContextBuilder::new()
 //        .with_vsync(true)
         .build_windowed(window, &events_loop)
@@ -26,7 +166,44 @@ fn main() {
     let size = window_context.window().inner_size();
     // We put the dpi at 1.0 because the size is already scaled.
     peglrs::init_gl(size.width as f64, size.height as f64, 1.0);
-    peglrs::init_scene(size.width as f64, size.height as f64, 1.0);
+    let mut scene = match args.pipeline.as_ref().or(session.pipeline.as_ref()) {
+        Some(pipeline) => peglrs::init_scene_from_file(pipeline, size.width as f64, size.height as f64, 1.0),
+        None => peglrs::init_scene(size.width as f64, size.height as f64, 1.0),
+    }
+    .expect("Couldn't create the scene");
+    for (name, value) in session.uniform_values() {
+        peglrs::set_uniform(&mut scene, name, value);
+    }
+    // Keep the accumulated samples when the window gets resized.
+    peglrs::set_resize_policy(&mut scene, peglrs::ResizePolicy::Rescale);
+    peglrs::set_render_goal(&mut scene, args.goal);
+    if let Some(path) = &args.environment {
+        if peglrs::set_environment(&mut scene, Some(path)).is_none() {
+            eprintln!("[ERR] Couldn't load the environment {}", path.display());
+        }
+    }
+    if let Some(path) = &args.world {
+        if peglrs::load_world(&mut scene, Some(path)).is_none() {
+            eprintln!("[ERR] Couldn't load the scene {}", path.display());
+        }
+    }
+    if let Some(addr) = &args.remote {
+        if peglrs::set_remote(&mut scene, Some(addr), args.remote_export.as_deref()).is_none() {
+            eprintln!("[ERR] Couldn't start the remote control on {}", addr);
+        }
+    }
+    if let Some(addr) = &args.osc {
+        let config = match &args.osc_map {
+            Some(path) => peglrs::OscConfig::load(path).unwrap_or_default(),
+            None => peglrs::OscConfig::default(),
+        };
+        if peglrs::set_osc(&mut scene, Some(addr), config).is_none() {
+            eprintln!("[ERR] Couldn't listen for OSC on {}", addr);
+        }
+    }
+    if let Some(path) = &args.timeline {
+        peglrs::set_timeline(&mut scene, peglrs::Timeline::load(path));
+    }
 
     let mut mouse_init = false;
     let mut mouse_prev: (f64, f64) = (0.0, 0.0);
@@ -34,13 +211,13 @@ fn main() {
     let mut pause = false;
     let mut iter: usize = 0;
 
-    let mut hangle: f32 = -2.770002;
-    let mut vangle: f32 = -0.0999;
+    let mut hangle: f32 = session.hangle;
+    let mut vangle: f32 = session.vangle;
     let mut dt: f32 = 0.0;
 
-    let mut cam_eye: Vector3<f32> = Vector3::new(1.9259938, 1.0516713, 2.5938892);
-    let mut focus_pos = Vector2::new(0.43140244, 0.5055225);
-    let mut aperture: f32 = 0.2;
+    let mut cam_eye: Vector3<f32> = session.eye.into();
+    let mut focus_pos: Vector2<f32> = session.focus_pos.into();
+    let mut aperture: f32 = session.aperture;
     let mut cam_direction = Vector3::new(
         vangle.cos() * hangle.sin(), 
         vangle.sin(), 
@@ -52,6 +229,7 @@ fn main() {
     let mut cam_up = right.cross(cam_direction);
     
     peglrs::update_camera(
+        &mut scene,
         cam_eye,
         cam_eye + cam_direction,
         cam_up,
@@ -69,6 +247,23 @@ fn main() {
     let mouse_speed: f32 = 0.01;
     let keyboard_speed: f32 = 10.0;
     let mut zero_aperture = false;
+    // The keys toggling uniforms start from the ones of the session.
+    let mut debug_view: f32 = match scene.uniforms.get("debug_view") {
+        Some(peglrs::UniformValue::Float(v)) => *v,
+        _ => 0.0,
+    };
+    let mut adaptive = matches!(scene.uniforms.get("adaptive_threshold"), Some(peglrs::UniformValue::Float(v)) if *v > 0.0);
+    let mut tiled = false;
+    let mut profiling = false;
+    // When the timeline started playing.
+    let mut playback: Option<Instant> = None;
+    // The camera path being recorded, and when it started.
+    let mut recording: Option<(peglrs::Recording, Instant)> = None;
+    let mut camera_path = args.replay.as_ref().and_then(|path| peglrs::Recording::load(path));
+    let record_path = args.record;
+    let timeline_path = record_path.with_extension("timeline.toml");
+    // When the camera path started replaying.
+    let mut replay: Option<Instant> = None;
     let mut mouse_pos = Vector2::new(0.0, 0.0);
 
     let mut resize_triggered = false;
@@ -78,6 +273,7 @@ fn main() {
     events_loop.run(move |event, _, control_flow| {
         let loop_start = Instant::now();
         let mut stop = false;
+        let mut save_session = false;
 
         let mut cam_moved = false;
         let mut mouse_moved = false;
@@ -96,7 +292,6 @@ fn main() {
                     }
                 }
                 WindowEvent::CloseRequested => {
-                    peglrs::quit();
                     stop = true;
                 }
                 WindowEvent::KeyboardInput { input: KeyboardInput { virtual_keycode: Some(virtual_keycode), state, .. }, .. } => {
@@ -104,9 +299,12 @@ fn main() {
                         (VirtualKeyCode::Escape, ElementState::Pressed) => {
                             stop = true;
                         }
+                        (VirtualKeyCode::F5, ElementState::Pressed) => {
+                            save_session = true;
+                        }
                         (VirtualKeyCode::R, ElementState::Pressed) => {
                             iter = 0;
-                            peglrs::reset(0);
+                            peglrs::reset(&mut scene, 0);
                             window_context.swap_buffers().unwrap();
                         }
                         (VirtualKeyCode::P, ElementState::Pressed) => {
@@ -117,11 +315,76 @@ fn main() {
                         }
                         (VirtualKeyCode::O, ElementState::Pressed) => {
                             println!("samples: {}, last frame rendered in {}s", iter, dt);
+                            for timing in peglrs::pass_timings(&scene) {
+                                println!("  {}", timing);
+                            }
                         }
                         (VirtualKeyCode::I, ElementState::Pressed) => {
                             println!("Cam:\neye: {:?}\ndirection: {:?}\n, up: {:?}\n focus pos: {:?}\n, aperture: {}\n hangle: {}\n vangle: {}", 
                                     cam_eye, cam_direction, cam_up, focus_pos, aperture, hangle, vangle);
                         }
+                        (VirtualKeyCode::N, ElementState::Pressed) => {
+                            // Off, noise, samples.
+                            debug_view = (debug_view + 1.0) % 3.0;
+                            peglrs::set_uniform(&mut scene, "debug_view", peglrs::UniformValue::Float(debug_view));
+                        }
+                        (VirtualKeyCode::M, ElementState::Pressed) => {
+                            adaptive = !adaptive;
+                            let threshold = if adaptive { ADAPTIVE_THRESHOLD } else { 0.0 };
+                            peglrs::set_uniform(&mut scene, "adaptive_threshold", peglrs::UniformValue::Float(threshold));
+                            println!("adaptive sampling: {}", adaptive);
+                        }
+                        (VirtualKeyCode::G, ElementState::Pressed) => {
+                            profiling = !profiling;
+                            peglrs::set_profiling(&mut scene, profiling, Some(PROFILE_LOG_INTERVAL));
+                            println!("GPU profiling: {}", profiling);
+                        }
+                        (VirtualKeyCode::T, ElementState::Pressed) => {
+                            tiled = !tiled;
+                            peglrs::set_tiling(&mut scene, if tiled { Some(TILING) } else { None });
+                            println!("tiled rendering: {}", tiled);
+                        }
+                        (VirtualKeyCode::L, ElementState::Pressed) => {
+                            playback = match playback {
+                                Some(_) => None,
+                                None if scene.timeline.is_some() => Some(Instant::now()),
+                                None => {
+                                    println!("no timeline to play");
+                                    None
+                                }
+                            };
+                            println!("timeline playback: {}", playback.is_some());
+                        }
+                        (VirtualKeyCode::C, ElementState::Pressed) => {
+                            match recording.take() {
+                                Some((recorded, _)) => {
+                                    let timeline = recorded.to_timeline(RECORD_TOLERANCE);
+                                    if recorded.save(&record_path).is_some() && timeline.save(&timeline_path).is_some() {
+                                        println!("camera path saved to {} and {}, {} frames simplified to {} keyframes",
+                                                record_path.display(), timeline_path.display(), recorded.samples.len(),
+                                                timeline.eye.as_ref().map_or(0, |track| track.keys.len()));
+                                    }
+                                    // Played with L, or K for the path as it was.
+                                    peglrs::set_timeline(&mut scene, Some(timeline));
+                                    camera_path = Some(recorded);
+                                }
+                                None => {
+                                    println!("recording the camera path");
+                                    recording = Some((peglrs::Recording::new(), Instant::now()));
+                                }
+                            }
+                        }
+                        (VirtualKeyCode::K, ElementState::Pressed) => {
+                            replay = match replay {
+                                Some(_) => None,
+                                None if camera_path.is_some() => Some(Instant::now()),
+                                None => {
+                                    println!("no camera path to replay");
+                                    None
+                                }
+                            };
+                            println!("camera path replay: {}", replay.is_some());
+                        }
                         (VirtualKeyCode::V, ElementState::Pressed) => {
                             zero_aperture = true;
                             cam_moved = true;
@@ -179,6 +442,7 @@ fn main() {
                     new_inner_size,
                 } => {
                     peglrs::resize_window(
+                        &mut scene,
                         new_inner_size.width as f64,
                         new_inner_size.height as f64,
                         scale_factor,
@@ -196,7 +460,6 @@ fn main() {
                             mouse_dx = mouse_delta.0 as f32;
                             mouse_dy = mouse_delta.1 as f32;
                             cam_moved = true;
-                            peglrs::handle_mouse(mouse_delta.0 as f32, mouse_delta.1 as f32, mouse_speed);
                             mouse_prev = (position.x, position.y);
                             mouse_moved = true;
                         }
@@ -229,15 +492,67 @@ fn main() {
                 _ => (),
             },
             Event::MainEventsCleared => {
+                let remote_moved = peglrs::handle_remote(&mut scene, 0);
+                if peglrs::handle_osc(&mut scene, 0) | remote_moved {
+                    // Moving from there starts where the commands left the camera.
+                    let direction = (scene.target - scene.eye).normalize();
+                    hangle = direction.x.atan2(direction.z);
+                    vangle = direction.y.asin();
+                    cam_eye = scene.eye;
+                    cam_direction = direction;
+                    cam_up = scene.up;
+                    focus_pos = scene.focus_pos;
+                    aperture = scene.aperture;
+                    iter = 0;
+                }
+                if let Some(start) = playback {
+                    // Accumulation starts over whenever the timeline moves something.
+                    if peglrs::apply_timeline(&mut scene, start.elapsed().as_secs_f32()) {
+                        iter = 0;
+                        peglrs::reset(&mut scene, 0);
+                    }
+                }
                 if !pause {
-                    peglrs::display_loop(counter.elapsed().as_millis() as f64 / 1000.0, 0, true);
+                    let idle = peglrs::is_idle(&scene);
+                    peglrs::display_loop(&mut scene, counter.elapsed().as_millis() as f64 / 1000.0, 0, true);
                     window_context.swap_buffers().unwrap();
-                    iter += 1;
+                    if !idle {
+                        iter += 1;
+                    }
+                }
+                if let Some((recorded, start)) = recording.as_mut() {
+                    recorded.push(peglrs::CameraSample::new(
+                        start.elapsed().as_secs_f32(),
+                        cam_eye,
+                        cam_direction,
+                        cam_up,
+                        focus_pos,
+                        if zero_aperture { 0.0 } else { aperture },
+                    ));
                 }
             }
             _ => (),
         };
 
+        if stop || save_session {
+            let window = window_context.window();
+            let size = window.inner_size().to_logical::<f64>(window.scale_factor());
+            let mut session = Session {
+                eye: cam_eye.into(),
+                hangle,
+                vangle,
+                focus_pos: focus_pos.into(),
+                aperture,
+                pipeline: Some(scene.pipeline_watcher.path.display().to_string()),
+                window_size: [size.width, size.height],
+                ..Session::default()
+            };
+            session.set_uniforms(&scene.uniforms);
+            if session.save(&session_path).is_some() {
+                println!("session saved to {}", session_path.display());
+            }
+        }
+
         if stop {
             *control_flow = ControlFlow::Exit;
         }
@@ -245,7 +560,7 @@ fn main() {
         if resize_triggered && Instant::now() > trigger_resize_timer {
             // We put the dpi at 1.0 because the size is already scaled.
             let new_size = window_context.window().inner_size();
-            peglrs::resize_window(new_size.width as f64, new_size.height as f64, 1.0);
+            peglrs::resize_window(&mut scene, new_size.width as f64, new_size.height as f64, 1.0);
             resize_triggered = false;
         }
 
@@ -285,6 +600,28 @@ fn main() {
             cam_moved = true;
         }
 
+        if let (Some(start), Some(path)) = (replay, camera_path.as_ref()) {
+            let time = start.elapsed().as_secs_f32();
+            if let Some(sample) = path.sample(time) {
+                // Through the angles, so moving from there once it is done
+                // starts where it left off.
+                let direction = sample.direction();
+                hangle = direction.x.atan2(direction.z);
+                vangle = direction.y.asin();
+                mouse_moved |= sample.eye() != cam_eye
+                    || sample.focus_pos() != focus_pos
+                    || sample.aperture != aperture
+                    || direction != cam_direction;
+                cam_eye = sample.eye();
+                focus_pos = sample.focus_pos();
+                aperture = sample.aperture;
+            }
+            if time > path.duration() {
+                replay = None;
+                println!("camera path replay: false");
+            }
+        }
+
         if mouse_moved {
             aperture += mouse_ds / 50.0;
             
@@ -307,13 +644,25 @@ fn main() {
         if cam_moved {
             iter = 0;
             peglrs::update_camera(
+                &mut scene,
                 cam_eye,
                 cam_eye + cam_direction,
                 cam_up,
                 focus_pos,
                 if zero_aperture { 0.0 } else { aperture },
             );
-            peglrs::reset(0);
+            peglrs::reset(&mut scene, 0);
+        }
+
+        if !stop {
+            *control_flow = if peglrs::is_idle(&scene) && playback.is_none() && replay.is_none() {
+                // The goal is reached, only wake up to redraw, look for reloads
+                // and run the remote commands and OSC messages.
+                let wait = if scene.remote.is_some() || scene.osc.is_some() { LISTEN_INTERVAL } else { Duration::from_millis(500) };
+                ControlFlow::WaitUntil(Instant::now() + wait)
+            } else {
+                ControlFlow::Poll
+            };
         }
 
         let ms = Instant::now().duration_since(loop_start).as_millis();
@@ -0,0 +1,128 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+
+use cgmath::{Vector2, Vector3, Vector4};
+use serde::{Deserialize, Serialize};
+
+// A uniform as written in the session file: 1 is an int, 1.0 a float, and
+// [1.0, 2.0] a vector.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Uniform {
+    Int(i32),
+    Float(f32),
+    Vector(Vec<f32>),
+}
+
+impl From<peglrs::UniformValue> for Uniform {
+    fn from(value: peglrs::UniformValue) -> Uniform {
+        match value {
+            peglrs::UniformValue::Int(v) => Uniform::Int(v),
+            peglrs::UniformValue::Float(v) => Uniform::Float(v),
+            peglrs::UniformValue::Vec2(v) => Uniform::Vector(vec![v.x, v.y]),
+            peglrs::UniformValue::Vec3(v) => Uniform::Vector(vec![v.x, v.y, v.z]),
+            peglrs::UniformValue::Vec4(v) => Uniform::Vector(vec![v.x, v.y, v.z, v.w]),
+        }
+    }
+}
+
+impl Uniform {
+    fn value(&self) -> Option<peglrs::UniformValue> {
+        match self {
+            Uniform::Int(v) => Some(peglrs::UniformValue::Int(*v)),
+            Uniform::Float(v) => Some(peglrs::UniformValue::Float(*v)),
+            Uniform::Vector(v) => match v.as_slice() {
+                [x] => Some(peglrs::UniformValue::Float(*x)),
+                [x, y] => Some(peglrs::UniformValue::Vec2(Vector2::new(*x, *y))),
+                [x, y, z] => Some(peglrs::UniformValue::Vec3(Vector3::new(*x, *y, *z))),
+                [x, y, z, w] => Some(peglrs::UniformValue::Vec4(Vector4::new(*x, *y, *z, *w))),
+                _ => None,
+            },
+        }
+    }
+}
+
+// Where the window was left: the camera, the pipeline and the uniforms set
+// with the keys. Saved as TOML, so it can also be written by hand.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Session {
+    pub eye: [f32; 3],
+    // Angles of the camera direction, in radians.
+    pub hangle: f32,
+    pub vangle: f32,
+    pub focus_pos: [f32; 2],
+    pub aperture: f32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pipeline: Option<String>,
+    // Logical size of the window.
+    pub window_size: [f64; 2],
+    pub uniforms: BTreeMap<String, Uniform>,
+}
+
+impl Default for Session {
+    // The view stuffy opens on without a session.
+    fn default() -> Session {
+        Session {
+            eye: [1.9259938, 1.0516713, 2.5938892],
+            hangle: -2.770002,
+            vangle: -0.0999,
+            focus_pos: [0.43140244, 0.5055225],
+            aperture: 0.2,
+            pipeline: None,
+            window_size: [1280.0, 700.0],
+            uniforms: BTreeMap::new(),
+        }
+    }
+}
+
+impl Session {
+    pub fn load(path: &Path) -> Option<Session> {
+        let src = match fs::read_to_string(path) {
+            Ok(src) => src,
+            Err(err) => {
+                eprintln!("[ERR] Couldn't read session {} : {}", path.display(), err);
+                return None;
+            }
+        };
+        match toml::from_str(&src) {
+            Ok(session) => Some(session),
+            Err(err) => {
+                eprintln!("[ERR] Couldn't parse session {} : {}", path.display(), err);
+                None
+            }
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Option<()> {
+        // Going through a Value puts the uniforms table after the plain
+        // values, as TOML wants.
+        let res = toml::Value::try_from(self)
+            .and_then(|value| toml::to_string(&value))
+            .map_err(|err| err.to_string())
+            .and_then(|src| fs::write(path, src).map_err(|err| err.to_string()));
+        match res {
+            Ok(()) => Some(()),
+            Err(err) => {
+                eprintln!("[ERR] Couldn't save session {} : {}", path.display(), err);
+                None
+            }
+        }
+    }
+
+    pub fn set_uniforms(&mut self, uniforms: &HashMap<String, peglrs::UniformValue>) {
+        self.uniforms = uniforms
+            .iter()
+            .map(|(name, value)| (name.clone(), Uniform::from(*value)))
+            .collect();
+    }
+
+    // The uniforms that can be set, skipping vectors of the wrong size.
+    pub fn uniform_values(&self) -> Vec<(&str, peglrs::UniformValue)> {
+        self.uniforms
+            .iter()
+            .filter_map(|(name, uniform)| Some((name.as_str(), uniform.value()?)))
+            .collect()
+    }
+}
@@ -0,0 +1,84 @@
+// An OpenGL context without any window or display server, through Mesa's
+// surfaceless EGL platform. With LIBGL_ALWAYS_SOFTWARE=1 it runs on llvmpipe,
+// so it also works on machines without a GPU.
+
+use std::os::raw::c_void;
+
+use khronos_egl as egl;
+
+const PLATFORM_SURFACELESS_MESA: egl::Enum = 0x31DD;
+
+pub struct HeadlessContext {
+    egl: egl::DynamicInstance<egl::EGL1_5>,
+    display: egl::Display,
+    context: egl::Context,
+}
+
+impl HeadlessContext {
+    // Create an OpenGL 4.1 core context and make it current.
+    pub fn new() -> Result<HeadlessContext, String> {
+        let egl = unsafe { egl::DynamicInstance::<egl::EGL1_5>::load_required() }
+            .map_err(|err| format!("couldn't load libEGL: {}", err))?;
+
+        let display = unsafe {
+            egl.get_platform_display(
+                PLATFORM_SURFACELESS_MESA,
+                egl::DEFAULT_DISPLAY,
+                &[egl::ATTRIB_NONE],
+            )
+        }
+        .map_err(|err| format!("no surfaceless EGL display: {}", err))?;
+        egl.initialize(display)
+            .map_err(|err| format!("couldn't initialize EGL: {}", err))?;
+
+        let config_attribs = [
+            egl::SURFACE_TYPE,
+            0,
+            egl::RENDERABLE_TYPE,
+            egl::OPENGL_BIT,
+            egl::NONE,
+        ];
+        let config = egl
+            .choose_first_config(display, &config_attribs)
+            .map_err(|err| format!("couldn't choose an EGL config: {}", err))?
+            .ok_or_else(|| String::from("no EGL config supporting desktop OpenGL"))?;
+
+        egl.bind_api(egl::OPENGL_API)
+            .map_err(|err| format!("couldn't bind the OpenGL API: {}", err))?;
+        let context_attribs = [
+            egl::CONTEXT_MAJOR_VERSION,
+            4,
+            egl::CONTEXT_MINOR_VERSION,
+            1,
+            egl::CONTEXT_OPENGL_PROFILE_MASK,
+            egl::CONTEXT_OPENGL_CORE_PROFILE_BIT,
+            egl::NONE,
+        ];
+        let context = egl
+            .create_context(display, config, None, &context_attribs)
+            .map_err(|err| format!("couldn't create an OpenGL 4.1 context: {}", err))?;
+        egl.make_current(display, None, None, Some(context))
+            .map_err(|err| format!("couldn't make the context current: {}", err))?;
+
+        Ok(HeadlessContext {
+            egl,
+            display,
+            context,
+        })
+    }
+
+    pub fn get_proc_address(&self, symbol: &str) -> *const c_void {
+        match self.egl.get_proc_address(symbol) {
+            Some(f) => f as *const c_void,
+            None => std::ptr::null(),
+        }
+    }
+}
+
+impl Drop for HeadlessContext {
+    fn drop(&mut self) {
+        let _ = self.egl.make_current(self.display, None, None, None);
+        let _ = self.egl.destroy_context(self.display, self.context);
+        let _ = self.egl.terminate(self.display);
+    }
+}
@@ -0,0 +1,193 @@
+extern crate peglrs;
+
+mod context;
+mod options;
+
+use std::fs::File;
+use std::os::unix::io::FromRawFd;
+use std::path::Path;
+use std::process;
+use std::time::{Duration, Instant};
+
+use context::HeadlessContext;
+use options::{RenderOptions, USAGE};
+use peglrs::export::video::VideoSink;
+
+// How often the GPU time of the passes is printed with --profile, on top of
+// after every image.
+const PROFILE_LOG_INTERVAL: Duration = Duration::from_secs(5);
+
+// Accumulate one image at `time` from scratch.
+fn accumulate(
+    scene: &mut peglrs::Scene,
+    screen: &peglrs::export::Offscreen,
+    time: f64,
+) -> peglrs::export::Image {
+    let start = Instant::now();
+    peglrs::reset(scene, screen.addr());
+    while !peglrs::is_idle(scene) {
+        peglrs::display_loop(scene, time, screen.addr(), false);
+    }
+
+    let image = screen.read();
+    println!(
+        "{} samples at {}x{} rendered in {:.2}s",
+        peglrs::sample_count(scene),
+        image.width,
+        image.height,
+        start.elapsed().as_secs_f32()
+    );
+    for timing in peglrs::pass_timings(scene) {
+        println!("  {}", timing);
+    }
+    image
+}
+
+fn save(image: &peglrs::export::Image, output: &Path) -> Result<(), String> {
+    image
+        .save(output)
+        .ok_or_else(|| format!("couldn't write {}", output.display()))?;
+    println!("Saved {}", output.display());
+    Ok(())
+}
+
+// Take the standard output for the video. Everything printed afterwards,
+// by peglrs too, goes to the standard error instead.
+fn take_stdout() -> Result<File, String> {
+    unsafe {
+        let video = libc::dup(libc::STDOUT_FILENO);
+        if video < 0 || libc::dup2(libc::STDERR_FILENO, libc::STDOUT_FILENO) < 0 {
+            return Err(String::from("couldn't redirect the standard output"));
+        }
+        Ok(File::from_raw_fd(video))
+    }
+}
+
+fn open_video(options: &RenderOptions) -> Result<Option<VideoSink>, String> {
+    let (width, height) = (options.width as u32, options.height as u32);
+    let sink = match (&options.video, &options.encoder) {
+        (Some(path), _) if path == Path::new("-") => {
+            let stdout = Box::new(std::io::BufWriter::new(take_stdout()?));
+            VideoSink::y4m(stdout, width, height, options.video_options)
+        }
+        (Some(path), _) => VideoSink::create(path, width, height, options.video_options),
+        (None, Some(command)) => VideoSink::spawn(command, width, height, options.video_options),
+        (None, None) => return Ok(None),
+    };
+    sink.map(Some)
+        .ok_or_else(|| String::from("couldn't open the video output"))
+}
+
+fn render(options: &RenderOptions) -> Result<(), String> {
+    // Before anything gets printed, in case the video goes to the standard
+    // output.
+    let mut video = open_video(options)?;
+
+    let context = HeadlessContext::new()?;
+    peglrs::load_gl_with(|symbol| context.get_proc_address(symbol));
+    peglrs::print_gl_info();
+
+    let (width, height) = (options.width as f64, options.height as f64);
+    peglrs::init_gl(width, height, 1.0);
+    let mut scene = peglrs::init_scene_from_file(&options.pipeline, width, height, 1.0)
+        .ok_or_else(|| format!("couldn't create the scene from {}", options.pipeline))?;
+    peglrs::update_camera(
+        &mut scene,
+        options.eye,
+        options.target,
+        options.up,
+        options.focus_pos,
+        options.aperture,
+    );
+    for (name, value) in &options.uniforms {
+        peglrs::set_uniform(&mut scene, name, *value);
+    }
+    if let Some(path) = &options.environment {
+        peglrs::set_environment(&mut scene, Some(path))
+            .ok_or_else(|| format!("couldn't load the environment {}", path.display()))?;
+    }
+    if let Some(path) = &options.world {
+        peglrs::load_world(&mut scene, Some(path))
+            .ok_or_else(|| format!("couldn't load the scene {}", path.display()))?;
+    }
+    // Without --frames, the animation lasts as long as the timeline.
+    let mut frames = options.frames;
+    if let Some(path) = &options.timeline {
+        let timeline = peglrs::Timeline::load(path)
+            .ok_or_else(|| format!("couldn't load the timeline {}", path.display()))?;
+        let duration = timeline.duration() as f64 - options.time;
+        frames = frames.or(Some((duration.max(0.0) / options.frame_step()) as u32 + 1));
+        peglrs::set_timeline(&mut scene, Some(timeline));
+    }
+
+    peglrs::set_tiling(&mut scene, options.tiling);
+    peglrs::set_profiling(&mut scene, options.profile, Some(PROFILE_LOG_INTERVAL));
+    peglrs::set_render_goal(
+        &mut scene,
+        Some(peglrs::RenderGoal {
+            samples: Some(options.samples),
+            time: options.time_budget,
+            noise: options.noise,
+            export: None,
+        }),
+    );
+
+    let screen = peglrs::export::Offscreen::new(options.width, options.height);
+    if frames.is_none() && video.is_none() {
+        peglrs::set_seed(&mut scene, Some(options.seed));
+        let image = accumulate(&mut scene, &screen, options.time);
+        return save(&image, &options.output);
+    }
+
+    // Every frame gets its own seeds, so the noise doesn't stay still over
+    // the animation, but the same options always give the same frames.
+    for frame in 0..frames.unwrap_or(1) {
+        let seed = options
+            .seed
+            .wrapping_add(frame.wrapping_mul(options.samples));
+        peglrs::set_seed(&mut scene, Some(seed));
+        let time = options.time + frame as f64 * options.frame_step();
+        peglrs::apply_timeline(&mut scene, time as f32);
+        let image = accumulate(&mut scene, &screen, time);
+        match video.as_mut() {
+            Some(video) => video
+                .write_frame(&image)
+                .ok_or_else(|| format!("couldn't write frame {}", frame))?,
+            None => save(&image, &options.frame_path(frame))?,
+        }
+    }
+
+    if let Some(video) = video {
+        let frames = video.frames();
+        video
+            .finish()
+            .ok_or_else(|| String::from("couldn't finish the video"))?;
+        println!("Wrote {} video frames", frames);
+    }
+    Ok(())
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let options = match args.first().map(|arg| arg.as_str()) {
+        Some("render") => RenderOptions::parse(&args[1..]),
+        Some("-h") | Some("--help") => {
+            println!("{}", USAGE);
+            return;
+        }
+        _ => Err(String::from("expected a command")),
+    };
+
+    let options = match options {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("[ERR] {}\n\n{}", err, USAGE);
+            process::exit(2);
+        }
+    };
+
+    if let Err(err) = render(&options) {
+        eprintln!("[ERR] {}", err);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,460 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use cgmath::{InnerSpace, Vector2, Vector3, Vector4};
+use peglrs::export::video::{Chroma, ColorRange, VideoOptions};
+
+pub const USAGE: &str = "usage: stuffy render [options]
+
+  -o, --output <file>     image to write, .png, .jpg or .hdr (default: render.png). With --frames,
+                          a run of # in the name is replaced by the frame number, else it is appended
+  --size <w>x<h>          resolution in pixels (default: 1280x720)
+  --samples <n>           number of frames to accumulate (default: 64)
+  --time-budget <s>       stop before reaching the sample count after s seconds
+  --noise <threshold>     stop before reaching the sample count once the noise estimate is below threshold
+  --pipeline <file>       pipeline to render (default: data/pipelines/default.toml)
+  --time <t>              time given to the shaders on the first frame (default: 0)
+  --seed <n>              seed of the first sample, the same seed gives the same image (default: 0)
+  --frames <n>            render an animation of n frames, every one with the full sample count
+  --fps <f>               frames per second of the animation, time advances by 1/f every frame, as a
+                          number or a fraction like 30000/1001 (default: 30)
+  --video <file>          write the frames to a Y4M video instead of images, - for the standard output
+  --encoder <command>     pipe the frames as raw RGBA to a command instead, {width}, {height} and {fps}
+                          being replaced in it, e.g. 'ffmpeg -f rawvideo -pix_fmt rgba -s {width}x{height}
+                          -r {fps} -i - out.mp4'
+  --color-range <r>       limited or full, for --video (default: limited)
+  --chroma <c>            chroma subsampling for --video: 420, 422 or 444 (default: 420)
+  --eye <x,y,z>           camera position
+  --target <x,y,z>        point the camera looks at
+  --up <x,y,z>            up direction of the camera
+  --focus <x,y>           point of the image in focus, in [0, 1] (default: 0.5,0.5)
+  --aperture <a>          size of the lens (default: 0.2)
+  --env <file>            light the scene with an equirectangular .hdr or .exr environment map
+  --env-rotation <deg>    rotation of the environment map around the vertical axis (default: 0)
+  --env-intensity <i>     multiplier of the environment light (default: 1)
+  --scene <file>          spheres, rects, boxes and materials to render instead of the built-in scene
+  --uniform <name>=<v>    set a uniform of every pass, v being 1 to 4 comma separated numbers
+  --timeline <file>       move the camera and uniforms along the keyframes of a timeline, rendering
+                          all of it when --frames isn't given
+  --tile <w>x<h>          render the accumulation in tiles of w by h pixels
+  --tiles-per-frame <n>   number of tiles rendered every frame with --tile (default: 1)
+  --profile               print the GPU time of every pass";
+
+#[derive(Debug)]
+pub struct RenderOptions {
+    pub output: PathBuf,
+    pub width: i32,
+    pub height: i32,
+    pub samples: u32,
+    pub time_budget: Option<Duration>,
+    pub noise: Option<f32>,
+    pub pipeline: String,
+    pub time: f64,
+    pub seed: u32,
+    pub frames: Option<u32>,
+    pub fps: (u32, u32),
+    pub video: Option<PathBuf>,
+    pub encoder: Option<Vec<String>>,
+    pub video_options: VideoOptions,
+    pub eye: Vector3<f32>,
+    pub target: Vector3<f32>,
+    pub up: Vector3<f32>,
+    pub focus_pos: Vector2<f32>,
+    pub aperture: f32,
+    pub uniforms: Vec<(String, peglrs::UniformValue)>,
+    pub environment: Option<PathBuf>,
+    pub world: Option<PathBuf>,
+    pub timeline: Option<PathBuf>,
+    pub tiling: Option<peglrs::TileConfig>,
+    pub profile: bool,
+}
+
+impl Default for RenderOptions {
+    // Same camera as glutin_backend when it starts.
+    fn default() -> RenderOptions {
+        let hangle: f32 = -2.770002;
+        let vangle: f32 = -0.0999;
+        let eye = Vector3::new(1.9259938, 1.0516713, 2.5938892);
+        let direction = Vector3::new(
+            vangle.cos() * hangle.sin(),
+            vangle.sin(),
+            vangle.cos() * hangle.cos(),
+        );
+        let right = Vector3::new(
+            (hangle - std::f32::consts::FRAC_PI_2).sin(),
+            0.0,
+            (hangle - std::f32::consts::FRAC_PI_2).cos(),
+        );
+
+        RenderOptions {
+            output: PathBuf::from("render.png"),
+            width: 1280,
+            height: 720,
+            samples: 64,
+            time_budget: None,
+            noise: None,
+            pipeline: String::from("data/pipelines/default.toml"),
+            time: 0.0,
+            seed: 0,
+            frames: None,
+            fps: (30, 1),
+            video: None,
+            encoder: None,
+            video_options: VideoOptions::default(),
+            eye,
+            target: eye + direction,
+            up: right.cross(direction).normalize(),
+            focus_pos: Vector2::new(0.5, 0.5),
+            aperture: 0.2,
+            uniforms: Vec::new(),
+            environment: None,
+            world: None,
+            timeline: None,
+            tiling: None,
+            profile: false,
+        }
+    }
+}
+
+fn parse_floats(value: &str, count: usize) -> Result<Vec<f32>, String> {
+    let values: Vec<f32> = value
+        .split(',')
+        .map(|v| v.trim().parse::<f32>())
+        .collect::<Result<_, _>>()
+        .map_err(|_| format!("invalid numbers: {}", value))?;
+    if values.len() != count {
+        return Err(format!("expected {} numbers, got {}", count, value));
+    }
+    Ok(values)
+}
+
+fn parse_vec3(value: &str) -> Result<Vector3<f32>, String> {
+    let v = parse_floats(value, 3)?;
+    Ok(Vector3::new(v[0], v[1], v[2]))
+}
+
+fn parse_size(value: &str) -> Result<(i32, i32), String> {
+    let mut parts = value.split('x');
+    let size = match (parts.next(), parts.next(), parts.next()) {
+        (Some(w), Some(h), None) => w.parse::<i32>().ok().zip(h.parse::<i32>().ok()),
+        _ => None,
+    };
+    match size {
+        Some((w, h)) if w > 0 && h > 0 => Ok((w, h)),
+        _ => Err(format!("invalid size: {}", value)),
+    }
+}
+
+fn parse_uniform(value: &str) -> Result<(String, peglrs::UniformValue), String> {
+    let (name, values) = value
+        .split_once('=')
+        .ok_or_else(|| format!("expected <name>=<value>, got {}", value))?;
+    let count = values.split(',').count();
+    let v = parse_floats(values, count)?;
+    let uniform = match count {
+        1 => peglrs::UniformValue::Float(v[0]),
+        2 => peglrs::UniformValue::Vec2(Vector2::new(v[0], v[1])),
+        3 => peglrs::UniformValue::Vec3(Vector3::new(v[0], v[1], v[2])),
+        4 => peglrs::UniformValue::Vec4(Vector4::new(v[0], v[1], v[2], v[3])),
+        _ => return Err(format!("too many values for uniform {}", name)),
+    };
+    Ok((String::from(name), uniform))
+}
+
+// A frame rate as a fraction: "30000/1001", or "29.97" which is read as
+// 2997/100.
+fn parse_fps(value: &str) -> Result<(u32, u32), String> {
+    let invalid = || format!("invalid frame rate: {}", value);
+    let (num, den) = match value.split_once('/') {
+        Some((num, den)) => (
+            num.parse::<u32>().map_err(|_| invalid())?,
+            den.parse::<u32>().map_err(|_| invalid())?,
+        ),
+        None => {
+            let fps = value.parse::<f64>().map_err(|_| invalid())?;
+            ((fps * 1000.0).round() as u32, 1000)
+        }
+    };
+    if num == 0 || den == 0 {
+        return Err(invalid());
+    }
+
+    let (mut a, mut b) = (num, den);
+    while b != 0 {
+        let r = a % b;
+        a = b;
+        b = r;
+    }
+    Ok((num / a, den / a))
+}
+
+fn parse_number<T: std::str::FromStr>(name: &str, value: &str) -> Result<T, String> {
+    value
+        .parse::<T>()
+        .map_err(|_| format!("invalid value for {}: {}", name, value))
+}
+
+impl RenderOptions {
+    // Time between two frames of the animation.
+    pub fn frame_step(&self) -> f64 {
+        self.fps.1 as f64 / self.fps.0 as f64
+    }
+
+    // Where to write frame `frame` of an animation: the first run of # in
+    // the file name becomes the zero padded frame number, or the number is
+    // appended to the name when there is none.
+    pub fn frame_path(&self, frame: u32) -> PathBuf {
+        let stem = self
+            .output
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let name = match stem.find('#') {
+            Some(start) => {
+                let width = stem[start..].chars().take_while(|c| *c == '#').count();
+                format!(
+                    "{}{:0width$}{}",
+                    &stem[..start],
+                    frame,
+                    &stem[start + width..],
+                    width = width
+                )
+            }
+            None => format!("{}_{:04}", stem, frame),
+        };
+
+        let mut path = self.output.with_file_name(name);
+        if let Some(extension) = self.output.extension() {
+            path.set_extension(extension);
+        }
+        path
+    }
+
+    pub fn parse(args: &[String]) -> Result<RenderOptions, String> {
+        let mut options = RenderOptions::default();
+        let mut tiles_per_frame = 1;
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .map(|v| v.as_str())
+                    .ok_or_else(|| format!("missing value for {}", arg))
+            };
+            match arg.as_str() {
+                "-o" | "--output" => options.output = PathBuf::from(value()?),
+                "--size" => {
+                    let (w, h) = parse_size(value()?)?;
+                    options.width = w;
+                    options.height = h;
+                }
+                "--samples" => options.samples = parse_number(arg, value()?)?,
+                "--time-budget" => {
+                    let secs: f32 = parse_number(arg, value()?)?;
+                    options.time_budget = Some(Duration::from_secs_f32(secs.max(0.0)));
+                }
+                "--noise" => options.noise = Some(parse_number(arg, value()?)?),
+                "--pipeline" => options.pipeline = String::from(value()?),
+                "--time" => options.time = parse_number(arg, value()?)?,
+                "--seed" => options.seed = parse_number(arg, value()?)?,
+                "--frames" => options.frames = Some(parse_number(arg, value()?)?),
+                "--fps" => options.fps = parse_fps(value()?)?,
+                "--video" => options.video = Some(PathBuf::from(value()?)),
+                "--encoder" => {
+                    let command: Vec<String> =
+                        value()?.split_whitespace().map(String::from).collect();
+                    if command.is_empty() {
+                        return Err(String::from("empty encoder command"));
+                    }
+                    options.encoder = Some(command);
+                }
+                "--color-range" => {
+                    options.video_options.range = match value()? {
+                        "limited" => ColorRange::Limited,
+                        "full" => ColorRange::Full,
+                        other => return Err(format!("invalid color range: {}", other)),
+                    }
+                }
+                "--chroma" => {
+                    options.video_options.chroma = match value()? {
+                        "420" => Chroma::C420,
+                        "422" => Chroma::C422,
+                        "444" => Chroma::C444,
+                        other => return Err(format!("invalid chroma subsampling: {}", other)),
+                    }
+                }
+                "--eye" => options.eye = parse_vec3(value()?)?,
+                "--target" => options.target = parse_vec3(value()?)?,
+                "--up" => options.up = parse_vec3(value()?)?,
+                "--focus" => {
+                    let v = parse_floats(value()?, 2)?;
+                    options.focus_pos = Vector2::new(v[0], v[1]);
+                }
+                "--aperture" => options.aperture = parse_number(arg, value()?)?,
+                "--uniform" => options.uniforms.push(parse_uniform(value()?)?),
+                "--timeline" => options.timeline = Some(PathBuf::from(value()?)),
+                "--env" => options.environment = Some(PathBuf::from(value()?)),
+                "--scene" => options.world = Some(PathBuf::from(value()?)),
+                "--env-rotation" => {
+                    let degrees: f32 = parse_number(arg, value()?)?;
+                    options.uniforms.push((
+                        String::from("env_rotation"),
+                        peglrs::UniformValue::Float(degrees.to_radians()),
+                    ));
+                }
+                "--env-intensity" => options.uniforms.push((
+                    String::from("env_intensity"),
+                    peglrs::UniformValue::Float(parse_number(arg, value()?)?),
+                )),
+                "--tile" => {
+                    let (width, height) = parse_size(value()?)?;
+                    options.tiling = Some(peglrs::TileConfig {
+                        width,
+                        height,
+                        per_frame: 1,
+                    });
+                }
+                "--profile" => options.profile = true,
+                "--tiles-per-frame" => tiles_per_frame = parse_number(arg, value()?)?,
+                _ => return Err(format!("unknown option {}", arg)),
+            }
+        }
+
+        if options.samples == 0 {
+            return Err(String::from("at least one sample is needed"));
+        }
+        if options.frames == Some(0) {
+            return Err(String::from("at least one frame is needed"));
+        }
+        if options.video.is_some() && options.encoder.is_some() {
+            return Err(String::from("--video and --encoder can't be used together"));
+        }
+        options.video_options.fps = options.fps;
+        if let Some(tiling) = options.tiling.as_mut() {
+            tiling.per_frame = tiles_per_frame;
+        }
+        Ok(options)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<RenderOptions, String> {
+        let args: Vec<String> = args.iter().map(|arg| String::from(*arg)).collect();
+        RenderOptions::parse(&args)
+    }
+
+    #[test]
+    fn parses_numbers_and_vectors() {
+        assert_eq!(parse_floats("1, 2.5,-3", 3), Ok(vec![1.0, 2.5, -3.0]));
+        assert!(parse_floats("1,2", 3).is_err());
+        assert!(parse_floats("1,a,3", 3).is_err());
+        assert_eq!(parse_vec3("0,1,0"), Ok(Vector3::new(0.0, 1.0, 0.0)));
+        assert_eq!(parse_number::<u32>("--samples", "12"), Ok(12));
+        assert!(parse_number::<u32>("--samples", "-1").is_err());
+    }
+
+    #[test]
+    fn parses_sizes() {
+        assert_eq!(parse_size("1920x1080"), Ok((1920, 1080)));
+        for size in ["1920", "1920x", "x1080", "0x10", "-4x4", "1x2x3", "axb"] {
+            assert!(parse_size(size).is_err(), "{}", size);
+        }
+    }
+
+    #[test]
+    fn parses_uniforms() {
+        assert_eq!(
+            parse_uniform("exposure=1.5"),
+            Ok((String::from("exposure"), peglrs::UniformValue::Float(1.5)))
+        );
+        assert_eq!(
+            parse_uniform("tint=1,0.5,0"),
+            Ok((
+                String::from("tint"),
+                peglrs::UniformValue::Vec3(Vector3::new(1.0, 0.5, 0.0))
+            ))
+        );
+        for uniform in ["exposure", "exposure=", "exposure=a", "v=1,2,3,4,5"] {
+            assert!(parse_uniform(uniform).is_err(), "{}", uniform);
+        }
+    }
+
+    #[test]
+    fn parses_options() {
+        let options = parse(&[
+            "-o",
+            "out.hdr",
+            "--size",
+            "64x32",
+            "--samples",
+            "8",
+            "--uniform",
+            "exposure=2",
+            "--tile",
+            "16x16",
+            "--tiles-per-frame",
+            "2",
+            "--profile",
+        ])
+        .unwrap();
+        assert_eq!(options.output, PathBuf::from("out.hdr"));
+        assert_eq!((options.width, options.height), (64, 32));
+        assert_eq!(options.samples, 8);
+        assert_eq!(options.uniforms.len(), 1);
+        assert_eq!(
+            options.tiling,
+            Some(peglrs::TileConfig {
+                width: 16,
+                height: 16,
+                per_frame: 2,
+            })
+        );
+        assert!(options.profile);
+
+        let options = parse(&[]).unwrap();
+        assert_eq!(options.output, PathBuf::from("render.png"));
+        assert_eq!(options.samples, 64);
+    }
+
+    #[test]
+    fn rejects_bad_options() {
+        assert_eq!(parse(&["--nope"]).unwrap_err(), "unknown option --nope");
+        assert_eq!(parse(&["--size"]).unwrap_err(), "missing value for --size");
+        assert!(parse(&["--size", "big"]).is_err());
+        assert!(parse(&["--samples", "0"]).is_err());
+        assert!(parse(&["--chroma", "411"]).is_err());
+        assert!(parse(&["--video", "a.y4m", "--encoder", "cat"]).is_err());
+    }
+
+    #[test]
+    fn parses_frame_rates() {
+        assert_eq!(parse_fps("30"), Ok((30, 1)));
+        assert_eq!(parse_fps("30000/1001"), Ok((30000, 1001)));
+        assert_eq!(parse_fps("50/2"), Ok((25, 1)));
+        assert_eq!(parse_fps("29.97"), Ok((2997, 100)));
+        for fps in ["0", "0/1", "30/0", "x/0", "30/", "-1", "fast"] {
+            assert!(parse_fps(fps).is_err(), "{}", fps);
+        }
+
+        let options = parse(&["--fps", "30000/1001"]).unwrap();
+        assert_eq!(options.video_options.fps, (30000, 1001));
+        assert!((options.frame_step() - 1001.0 / 30000.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn names_frames() {
+        let options = parse(&["-o", "out/shot_###_v2.png"]).unwrap();
+        assert_eq!(options.frame_path(7), PathBuf::from("out/shot_007_v2.png"));
+        assert_eq!(
+            options.frame_path(1234),
+            PathBuf::from("out/shot_1234_v2.png")
+        );
+
+        let options = parse(&["-o", "render.exr"]).unwrap();
+        assert_eq!(options.frame_path(12), PathBuf::from("render_0012.exr"));
+        let options = parse(&["-o", "frames/render"]).unwrap();
+        assert_eq!(options.frame_path(3), PathBuf::from("frames/render_0003"));
+    }
+}
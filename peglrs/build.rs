@@ -0,0 +1,30 @@
+extern crate cbindgen;
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+fn main() {
+    let crate_dir = env::var("CARGO_MANIFEST_DIR").unwrap();
+    let header = PathBuf::from(&crate_dir).join("include").join("peglrs.h");
+
+    println!("cargo:rerun-if-changed=src/capi");
+    println!("cargo:rerun-if-changed=cbindgen.toml");
+
+    match cbindgen::generate(&crate_dir) {
+        Ok(bindings) => {
+            let mut src = Vec::new();
+            bindings.write(&mut src);
+            // Leave the header alone when nothing changed, so its timestamp
+            // doesn't make the C users rebuild.
+            if fs::read(&header).ok().as_ref() != Some(&src) {
+                if let Err(err) = fs::write(&header, &src) {
+                    println!("cargo:warning=Couldn't write the C header: {}", err);
+                }
+            }
+        }
+        Err(err) => {
+            println!("cargo:warning=Couldn't generate the C header: {}", err);
+        }
+    }
+}
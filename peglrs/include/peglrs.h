@@ -0,0 +1,137 @@
+#ifndef PEGLRS_H
+#define PEGLRS_H
+
+/* Generated by cbindgen from src/capi, do not edit. */
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+typedef enum PeglrsErrorCode {
+  PEGLRS_ERROR_CODE_OK = 0,
+  PEGLRS_ERROR_CODE_NULL_POINTER = 1,
+  PEGLRS_ERROR_CODE_INVALID_ARGUMENT = 2,
+  PEGLRS_ERROR_CODE_PANIC = 3,
+} PeglrsErrorCode;
+
+typedef enum PeglrsResizeMode {
+  PEGLRS_RESIZE_MODE_RESET = 0,
+  PEGLRS_RESIZE_MODE_RESCALE = 1,
+  PEGLRS_RESIZE_MODE_FIXED = 2,
+} PeglrsResizeMode;
+
+typedef struct PeglrsScene PeglrsScene;
+
+typedef struct PeglrsSceneConfig {
+  double width;
+  double height;
+  double dpi_ratio;
+  /**
+   * Path to the pipeline file, or NULL for the default one.
+   */
+  const char *pipeline;
+} PeglrsSceneConfig;
+
+typedef struct PeglrsVec3 {
+  float x;
+  float y;
+  float z;
+} PeglrsVec3;
+
+typedef struct PeglrsVec2 {
+  float x;
+  float y;
+} PeglrsVec2;
+
+typedef struct PeglrsCameraState {
+  struct PeglrsVec3 eye;
+  struct PeglrsVec3 target;
+  struct PeglrsVec3 up;
+  struct PeglrsVec2 focus_pos;
+  float aperture;
+} PeglrsCameraState;
+
+/**
+ * Message of the last error which happened on this thread, or NULL.
+ * The string stays valid until the next failing call on this thread.
+ */
+const char *peglrs_last_error(void);
+
+enum PeglrsErrorCode peglrs_load_gl_symbol(void);
+
+enum PeglrsErrorCode peglrs_print_gl_info(void);
+
+enum PeglrsErrorCode peglrs_init_gl(double width, double height, double dpi_ratio);
+
+/**
+ * Create a scene. Returns NULL on failure.
+ *
+ * # Safety
+ * `config` must be NULL or point to a valid `SceneConfig`, whose `pipeline`
+ * is NULL or a valid C string.
+ */
+struct PeglrsScene *peglrs_scene_new(const struct PeglrsSceneConfig *config);
+
+/**
+ * Destroy a scene created by `peglrs_scene_new`. NULL is ignored.
+ *
+ * # Safety
+ * `scene` must be NULL or come from `peglrs_scene_new`, and not be used afterwards.
+ */
+void peglrs_scene_free(struct PeglrsScene *scene);
+
+/**
+ * # Safety
+ * `scene` must be NULL or come from `peglrs_scene_new`.
+ */
+enum PeglrsErrorCode peglrs_display_loop(struct PeglrsScene *scene,
+                                         double time,
+                                         uint32_t fbo,
+                                         bool reset_on_reload);
+
+/**
+ * # Safety
+ * `scene` must be NULL or come from `peglrs_scene_new`.
+ */
+enum PeglrsErrorCode peglrs_resize_window(struct PeglrsScene *scene,
+                                          double width,
+                                          double height,
+                                          double dpi_ratio);
+
+/**
+ * `mode` is a `ResizeMode`. `width` and `height` are only used by
+ * `ResizeMode::Fixed`.
+ *
+ * # Safety
+ * `scene` must be NULL or come from `peglrs_scene_new`.
+ */
+enum PeglrsErrorCode peglrs_set_resize_policy(struct PeglrsScene *scene,
+                                              uint32_t mode,
+                                              int32_t width,
+                                              int32_t height);
+
+/**
+ * Samples above what the driver supports are clamped.
+ *
+ * # Safety
+ * `scene` must be NULL or come from `peglrs_scene_new`.
+ */
+enum PeglrsErrorCode peglrs_set_pass_samples(struct PeglrsScene *scene,
+                                             uintptr_t pass,
+                                             int32_t samples);
+
+/**
+ * # Safety
+ * `scene` must be NULL or come from `peglrs_scene_new`, and `camera` must be
+ * NULL or point to a valid `CameraState`.
+ */
+enum PeglrsErrorCode peglrs_update_camera(struct PeglrsScene *scene,
+                                          const struct PeglrsCameraState *camera);
+
+/**
+ * # Safety
+ * `scene` must be NULL or come from `peglrs_scene_new`.
+ */
+enum PeglrsErrorCode peglrs_reset(struct PeglrsScene *scene, uint32_t fbo);
+
+#endif /* PEGLRS_H */
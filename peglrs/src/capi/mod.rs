@@ -0,0 +1,366 @@
+// C API of peglrs. Every function here can be called from any language able
+// to call C: scenes are opaque pointers, errors are returned as codes and the
+// message of the last error can be fetched with `peglrs_last_error`.
+//
+// The header is generated by cbindgen at build time in `include/peglrs.h`.
+
+use std::cell::RefCell;
+use std::ffi::{CStr, CString};
+use std::os::raw::c_char;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::ptr;
+
+use cgmath::{Vector2, Vector3};
+
+use crate::{ResizePolicy, Scene};
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct Vec2 {
+    pub x: f32,
+    pub y: f32,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct Vec3 {
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct CameraState {
+    pub eye: Vec3,
+    pub target: Vec3,
+    pub up: Vec3,
+    pub focus_pos: Vec2,
+    pub aperture: f32,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct SceneConfig {
+    pub width: f64,
+    pub height: f64,
+    pub dpi_ratio: f64,
+    /// Path to the pipeline file, or NULL for the default one.
+    pub pipeline: *const c_char,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    Ok = 0,
+    NullPointer = 1,
+    InvalidArgument = 2,
+    Panic = 3,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResizeMode {
+    Reset = 0,
+    Rescale = 1,
+    Fixed = 2,
+}
+
+impl ResizeMode {
+    // C may pass any integer for an enum, which would be UB as a Rust enum,
+    // so the functions take a u32 and check it here.
+    fn from_raw(mode: u32) -> Option<ResizeMode> {
+        match mode {
+            0 => Some(ResizeMode::Reset),
+            1 => Some(ResizeMode::Rescale),
+            2 => Some(ResizeMode::Fixed),
+            _ => None,
+        }
+    }
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(message: &str) {
+    let message = CString::new(message.replace('\0', " ")).unwrap();
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+}
+
+impl From<Vec2> for Vector2<f32> {
+    fn from(v: Vec2) -> Vector2<f32> {
+        Vector2::new(v.x, v.y)
+    }
+}
+
+impl From<Vec3> for Vector3<f32> {
+    fn from(v: Vec3) -> Vector3<f32> {
+        Vector3::new(v.x, v.y, v.z)
+    }
+}
+
+// Run `f` on the scene behind `scene`, turning null pointers and panics into
+// error codes.
+//
+// # Safety
+// `scene` must be NULL or come from `peglrs_scene_new`.
+unsafe fn with_scene<F>(scene: *mut Scene, f: F) -> ErrorCode
+where
+    F: FnOnce(&mut Scene) -> ErrorCode,
+{
+    if scene.is_null() {
+        set_last_error("scene is NULL");
+        return ErrorCode::NullPointer;
+    }
+
+    let scene = &mut *scene;
+    guard(|| f(scene))
+}
+
+fn guard<F>(f: F) -> ErrorCode
+where
+    F: FnOnce() -> ErrorCode,
+{
+    match catch_unwind(AssertUnwindSafe(f)) {
+        Ok(code) => code,
+        Err(err) => {
+            let message = err
+                .downcast_ref::<&str>()
+                .map(|s| s.to_string())
+                .or_else(|| err.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| String::from("unknown panic"));
+            set_last_error(&format!("panic: {}", message));
+            ErrorCode::Panic
+        }
+    }
+}
+
+/// Message of the last error which happened on this thread, or NULL.
+/// The string stays valid until the next failing call on this thread.
+#[no_mangle]
+pub extern "C" fn peglrs_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| match &*last.borrow() {
+        Some(message) => message.as_ptr(),
+        None => ptr::null(),
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn peglrs_load_gl_symbol() -> ErrorCode {
+    guard(|| {
+        crate::load_gl_symbol();
+        ErrorCode::Ok
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn peglrs_print_gl_info() -> ErrorCode {
+    guard(|| {
+        crate::print_gl_info();
+        ErrorCode::Ok
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn peglrs_init_gl(width: f64, height: f64, dpi_ratio: f64) -> ErrorCode {
+    guard(|| {
+        crate::init_gl(width, height, dpi_ratio);
+        ErrorCode::Ok
+    })
+}
+
+/// Create a scene. Returns NULL on failure.
+///
+/// # Safety
+/// `config` must be NULL or point to a valid `SceneConfig`, whose `pipeline`
+/// is NULL or a valid C string.
+#[no_mangle]
+pub unsafe extern "C" fn peglrs_scene_new(config: *const SceneConfig) -> *mut Scene {
+    if config.is_null() {
+        set_last_error("config is NULL");
+        return ptr::null_mut();
+    }
+
+    let config = &*config;
+    let path = if config.pipeline.is_null() {
+        String::from(crate::DEFAULT_PIPELINE)
+    } else {
+        match CStr::from_ptr(config.pipeline).to_str() {
+            Ok(path) => String::from(path),
+            Err(_) => {
+                set_last_error("pipeline path is not valid UTF-8");
+                return ptr::null_mut();
+            }
+        }
+    };
+
+    let mut scene: Option<Scene> = None;
+    let code = guard(|| {
+        scene =
+            crate::init_scene_from_file(&path, config.width, config.height, config.dpi_ratio);
+        ErrorCode::Ok
+    });
+    match scene {
+        Some(scene) => Box::into_raw(Box::new(scene)),
+        None => {
+            if code == ErrorCode::Ok {
+                set_last_error(&format!("couldn't load pipeline {}", path));
+            }
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Destroy a scene created by `peglrs_scene_new`. NULL is ignored.
+///
+/// # Safety
+/// `scene` must be NULL or come from `peglrs_scene_new`, and not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn peglrs_scene_free(scene: *mut Scene) {
+    if !scene.is_null() {
+        let scene = Box::from_raw(scene);
+        guard(|| {
+            crate::quit(*scene);
+            ErrorCode::Ok
+        });
+    }
+}
+
+/// # Safety
+/// `scene` must be NULL or come from `peglrs_scene_new`.
+#[no_mangle]
+pub unsafe extern "C" fn peglrs_display_loop(
+    scene: *mut Scene,
+    time: f64,
+    fbo: u32,
+    reset_on_reload: bool,
+) -> ErrorCode {
+    with_scene(scene, |scene| {
+        crate::display_loop(scene, time, fbo, reset_on_reload);
+        ErrorCode::Ok
+    })
+}
+
+/// # Safety
+/// `scene` must be NULL or come from `peglrs_scene_new`.
+#[no_mangle]
+pub unsafe extern "C" fn peglrs_resize_window(
+    scene: *mut Scene,
+    width: f64,
+    height: f64,
+    dpi_ratio: f64,
+) -> ErrorCode {
+    with_scene(scene, |scene| {
+        crate::resize_window(scene, width, height, dpi_ratio);
+        ErrorCode::Ok
+    })
+}
+
+/// `mode` is a `ResizeMode`. `width` and `height` are only used by
+/// `ResizeMode::Fixed`.
+///
+/// # Safety
+/// `scene` must be NULL or come from `peglrs_scene_new`.
+#[no_mangle]
+pub unsafe extern "C" fn peglrs_set_resize_policy(
+    scene: *mut Scene,
+    mode: u32,
+    width: i32,
+    height: i32,
+) -> ErrorCode {
+    with_scene(scene, |scene| {
+        let mode = match ResizeMode::from_raw(mode) {
+            Some(mode) => mode,
+            None => {
+                set_last_error(&format!("unknown resize mode {}", mode));
+                return ErrorCode::InvalidArgument;
+            }
+        };
+        let policy = match mode {
+            ResizeMode::Reset => ResizePolicy::Reset,
+            ResizeMode::Rescale => ResizePolicy::Rescale,
+            ResizeMode::Fixed => {
+                if width <= 0 || height <= 0 {
+                    set_last_error("fixed resolution must be positive");
+                    return ErrorCode::InvalidArgument;
+                }
+                ResizePolicy::Fixed { width, height }
+            }
+        };
+        crate::set_resize_policy(scene, policy);
+        ErrorCode::Ok
+    })
+}
+
+/// Samples above what the driver supports are clamped.
+///
+/// # Safety
+/// `scene` must be NULL or come from `peglrs_scene_new`.
+#[no_mangle]
+pub unsafe extern "C" fn peglrs_set_pass_samples(
+    scene: *mut Scene,
+    pass: usize,
+    samples: i32,
+) -> ErrorCode {
+    with_scene(scene, |scene| {
+        match crate::set_pass_samples(scene, pass, samples) {
+            Some(()) => ErrorCode::Ok,
+            None => {
+                set_last_error(&format!("can't render pass {} with {} samples", pass, samples));
+                ErrorCode::InvalidArgument
+            }
+        }
+    })
+}
+
+/// # Safety
+/// `scene` must be NULL or come from `peglrs_scene_new`, and `camera` must be
+/// NULL or point to a valid `CameraState`.
+#[no_mangle]
+pub unsafe extern "C" fn peglrs_update_camera(
+    scene: *mut Scene,
+    camera: *const CameraState,
+) -> ErrorCode {
+    if camera.is_null() {
+        set_last_error("camera is NULL");
+        return ErrorCode::NullPointer;
+    }
+
+    let camera = *camera;
+    with_scene(scene, |scene| {
+        crate::update_camera(
+            scene,
+            camera.eye.into(),
+            camera.target.into(),
+            camera.up.into(),
+            camera.focus_pos.into(),
+            camera.aperture,
+        );
+        ErrorCode::Ok
+    })
+}
+
+/// # Safety
+/// `scene` must be NULL or come from `peglrs_scene_new`.
+#[no_mangle]
+pub unsafe extern "C" fn peglrs_reset(scene: *mut Scene, fbo: u32) -> ErrorCode {
+    with_scene(scene, |scene| {
+        crate::reset(scene, fbo);
+        ErrorCode::Ok
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resize_modes_are_checked() {
+        assert_eq!(ResizeMode::from_raw(0), Some(ResizeMode::Reset));
+        assert_eq!(ResizeMode::from_raw(1), Some(ResizeMode::Rescale));
+        assert_eq!(ResizeMode::from_raw(2), Some(ResizeMode::Fixed));
+        assert_eq!(ResizeMode::from_raw(3), None);
+        assert_eq!(ResizeMode::from_raw(u32::MAX), None);
+    }
+}
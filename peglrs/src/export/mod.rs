@@ -0,0 +1,157 @@
+pub mod video;
+
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+use image::codecs::hdr::HdrEncoder;
+use image::{ColorType, Rgb};
+
+use crate::frame::fbo::Framebuffer;
+
+// Pixels read back from a framebuffer, as RGBA floats. Rows are stored top
+// to bottom, unlike GL.
+#[derive(Debug, Clone)]
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<f32>,
+}
+
+impl Image {
+    // Read the color of a framebuffer. Waits for the GPU to be done with it.
+    pub fn read(fbo: u32, width: u32, height: u32) -> Image {
+        Image::read_area(fbo, 0, 0, width, height)
+    }
+
+    // Read another color attachment than the first one of a framebuffer.
+    pub fn read_attachment(fbo: &Framebuffer, attachment: usize) -> Image {
+        unsafe {
+            gl::BindFramebuffer(gl::READ_FRAMEBUFFER, fbo.addr);
+            gl::ReadBuffer(gl::COLOR_ATTACHMENT0 + attachment as u32);
+        }
+        let image = Image::read(fbo.addr, fbo.width as u32, fbo.height as u32);
+        unsafe {
+            gl::BindFramebuffer(gl::READ_FRAMEBUFFER, fbo.addr);
+            gl::ReadBuffer(gl::COLOR_ATTACHMENT0);
+            gl::BindFramebuffer(gl::READ_FRAMEBUFFER, 0);
+        }
+        image
+    }
+
+    // Read the part of a framebuffer starting at (x, y) from the bottom left.
+    pub fn read_area(fbo: u32, x: i32, y: i32, width: u32, height: u32) -> Image {
+        let mut pixels = vec![0.0f32; (width * height * 4) as usize];
+        unsafe {
+            gl::BindFramebuffer(gl::READ_FRAMEBUFFER, fbo);
+            gl::PixelStorei(gl::PACK_ALIGNMENT, 4);
+            gl::ReadPixels(
+                x,
+                y,
+                width as i32,
+                height as i32,
+                gl::RGBA,
+                gl::FLOAT,
+                pixels.as_mut_ptr() as *mut _,
+            );
+            gl::BindFramebuffer(gl::READ_FRAMEBUFFER, 0);
+        }
+
+        let row = (width * 4) as usize;
+        let mut flipped = Vec::with_capacity(pixels.len());
+        for line in pixels.chunks(row).rev() {
+            flipped.extend_from_slice(line);
+        }
+
+        Image {
+            width,
+            height,
+            pixels: flipped,
+        }
+    }
+
+    // Clamp to [0, 1] and quantize, keeping `channels` channels per pixel.
+    pub fn to_bytes(&self, channels: usize) -> Vec<u8> {
+        self.pixels
+            .chunks(4)
+            .flat_map(|pixel| pixel[..channels].iter())
+            .map(|v| (v.clamp(0.0, 1.0) * 255.0 + 0.5) as u8)
+            .collect()
+    }
+
+    // Save to a file, the format is picked from the extension. `.hdr` keeps
+    // the float values, every other format is 8 bits per channel.
+    pub fn save(&self, path: &Path) -> Option<()> {
+        let ext = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| ext.to_lowercase());
+
+        let res = match ext.as_deref() {
+            Some("hdr") => self.save_hdr(path),
+            Some("jpg") | Some("jpeg") => image::save_buffer(
+                path,
+                &self.to_bytes(3),
+                self.width,
+                self.height,
+                ColorType::Rgb8,
+            ),
+            _ => image::save_buffer(
+                path,
+                &self.to_bytes(4),
+                self.width,
+                self.height,
+                ColorType::Rgba8,
+            ),
+        };
+
+        match res {
+            Ok(()) => Some(()),
+            Err(err) => {
+                eprintln!("[ERR] Couldn't save image {} : {}", path.display(), err);
+                None
+            }
+        }
+    }
+
+    fn save_hdr(&self, path: &Path) -> image::ImageResult<()> {
+        let file = File::create(path)?;
+        let pixels: Vec<Rgb<f32>> = self
+            .pixels
+            .chunks(4)
+            .map(|pixel| Rgb([pixel[0], pixel[1], pixel[2]]))
+            .collect();
+        HdrEncoder::new(BufWriter::new(file)).encode(
+            &pixels,
+            self.width as usize,
+            self.height as usize,
+        )
+    }
+}
+
+// A framebuffer standing in for the screen when rendering without a window.
+#[derive(Debug)]
+pub struct Offscreen {
+    framebuffer: Framebuffer,
+}
+
+impl Offscreen {
+    pub fn new(width: i32, height: i32) -> Offscreen {
+        Offscreen {
+            framebuffer: Framebuffer::new_xhdr(width, height),
+        }
+    }
+
+    // To be given as the `fbo` of `display_loop`.
+    pub fn addr(&self) -> u32 {
+        self.framebuffer.addr
+    }
+
+    pub fn read(&self) -> Image {
+        Image::read(
+            self.framebuffer.addr,
+            self.framebuffer.width as u32,
+            self.framebuffer.height as u32,
+        )
+    }
+}
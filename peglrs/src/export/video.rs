@@ -0,0 +1,459 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+
+use super::Image;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorRange {
+    // Y in [16, 235] and chroma in [16, 240], what most players expect.
+    Limited,
+    Full,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Chroma {
+    C420,
+    C422,
+    C444,
+}
+
+impl Chroma {
+    // Size of a block of pixels sharing the same chroma.
+    fn block(self) -> (u32, u32) {
+        match self {
+            Chroma::C420 => (2, 2),
+            Chroma::C422 => (2, 1),
+            Chroma::C444 => (1, 1),
+        }
+    }
+
+    fn tag(self) -> &'static str {
+        match self {
+            Chroma::C420 => "420jpeg",
+            Chroma::C422 => "422",
+            Chroma::C444 => "444",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VideoOptions {
+    // Frame rate as a fraction, 30000/1001 for NTSC.
+    pub fps: (u32, u32),
+    pub range: ColorRange,
+    pub chroma: Chroma,
+}
+
+impl Default for VideoOptions {
+    fn default() -> VideoOptions {
+        VideoOptions {
+            fps: (30, 1),
+            range: ColorRange::Limited,
+            chroma: Chroma::C420,
+        }
+    }
+}
+
+impl fmt::Display for VideoOptions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let range = match self.range {
+            ColorRange::Limited => "limited",
+            ColorRange::Full => "full",
+        };
+        write!(
+            f,
+            "{}/{} fps, {} range, {}",
+            self.fps.0,
+            self.fps.1,
+            range,
+            self.chroma.tag()
+        )
+    }
+}
+
+enum Output {
+    // YUV4MPEG2 to a file or a pipe.
+    Y4m(Box<dyn Write>),
+    // Raw RGBA frames to the standard input of an encoder.
+    Encoder(Child, BufWriter<std::process::ChildStdin>),
+}
+
+// Streams frames to a video as they get rendered. Every frame must have the
+// size the sink was created with.
+pub struct VideoSink {
+    output: Output,
+    width: u32,
+    height: u32,
+    options: VideoOptions,
+    frames: u32,
+}
+
+impl fmt::Debug for VideoSink {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("VideoSink")
+            .field("width", &self.width)
+            .field("height", &self.height)
+            .field("options", &self.options)
+            .field("frames", &self.frames)
+            .finish()
+    }
+}
+
+// BT.709 coefficients, the frames are expected to be gamma encoded already.
+fn to_ycbcr(r: f32, g: f32, b: f32) -> (f32, f32, f32) {
+    let y = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+    (y, (b - y) / 1.8556, (r - y) / 1.5748)
+}
+
+fn quantize(v: f32, offset: f32, scale: f32) -> u8 {
+    (offset + v * scale + 0.5).clamp(0.0, 255.0) as u8
+}
+
+impl VideoSink {
+    // Write a Y4M video to `path`, or to the standard output for "-".
+    pub fn create(
+        path: &Path,
+        width: u32,
+        height: u32,
+        options: VideoOptions,
+    ) -> Option<VideoSink> {
+        let writer: Box<dyn Write> = if path == Path::new("-") {
+            Box::new(BufWriter::new(io::stdout()))
+        } else {
+            match File::create(path) {
+                Ok(file) => Box::new(BufWriter::new(file)),
+                Err(err) => {
+                    eprintln!("[ERR] Couldn't create video {} : {}", path.display(), err);
+                    return None;
+                }
+            }
+        };
+        VideoSink::y4m(writer, width, height, options)
+    }
+
+    // Write a Y4M video to any writer.
+    pub fn y4m(
+        writer: Box<dyn Write>,
+        width: u32,
+        height: u32,
+        options: VideoOptions,
+    ) -> Option<VideoSink> {
+        let mut sink = VideoSink {
+            output: Output::Y4m(writer),
+            width,
+            height,
+            options,
+            frames: 0,
+        };
+        let range = match options.range {
+            ColorRange::Limited => "LIMITED",
+            ColorRange::Full => "FULL",
+        };
+        let header = format!(
+            "YUV4MPEG2 W{} H{} F{}:{} Ip A1:1 C{} XCOLORRANGE={}\n",
+            width,
+            height,
+            options.fps.0,
+            options.fps.1,
+            options.chroma.tag(),
+            range
+        );
+        sink.write(header.as_bytes())?;
+        Some(sink)
+    }
+
+    // Start `command` and write raw RGBA frames, top row first, to its
+    // standard input. In the arguments, {width}, {height} and {fps} are
+    // replaced by the size and frame rate of the video.
+    pub fn spawn(
+        command: &[String],
+        width: u32,
+        height: u32,
+        options: VideoOptions,
+    ) -> Option<VideoSink> {
+        let fps = format!("{}/{}", options.fps.0, options.fps.1);
+        let args: Vec<String> = command
+            .iter()
+            .map(|arg| {
+                arg.replace("{width}", &width.to_string())
+                    .replace("{height}", &height.to_string())
+                    .replace("{fps}", &fps)
+            })
+            .collect();
+        let (program, args) = match args.split_first() {
+            Some(split) => split,
+            None => {
+                eprintln!("[ERR] No encoder command given");
+                return None;
+            }
+        };
+
+        let mut child = match Command::new(program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .spawn()
+        {
+            Ok(child) => child,
+            Err(err) => {
+                eprintln!("[ERR] Couldn't start encoder {} : {}", program, err);
+                return None;
+            }
+        };
+        let stdin = child.stdin.take()?;
+        Some(VideoSink {
+            output: Output::Encoder(child, BufWriter::new(stdin)),
+            width,
+            height,
+            options,
+            frames: 0,
+        })
+    }
+
+    pub fn frames(&self) -> u32 {
+        self.frames
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> Option<()> {
+        let res = match &mut self.output {
+            Output::Y4m(writer) => writer.write_all(bytes),
+            Output::Encoder(_, stdin) => stdin.write_all(bytes),
+        };
+        match res {
+            Ok(()) => Some(()),
+            Err(err) => {
+                eprintln!("[ERR] Couldn't write video frame : {}", err);
+                None
+            }
+        }
+    }
+
+    pub fn write_frame(&mut self, image: &Image) -> Option<()> {
+        if image.width != self.width || image.height != self.height {
+            eprintln!(
+                "[ERR] Video frame is {}x{}, expected {}x{}",
+                image.width, image.height, self.width, self.height
+            );
+            return None;
+        }
+
+        let bytes = match self.output {
+            Output::Y4m(_) => {
+                let mut bytes = b"FRAME\n".to_vec();
+                bytes.extend(self.planes(image));
+                bytes
+            }
+            Output::Encoder(..) => image.to_bytes(4),
+        };
+        self.write(&bytes)?;
+        self.frames += 1;
+        Some(())
+    }
+
+    // The Y, Cb and Cr planes of a frame, one after the other. Chroma is
+    // averaged over the blocks of pixels sharing it.
+    fn planes(&self, image: &Image) -> Vec<u8> {
+        let (y_offset, y_scale, c_scale) = match self.options.range {
+            ColorRange::Limited => (16.0, 219.0, 224.0),
+            ColorRange::Full => (0.0, 255.0, 255.0),
+        };
+        let (width, height) = (self.width as usize, self.height as usize);
+        let ycbcr: Vec<(f32, f32, f32)> = image
+            .pixels
+            .chunks(4)
+            .map(|p| {
+                to_ycbcr(
+                    p[0].clamp(0.0, 1.0),
+                    p[1].clamp(0.0, 1.0),
+                    p[2].clamp(0.0, 1.0),
+                )
+            })
+            .collect();
+
+        let mut planes: Vec<u8> = ycbcr
+            .iter()
+            .map(|c| quantize(c.0, y_offset, y_scale))
+            .collect();
+
+        let (bw, bh) = self.options.chroma.block();
+        let (bw, bh) = (bw as usize, bh as usize);
+        let (cw, ch) = (width.div_ceil(bw), height.div_ceil(bh));
+        let mut cb = Vec::with_capacity(cw * ch);
+        let mut cr = Vec::with_capacity(cw * ch);
+        for by in 0..ch {
+            for bx in 0..cw {
+                let (mut sum_b, mut sum_r, mut count) = (0.0, 0.0, 0.0);
+                for y in by * bh..((by + 1) * bh).min(height) {
+                    for x in bx * bw..((bx + 1) * bw).min(width) {
+                        let c = ycbcr[y * width + x];
+                        sum_b += c.1;
+                        sum_r += c.2;
+                        count += 1.0;
+                    }
+                }
+                cb.push(quantize(sum_b / count, 128.0, c_scale));
+                cr.push(quantize(sum_r / count, 128.0, c_scale));
+            }
+        }
+
+        planes.extend(cb);
+        planes.extend(cr);
+        planes
+    }
+
+    // Flush the video, and wait for the encoder to be done if there is one.
+    pub fn finish(self) -> Option<()> {
+        let res = match self.output {
+            Output::Y4m(mut writer) => writer.flush(),
+            Output::Encoder(mut child, mut stdin) => {
+                let flushed = stdin.flush();
+                // Closing its input tells the encoder the video is over.
+                drop(stdin);
+                flushed.and_then(|()| child.wait()).and_then(|status| {
+                    if status.success() {
+                        Ok(())
+                    } else {
+                        Err(io::Error::other(format!("encoder {}", status)))
+                    }
+                })
+            }
+        };
+        match res {
+            Ok(()) => Some(()),
+            Err(err) => {
+                eprintln!("[ERR] Couldn't finish video : {}", err);
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    // A writer whose bytes can still be read once the sink owns it.
+    #[derive(Clone, Default)]
+    struct Shared(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn image(width: u32, height: u32, color: [f32; 4]) -> Image {
+        Image {
+            width,
+            height,
+            pixels: color.repeat((width * height) as usize),
+        }
+    }
+
+    // The bytes of one frame, without the headers.
+    fn encode(image: &Image, options: VideoOptions) -> Vec<u8> {
+        let out = Shared::default();
+        let mut sink =
+            VideoSink::y4m(Box::new(out.clone()), image.width, image.height, options).unwrap();
+        let header_len = out.0.borrow().len() + b"FRAME\n".len();
+        sink.write_frame(image).unwrap();
+        sink.finish().unwrap();
+        let bytes = out.0.borrow();
+        bytes[header_len..].to_vec()
+    }
+
+    #[test]
+    fn writes_the_y4m_header() {
+        let out = Shared::default();
+        let options = VideoOptions {
+            fps: (30000, 1001),
+            range: ColorRange::Full,
+            chroma: Chroma::C422,
+        };
+        let mut sink = VideoSink::y4m(Box::new(out.clone()), 4, 2, options).unwrap();
+        sink.write_frame(&image(4, 2, [0.0; 4])).unwrap();
+        assert_eq!(sink.frames(), 1);
+        sink.finish().unwrap();
+
+        let bytes = out.0.borrow();
+        let header = b"YUV4MPEG2 W4 H2 F30000:1001 Ip A1:1 C422 XCOLORRANGE=FULL\nFRAME\n";
+        assert!(bytes.starts_with(header));
+        assert_eq!(bytes.len(), header.len() + 4 * 2 + 2 * 2 * 2);
+    }
+
+    #[test]
+    fn rounds_the_chroma_planes_up_for_odd_sizes() {
+        let black = image(5, 3, [0.0, 0.0, 0.0, 1.0]);
+        let c420 = VideoOptions::default();
+        // 15 luma samples, then two 3x2 chroma planes.
+        assert_eq!(encode(&black, c420).len(), 15 + 2 * 3 * 2);
+
+        let c422 = VideoOptions {
+            chroma: Chroma::C422,
+            ..c420
+        };
+        assert_eq!(encode(&black, c422).len(), 15 + 2 * 3 * 3);
+    }
+
+    #[test]
+    fn rejects_frames_of_another_size() {
+        let mut sink =
+            VideoSink::y4m(Box::new(Shared::default()), 4, 4, VideoOptions::default()).unwrap();
+        assert!(sink.write_frame(&image(4, 2, [0.0; 4])).is_none());
+        assert_eq!(sink.frames(), 0);
+    }
+
+    #[test]
+    fn quantizes_to_the_color_range() {
+        let limited = VideoOptions {
+            chroma: Chroma::C444,
+            ..VideoOptions::default()
+        };
+        let full = VideoOptions {
+            range: ColorRange::Full,
+            ..limited
+        };
+
+        let black = image(1, 1, [0.0, 0.0, 0.0, 1.0]);
+        let white = image(1, 1, [1.0, 1.0, 1.0, 1.0]);
+        assert_eq!(encode(&black, limited), vec![16, 128, 128]);
+        assert_eq!(encode(&white, limited), vec![235, 128, 128]);
+        assert_eq!(encode(&black, full), vec![0, 128, 128]);
+        assert_eq!(encode(&white, full), vec![255, 128, 128]);
+
+        // Pure red has the largest Cr, which reaches 240 in limited range.
+        let red = image(1, 1, [1.0, 0.0, 0.0, 1.0]);
+        assert_eq!(encode(&red, limited)[2], 240);
+        assert_eq!(encode(&red, full)[2], 255);
+    }
+
+    #[test]
+    fn averages_the_chroma_of_a_block() {
+        // A red and a blue pixel share their chroma under 4:2:2.
+        let mut pixels = vec![1.0, 0.0, 0.0, 1.0];
+        pixels.extend(&[0.0, 0.0, 1.0, 1.0]);
+        let image = Image {
+            width: 2,
+            height: 1,
+            pixels,
+        };
+        let options = VideoOptions {
+            range: ColorRange::Full,
+            chroma: Chroma::C422,
+            ..VideoOptions::default()
+        };
+        let (_, cb, cr) = to_ycbcr(0.5, 0.0, 0.5);
+        let bytes = encode(&image, options);
+        assert_eq!(bytes.len(), 2 + 2);
+        assert_eq!(bytes[2], quantize(cb, 128.0, 255.0));
+        assert_eq!(bytes[3], quantize(cr, 128.0, 255.0));
+    }
+}
@@ -1,15 +1,29 @@
-#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+use serde::Deserialize;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Deserialize)]
 pub enum ColorAttachment {
+    #[serde(rename = "rgba8")]
     RGBA_8B,
+    #[serde(rename = "rgba16f")]
     RGBA_16F,
+    #[serde(rename = "rgba32f")]
     RGBA_32F,
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum DepthStencilAttachment {
     DEPTH24_STENCIL8,
 }
 
+// Everything needed to build a framebuffer, except its size.
+// A sample count of 1 or less gives a regular, single-sampled framebuffer.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct FramebufferDesc {
+    pub color: ColorAttachment,
+    pub depth_stencil: DepthStencilAttachment,
+    pub samples: i32,
+}
+
 #[derive(Debug)]
 pub struct Framebuffer {
     pub addr: u32,
@@ -17,6 +31,83 @@ pub struct Framebuffer {
     pub color_type: Option<ColorAttachment>,
     pub depth_stencil_attachment: Option<u32>,
     pub depth_stencil_type: Option<DepthStencilAttachment>,
+    // Color attachments after the first one, written by the fragment outputs
+    // at location 1 and up.
+    pub extra_color_attachments: Vec<u32>,
+    pub samples: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+fn gl_color_format(attachment_type: ColorAttachment) -> u32 {
+    match attachment_type {
+        ColorAttachment::RGBA_8B => gl::RGBA8,
+        ColorAttachment::RGBA_16F => gl::RGBA16F,
+        ColorAttachment::RGBA_32F => gl::RGBA32F,
+    }
+}
+
+fn gl_depth_stencil_format(attachment_type: &DepthStencilAttachment) -> u32 {
+    match attachment_type {
+        DepthStencilAttachment::DEPTH24_STENCIL8 => gl::DEPTH24_STENCIL8,
+    }
+}
+
+// Most samples a multisampled attachment can have.
+pub fn max_samples() -> i32 {
+    let mut max = 0;
+    unsafe {
+        gl::GetIntegerv(gl::MAX_SAMPLES, &mut max);
+    }
+    max.max(1)
+}
+
+pub fn make_multisample_color_attachment(
+    attachment_type: ColorAttachment,
+    samples: i32,
+    width: i32,
+    height: i32,
+) -> u32 {
+    let mut addr = 0;
+    unsafe {
+        gl::GenTextures(1, &mut addr);
+        gl::BindTexture(gl::TEXTURE_2D_MULTISAMPLE, addr);
+        gl::TexImage2DMultisample(
+            gl::TEXTURE_2D_MULTISAMPLE,
+            samples,
+            gl_color_format(attachment_type),
+            width,
+            height,
+            gl::TRUE,
+        );
+        gl::BindTexture(gl::TEXTURE_2D_MULTISAMPLE, 0);
+    }
+
+    addr
+}
+
+pub fn make_multisample_depth_stencil_attachment(
+    attachment_type: &DepthStencilAttachment,
+    samples: i32,
+    width: i32,
+    height: i32,
+) -> u32 {
+    let mut addr = 0;
+
+    unsafe {
+        gl::GenRenderbuffers(1, &mut addr);
+        gl::BindRenderbuffer(gl::RENDERBUFFER, addr);
+        gl::RenderbufferStorageMultisample(
+            gl::RENDERBUFFER,
+            samples,
+            gl_depth_stencil_format(attachment_type),
+            width,
+            height,
+        );
+        gl::BindRenderbuffer(gl::RENDERBUFFER, 0);
+    }
+
+    addr
 }
 
 pub fn make_color_attachment(attachment_type: ColorAttachment, width: i32, height: i32) -> u32 {
@@ -96,11 +187,12 @@ pub fn make_depth_stencil_attachment(
     unsafe {
         gl::GenRenderbuffers(1, &mut addr);
         gl::BindRenderbuffer(gl::RENDERBUFFER, addr);
-        match attachment_type {
-            &DepthStencilAttachment::DEPTH24_STENCIL8 => {
-                gl::RenderbufferStorage(gl::RENDERBUFFER, gl::DEPTH24_STENCIL8, width, height);
-            }
-        }
+        gl::RenderbufferStorage(
+            gl::RENDERBUFFER,
+            gl_depth_stencil_format(attachment_type),
+            width,
+            height,
+        );
         gl::BindRenderbuffer(gl::RENDERBUFFER, 0);
     }
 
@@ -116,6 +208,9 @@ impl Drop for Framebuffer {
             if self.depth_stencil_attachment.is_some() {
                 gl::DeleteRenderbuffers(1, &self.depth_stencil_attachment.unwrap());
             }
+            for attachment in &self.extra_color_attachments {
+                gl::DeleteTextures(1, attachment);
+            }
             gl::DeleteFramebuffers(1, &self.addr);
         }
     }
@@ -128,8 +223,40 @@ impl Framebuffer {
         width: i32,
         height: i32,
     ) -> Framebuffer {
-        let color = make_color_attachment(color_attachment, width, height);
-        let ds = make_depth_stencil_attachment(&depth_stencil_attachment, width, height);
+        Framebuffer::from_desc(
+            &FramebufferDesc {
+                color: color_attachment,
+                depth_stencil: depth_stencil_attachment,
+                samples: 1,
+            },
+            width,
+            height,
+        )
+    }
+
+    pub fn from_desc(desc: &FramebufferDesc, width: i32, height: i32) -> Framebuffer {
+        let multisampled = desc.samples > 1;
+        let (color, ds) = if multisampled {
+            (
+                make_multisample_color_attachment(desc.color, desc.samples, width, height),
+                make_multisample_depth_stencil_attachment(
+                    &desc.depth_stencil,
+                    desc.samples,
+                    width,
+                    height,
+                ),
+            )
+        } else {
+            (
+                make_color_attachment(desc.color, width, height),
+                make_depth_stencil_attachment(&desc.depth_stencil, width, height),
+            )
+        };
+        let color_target = if multisampled {
+            gl::TEXTURE_2D_MULTISAMPLE
+        } else {
+            gl::TEXTURE_2D
+        };
 
         let mut addr = 0;
         unsafe {
@@ -138,7 +265,7 @@ impl Framebuffer {
             gl::FramebufferTexture2D(
                 gl::FRAMEBUFFER,
                 gl::COLOR_ATTACHMENT0,
-                gl::TEXTURE_2D,
+                color_target,
                 color,
                 0,
             );
@@ -154,12 +281,136 @@ impl Framebuffer {
         Framebuffer {
             addr,
             color_attachment: Some(color),
-            color_type: Some(color_attachment),
+            color_type: Some(desc.color),
             depth_stencil_attachment: Some(ds),
-            depth_stencil_type: Some(depth_stencil_attachment),
+            depth_stencil_type: Some(desc.depth_stencil),
+            extra_color_attachments: Vec::new(),
+            samples: desc.samples.max(1),
+            width,
+            height,
         }
     }
 
+    // Add a single-sampled color attachment after the existing ones. Every
+    // attachment is drawn to. Returns its index.
+    pub fn add_color_attachment(&mut self, attachment_type: ColorAttachment) -> usize {
+        let texture = make_color_attachment(attachment_type, self.width, self.height);
+        self.extra_color_attachments.push(texture);
+        let idx = self.extra_color_attachments.len();
+        unsafe {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, self.addr);
+            gl::FramebufferTexture2D(
+                gl::FRAMEBUFFER,
+                gl::COLOR_ATTACHMENT0 + idx as u32,
+                gl::TEXTURE_2D,
+                texture,
+                0,
+            );
+            self.draw_all_attachments();
+            gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+        }
+        idx
+    }
+
+    // The texture of color attachment `idx`, 0 being the main one.
+    pub fn color_texture(&self, idx: usize) -> Option<u32> {
+        match idx {
+            0 => self.color_attachment,
+            _ => self.extra_color_attachments.get(idx - 1).copied(),
+        }
+    }
+
+    fn attachment_count(&self) -> usize {
+        1 + self.extra_color_attachments.len()
+    }
+
+    // The framebuffer must be bound.
+    unsafe fn draw_all_attachments(&self) {
+        let buffers: Vec<u32> = (0..self.attachment_count())
+            .map(|idx| gl::COLOR_ATTACHMENT0 + idx as u32)
+            .collect();
+        gl::DrawBuffers(buffers.len() as i32, buffers.as_ptr());
+    }
+
+    pub fn is_multisampled(&self) -> bool {
+        self.samples > 1
+    }
+
+    // The texture target to use when binding the color attachment.
+    pub fn texture_target(&self) -> u32 {
+        if self.is_multisampled() {
+            gl::TEXTURE_2D_MULTISAMPLE
+        } else {
+            gl::TEXTURE_2D
+        }
+    }
+
+    fn blit(&self, addr: u32, width: i32, height: i32, filter: u32) {
+        unsafe {
+            gl::BindFramebuffer(gl::READ_FRAMEBUFFER, self.addr);
+            gl::BindFramebuffer(gl::DRAW_FRAMEBUFFER, addr);
+            gl::BlitFramebuffer(
+                0,
+                0,
+                self.width,
+                self.height,
+                0,
+                0,
+                width,
+                height,
+                gl::COLOR_BUFFER_BIT,
+                filter,
+            );
+            gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+        }
+    }
+
+    // Blit the color of this framebuffer into the framebuffer at `addr`.
+    // When this framebuffer is multisampled, this resolves it.
+    pub fn blit_into(&self, addr: u32, width: i32, height: i32) {
+        self.blit(addr, width, height, gl::NEAREST);
+    }
+
+    // Copy the color of this framebuffer into `target`, linearly filtering it
+    // if they are not the same size. Each attachment goes into the attachment
+    // of `target` with the same index.
+    pub fn scale_into(&self, target: &Framebuffer) {
+        if target.attachment_count() == 1 {
+            self.blit(target.addr, target.width, target.height, gl::LINEAR);
+            return;
+        }
+
+        let count = self.attachment_count().min(target.attachment_count());
+        unsafe {
+            gl::BindFramebuffer(gl::READ_FRAMEBUFFER, self.addr);
+            gl::BindFramebuffer(gl::DRAW_FRAMEBUFFER, target.addr);
+            for idx in 0..count {
+                let attachment = gl::COLOR_ATTACHMENT0 + idx as u32;
+                gl::ReadBuffer(attachment);
+                gl::DrawBuffers(1, &attachment);
+                gl::BlitFramebuffer(
+                    0,
+                    0,
+                    self.width,
+                    self.height,
+                    0,
+                    0,
+                    target.width,
+                    target.height,
+                    gl::COLOR_BUFFER_BIT,
+                    gl::LINEAR,
+                );
+            }
+            gl::ReadBuffer(gl::COLOR_ATTACHMENT0);
+            target.draw_all_attachments();
+            gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+        }
+    }
+
+    pub fn resolve_into(&self, target: &Framebuffer) {
+        self.blit_into(target.addr, target.width, target.height);
+    }
+
     pub fn new_ldr(width: i32, height: i32) -> Framebuffer {
         Framebuffer::new(
             ColorAttachment::RGBA_8B,
@@ -0,0 +1,190 @@
+use std::collections::HashMap;
+use std::fmt;
+
+#[derive(Debug)]
+struct GraphNode {
+    name: String,
+    inputs: Vec<String>,
+    output: String,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum GraphError {
+    // A pass reads a resource no pass writes.
+    MissingProducer { pass: String, resource: String },
+    // These passes depend on each other.
+    Cycle(Vec<String>),
+}
+
+impl fmt::Display for GraphError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GraphError::MissingProducer { pass, resource } => write!(
+                f,
+                "pass {} reads {} but no pass writes to it",
+                pass, resource
+            ),
+            GraphError::Cycle(passes) => write!(f, "cycle between passes {}", passes.join(", ")),
+        }
+    }
+}
+
+// Passes reading and writing named resources. The graph finds an order in
+// which every pass runs after the passes writing what it reads.
+#[derive(Debug, Default)]
+pub struct RenderGraph {
+    nodes: Vec<GraphNode>,
+    external: Vec<String>,
+}
+
+impl RenderGraph {
+    pub fn new() -> RenderGraph {
+        RenderGraph {
+            nodes: Vec::new(),
+            external: Vec::new(),
+        }
+    }
+
+    // A resource which is available before any pass runs, like the
+    // content of a history buffer from the last frame.
+    pub fn add_external(&mut self, resource: &str) {
+        self.external.push(String::from(resource));
+    }
+
+    pub fn add_pass(&mut self, name: &str, inputs: &[&str], output: &str) -> usize {
+        self.nodes.push(GraphNode {
+            name: String::from(name),
+            inputs: inputs.iter().map(|input| String::from(*input)).collect(),
+            output: String::from(output),
+        });
+        self.nodes.len() - 1
+    }
+
+    // Order the passes so that each one runs after every pass it depends on.
+    // Passes writing the same resource keep the order they were added in, and
+    // independent passes too.
+    pub fn order(&self) -> Result<Vec<usize>, GraphError> {
+        let mut producers: HashMap<&str, Vec<usize>> = HashMap::new();
+        for (idx, node) in self.nodes.iter().enumerate() {
+            producers.entry(node.output.as_str()).or_default().push(idx);
+        }
+
+        // dependencies[i] lists the passes which must run before pass i.
+        let mut dependencies: Vec<Vec<usize>> = vec![Vec::new(); self.nodes.len()];
+        for (idx, node) in self.nodes.iter().enumerate() {
+            for input in &node.inputs {
+                if self.external.contains(input) {
+                    continue;
+                }
+                match producers.get(input.as_str()) {
+                    Some(writers) => {
+                        for writer in writers {
+                            if *writer != idx {
+                                dependencies[idx].push(*writer);
+                            }
+                        }
+                    }
+                    None => {
+                        return Err(GraphError::MissingProducer {
+                            pass: node.name.clone(),
+                            resource: input.clone(),
+                        })
+                    }
+                }
+            }
+
+            let writers = &producers[node.output.as_str()];
+            for writer in writers.iter().take_while(|writer| **writer < idx) {
+                dependencies[idx].push(*writer);
+            }
+        }
+
+        let mut done = vec![false; self.nodes.len()];
+        let mut order: Vec<usize> = Vec::with_capacity(self.nodes.len());
+        while order.len() < self.nodes.len() {
+            let next = (0..self.nodes.len())
+                .find(|idx| !done[*idx] && dependencies[*idx].iter().all(|dep| done[*dep]));
+            match next {
+                Some(idx) => {
+                    done[idx] = true;
+                    order.push(idx);
+                }
+                None => {
+                    let stuck = (0..self.nodes.len())
+                        .filter(|idx| !done[*idx])
+                        .map(|idx| self.nodes[idx].name.clone())
+                        .collect();
+                    return Err(GraphError::Cycle(stuck));
+                }
+            }
+        }
+
+        Ok(order)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn orders_passes_after_their_inputs() {
+        let mut graph = RenderGraph::new();
+        graph.add_external("history.previous");
+        graph.add_pass("tonemap", &["history"], "screen");
+        graph.add_pass("trace", &["history.previous"], "history");
+        assert_eq!(graph.order(), Ok(vec![1, 0]));
+    }
+
+    #[test]
+    fn keeps_the_order_of_passes_writing_the_same_resource() {
+        let mut graph = RenderGraph::new();
+        graph.add_pass("sky", &[], "color");
+        graph.add_pass("objects", &[], "color");
+        graph.add_pass("show", &["color"], "screen");
+        assert_eq!(graph.order(), Ok(vec![0, 1, 2]));
+    }
+
+    #[test]
+    fn reports_missing_producers() {
+        let mut graph = RenderGraph::new();
+        graph.add_pass("blur", &["bloom"], "screen");
+        assert_eq!(
+            graph.order(),
+            Err(GraphError::MissingProducer {
+                pass: String::from("blur"),
+                resource: String::from("bloom"),
+            })
+        );
+    }
+
+    #[test]
+    fn external_resources_need_no_producer() {
+        let mut graph = RenderGraph::new();
+        graph.add_external("environment");
+        graph.add_pass("sky", &["environment"], "screen");
+        assert_eq!(graph.order(), Ok(vec![0]));
+    }
+
+    #[test]
+    fn reports_cycles() {
+        let mut graph = RenderGraph::new();
+        graph.add_pass("first", &["b"], "a");
+        graph.add_pass("second", &["a"], "b");
+        graph.add_pass("show", &[], "screen");
+        assert_eq!(
+            graph.order(),
+            Err(GraphError::Cycle(vec![
+                String::from("first"),
+                String::from("second"),
+            ]))
+        );
+    }
+
+    #[test]
+    fn a_pass_may_read_its_own_output() {
+        let mut graph = RenderGraph::new();
+        graph.add_pass("accumulate", &["history"], "history");
+        assert_eq!(graph.order(), Ok(vec![0]));
+    }
+}
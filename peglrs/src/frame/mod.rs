@@ -1,12 +1,93 @@
 pub mod fbo;
+pub mod graph;
+pub mod pass;
+pub mod pingpong;
+pub mod pool;
+pub mod tiles;
 
-use fbo::Framebuffer;
+use std::collections::HashMap;
 
-pub trait Frame {
-    fn attach_fbo(fbo: Framebuffer);
-    fn detach_fbo();
+use crate::mesh::Mesh;
+use crate::shaders::{Program, UniformValue};
+use crate::texture::envmap::EnvMap;
+use crate::texture::world::WorldTexture;
+use crate::texture::TextureManager;
+
+use fbo::{ColorAttachment, Framebuffer};
+use pingpong::PingPong;
+
+// Where a pass writes its output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    Screen,
+    Framebuffer(usize),
+    History,
+}
 
-    fn draw();
+// What happens to the accumulated image when the window is resized.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResizePolicy {
+    // Throw the accumulation away and start over at the new size.
+    Reset,
+    // Scale the accumulation to the new size and keep accumulating.
+    Rescale,
+    // Keep rendering at a fixed resolution and letterbox it in the window.
+    Fixed { width: i32, height: i32 },
+}
+
+// The render targets shared by every pass of a frame.
+#[derive(Debug)]
+pub struct Resources<'a> {
+    pub screen: u32,
+    pub screen_viewport: (i32, i32, i32, i32),
+    pub width: i32,
+    pub height: i32,
+    pub framebuffers: Vec<&'a Framebuffer>,
+    pub history: &'a PingPong,
+    // Uniforms set by the host, applied after the ones of each pass.
+    pub uniforms: &'a HashMap<String, UniformValue>,
+    pub textures: &'a TextureManager,
+    pub environment: Option<&'a EnvMap>,
+    pub world: &'a WorldTexture,
+}
+
+impl<'a> Resources<'a> {
+    pub fn target_addr(&self, target: Target) -> u32 {
+        match target {
+            Target::Screen => self.screen,
+            Target::Framebuffer(idx) => self.framebuffers[idx].addr,
+            Target::History => self.history.current().addr,
+        }
+    }
+
+    pub fn target_size(&self, target: Target) -> (i32, i32) {
+        match target {
+            Target::Screen => (self.screen_viewport.2, self.screen_viewport.3),
+            Target::Framebuffer(idx) => (
+                self.framebuffers[idx].width,
+                self.framebuffers[idx].height,
+            ),
+            Target::History => (self.history.width, self.history.height),
+        }
+    }
+
+    pub fn target_color_type(&self, target: Target) -> ColorAttachment {
+        match target {
+            Target::Screen => ColorAttachment::RGBA_8B,
+            Target::Framebuffer(idx) => self.framebuffers[idx]
+                .color_type
+                .unwrap_or(ColorAttachment::RGBA_8B),
+            Target::History => self.history.color_type,
+        }
+    }
+}
+
+pub trait Frame {
+    // Bind the output of the frame and set up the viewport, clear and blend state.
+    fn attach_fbo(&self, res: &Resources);
+    // Finish writing the output of the frame.
+    fn detach_fbo(&self, res: &Resources);
 
-    // Add scene + uniform managment
+    // Bind the inputs and uniforms of the frame on an already bound program and draw.
+    fn draw(&self, res: &Resources, program: &Program, mesh: &mut Mesh);
 }
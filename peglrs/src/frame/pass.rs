@@ -0,0 +1,214 @@
+use std::collections::HashMap;
+
+use cgmath::Vector2;
+use serde::Deserialize;
+
+use crate::mesh::Mesh;
+use crate::shaders::{Program, UniformValue};
+
+use super::fbo::{self, DepthStencilAttachment, Framebuffer, FramebufferDesc};
+use super::pingpong::MOMENTS;
+use super::{Frame, Resources, Target};
+
+// Where a pass input is read from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    Framebuffer(usize),
+    // What the history pass wrote during this frame.
+    History,
+    // What the history pass wrote during the last frame.
+    PreviousHistory,
+    // The second moments written along the history, for this frame and the
+    // last one.
+    Moments,
+    PreviousMoments,
+    // A texture of the scene's texture manager.
+    Texture(usize),
+    // The environment map and the CDFs to importance sample it. Nothing is
+    // bound when there is no environment.
+    Environment,
+    EnvMarginal,
+    EnvConditional,
+    // The objects and materials the path tracer renders.
+    World,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Blend {
+    Disabled,
+    Additive,
+    Alpha,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Input {
+    pub sampler: String,
+    pub source: Source,
+}
+
+// One full screen draw of a program into a target.
+#[derive(Debug)]
+pub struct RenderPass {
+    pub name: String,
+    pub program: u32,
+    pub inputs: Vec<Input>,
+    pub target: Target,
+    pub clear: bool,
+    pub blend: Blend,
+    pub samples: i32,
+    pub uniforms: HashMap<String, UniformValue>,
+    msaa: Option<Framebuffer>,
+}
+
+impl RenderPass {
+    pub fn new(name: &str, program: u32, target: Target) -> RenderPass {
+        RenderPass {
+            name: String::from(name),
+            program,
+            inputs: Vec::new(),
+            target,
+            clear: true,
+            blend: Blend::Disabled,
+            samples: 1,
+            uniforms: HashMap::new(),
+            msaa: None,
+        }
+    }
+
+    pub fn add_input(&mut self, sampler: &str, source: Source) {
+        self.inputs.push(Input {
+            sampler: String::from(sampler),
+            source,
+        });
+    }
+
+    // Like add_input, but replaces the input already using `sampler`.
+    pub fn set_input(&mut self, sampler: &str, source: Source) {
+        match self.inputs.iter_mut().find(|input| input.sampler == sampler) {
+            Some(input) => input.source = source,
+            None => self.add_input(sampler, source),
+        }
+    }
+
+    pub fn set_uniform(&mut self, name: &str, value: UniformValue) {
+        self.uniforms.insert(String::from(name), value);
+    }
+
+    // Passes with more than one sample draw into a multisampled framebuffer
+    // which is resolved into their target. It has to be rebuilt whenever
+    // the targets change size. Passes drawing to the screen use the window
+    // size, because a multisample resolve can't scale.
+    pub fn rebuild_msaa(&mut self, res: &Resources, window_size: Vector2<f32>) {
+        if self.samples <= 1 {
+            self.msaa = None;
+            return;
+        }
+        let max = fbo::max_samples();
+        if self.samples > max {
+            eprintln!(
+                "[ERR] Pass {} can't have {} samples, using {}",
+                self.name, self.samples, max
+            );
+            self.samples = max;
+        }
+
+        let desc = FramebufferDesc {
+            color: res.target_color_type(self.target),
+            depth_stencil: DepthStencilAttachment::DEPTH24_STENCIL8,
+            samples: self.samples,
+        };
+        let (width, height) = if self.target == Target::Screen {
+            (window_size.x as i32, window_size.y as i32)
+        } else {
+            res.target_size(self.target)
+        };
+        self.msaa = Some(Framebuffer::from_desc(&desc, width, height));
+    }
+
+    // The texture target and texture to bind for an input.
+    fn source_texture(res: &Resources, source: Source) -> (u32, u32) {
+        let (fb, attachment) = match source {
+            Source::Texture(idx) => {
+                let texture = res.textures.get(idx).map_or(0, |texture| texture.id);
+                return (gl::TEXTURE_2D, texture);
+            }
+            Source::Environment | Source::EnvMarginal | Source::EnvConditional => {
+                let texture = res.environment.map_or(0, |env| match source {
+                    Source::EnvMarginal => env.marginal,
+                    Source::EnvConditional => env.conditional,
+                    _ => env.texture,
+                });
+                return (gl::TEXTURE_2D, texture);
+            }
+            Source::World => return (gl::TEXTURE_2D, res.world.texture),
+            Source::Framebuffer(idx) => (res.framebuffers[idx], 0),
+            Source::History => (res.history.current(), 0),
+            Source::PreviousHistory => (res.history.previous(), 0),
+            Source::Moments => (res.history.current(), MOMENTS),
+            Source::PreviousMoments => (res.history.previous(), MOMENTS),
+        };
+        (fb.texture_target(), fb.color_texture(attachment).unwrap())
+    }
+}
+
+impl Frame for RenderPass {
+    fn attach_fbo(&self, res: &Resources) {
+        unsafe {
+            match &self.msaa {
+                Some(ms_fb) => gl::BindFramebuffer(gl::FRAMEBUFFER, ms_fb.addr),
+                None => gl::BindFramebuffer(gl::FRAMEBUFFER, res.target_addr(self.target)),
+            }
+
+            if self.target == Target::Screen {
+                let (x, y, w, h) = res.screen_viewport;
+                gl::Viewport(x, y, w, h);
+            } else {
+                let (w, h) = res.target_size(self.target);
+                gl::Viewport(0, 0, w, h);
+            }
+
+            if self.clear {
+                gl::ClearColor(0.0, 0.0, 0.0, 0.0);
+                gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT | gl::STENCIL_BUFFER_BIT);
+            } else {
+                gl::Clear(gl::DEPTH_BUFFER_BIT | gl::STENCIL_BUFFER_BIT);
+            }
+
+            match self.blend {
+                Blend::Disabled => gl::Disable(gl::BLEND),
+                Blend::Additive => {
+                    gl::Enable(gl::BLEND);
+                    gl::BlendFunc(gl::ONE, gl::ONE);
+                }
+                Blend::Alpha => {
+                    gl::Enable(gl::BLEND);
+                    gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+                }
+            }
+        }
+    }
+
+    fn detach_fbo(&self, res: &Resources) {
+        if let Some(ms_fb) = &self.msaa {
+            ms_fb.blit_into(res.target_addr(self.target), ms_fb.width, ms_fb.height);
+        }
+    }
+
+    fn draw(&self, res: &Resources, program: &Program, mesh: &mut Mesh) {
+        for (name, value) in self.uniforms.iter().chain(res.uniforms.iter()) {
+            program.set_uniform(name, value);
+        }
+
+        for (unit, input) in self.inputs.iter().enumerate() {
+            let (texture_target, texture) = RenderPass::source_texture(res, input.source);
+            unsafe {
+                gl::ActiveTexture(gl::TEXTURE0 + unit as u32);
+                gl::BindTexture(texture_target, texture);
+            }
+            program.set_uniform(&input.sampler, &UniformValue::Int(unit as i32));
+        }
+
+        mesh.draw();
+    }
+}
@@ -0,0 +1,82 @@
+use super::fbo::{ColorAttachment, DepthStencilAttachment, Framebuffer};
+
+// Index of the attachment holding the sum of the squared luminance of the
+// samples, next to their sum in the main one.
+pub const MOMENTS: usize = 1;
+
+// A pair of framebuffers used for feedback passes: a pass renders into the
+// current buffer while reading what it produced the frame before from the
+// previous one. Calling `swap` at the end of a frame flips the two.
+#[derive(Debug)]
+pub struct PingPong {
+    buffers: [Framebuffer; 2],
+    current: usize,
+    pub color_type: ColorAttachment,
+    pub width: i32,
+    pub height: i32,
+}
+
+impl PingPong {
+    pub fn new(color_type: ColorAttachment, width: i32, height: i32) -> PingPong {
+        let make_buffer = || {
+            let mut fb = Framebuffer::new(
+                color_type,
+                DepthStencilAttachment::DEPTH24_STENCIL8,
+                width,
+                height,
+            );
+            fb.add_color_attachment(ColorAttachment::RGBA_32F);
+            fb
+        };
+        let pingpong = PingPong {
+            buffers: [make_buffer(), make_buffer()],
+            current: 0,
+            color_type,
+            width,
+            height,
+        };
+        // Feedback passes read the previous buffer on their first frame,
+        // so it must not contain garbage.
+        pingpong.clear();
+        pingpong
+    }
+
+    pub fn new_xhdr(width: i32, height: i32) -> PingPong {
+        PingPong::new(ColorAttachment::RGBA_32F, width, height)
+    }
+
+    // The buffer written during this frame.
+    pub fn current(&self) -> &Framebuffer {
+        &self.buffers[self.current]
+    }
+
+    // The buffer written during the last frame.
+    pub fn previous(&self) -> &Framebuffer {
+        &self.buffers[1 - self.current]
+    }
+
+    pub fn swap(&mut self) {
+        self.current = 1 - self.current;
+    }
+
+    pub fn resize(&mut self, width: i32, height: i32) {
+        *self = PingPong::new(self.color_type, width, height);
+    }
+
+    // Resize while keeping what was accumulated so far, scaled to the new size.
+    pub fn rescale(&mut self, width: i32, height: i32) {
+        let old = std::mem::replace(self, PingPong::new(self.color_type, width, height));
+        old.previous().scale_into(self.previous());
+    }
+
+    pub fn clear(&self) {
+        unsafe {
+            for fb in &self.buffers {
+                gl::BindFramebuffer(gl::FRAMEBUFFER, fb.addr);
+                gl::ClearColor(0.0, 0.0, 0.0, 0.0);
+                gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT | gl::STENCIL_BUFFER_BIT);
+            }
+            gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+        }
+    }
+}
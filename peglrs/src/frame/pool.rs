@@ -0,0 +1,136 @@
+use super::fbo::{ColorAttachment, DepthStencilAttachment, Framebuffer, FramebufferDesc};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TargetSize {
+    // A fixed size in pixels.
+    Absolute(i32, i32),
+    // A fraction of the render size.
+    Relative(f32),
+}
+
+impl TargetSize {
+    pub fn resolve(&self, width: i32, height: i32) -> (i32, i32) {
+        match self {
+            TargetSize::Absolute(w, h) => (*w, *h),
+            TargetSize::Relative(scale) => (
+                ((width as f32 * scale) as i32).max(1),
+                ((height as f32 * scale) as i32).max(1),
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TargetDesc {
+    pub size: TargetSize,
+    pub framebuffer: FramebufferDesc,
+}
+
+impl TargetDesc {
+    pub fn new_xhdr(size: TargetSize) -> TargetDesc {
+        TargetDesc {
+            size,
+            framebuffer: FramebufferDesc {
+                color: ColorAttachment::RGBA_32F,
+                depth_stencil: DepthStencilAttachment::DEPTH24_STENCIL8,
+                samples: 1,
+            },
+        }
+    }
+}
+
+#[derive(Debug)]
+struct PoolEntry {
+    desc: TargetDesc,
+    framebuffer: Framebuffer,
+    // Index of the last pass using this framebuffer in the current plan.
+    last_use: Option<usize>,
+}
+
+// Hands out framebuffers by descriptor. Framebuffers are only valid during
+// the frame: two requests with the same descriptor whose pass ranges don't
+// overlap share the same framebuffer.
+#[derive(Debug)]
+pub struct TargetPool {
+    entries: Vec<PoolEntry>,
+    width: i32,
+    height: i32,
+}
+
+impl TargetPool {
+    pub fn new(width: i32, height: i32) -> TargetPool {
+        TargetPool {
+            entries: Vec::new(),
+            width,
+            height,
+        }
+    }
+
+    // Get a framebuffer matching `desc` used from pass `first_use` to pass
+    // `last_use` included. Requests must be made in increasing `first_use`
+    // order after a call to `release_all`.
+    pub fn acquire(&mut self, desc: &TargetDesc, first_use: usize, last_use: usize) -> usize {
+        let free = self.entries.iter().position(|entry| {
+            entry.desc == *desc && entry.last_use.is_none_or(|last| last < first_use)
+        });
+
+        match free {
+            Some(idx) => {
+                self.entries[idx].last_use = Some(last_use);
+                idx
+            }
+            None => {
+                let (width, height) = desc.size.resolve(self.width, self.height);
+                self.entries.push(PoolEntry {
+                    desc: *desc,
+                    framebuffer: Framebuffer::from_desc(&desc.framebuffer, width, height),
+                    last_use: Some(last_use),
+                });
+                self.entries.len() - 1
+            }
+        }
+    }
+
+    // Forget every pass range so the targets can be planned again. The
+    // framebuffers are kept around to be reused.
+    pub fn release_all(&mut self) {
+        for entry in &mut self.entries {
+            entry.last_use = None;
+        }
+    }
+
+    // Drop the framebuffers nobody acquired since the last `release_all`.
+    pub fn trim(&mut self) {
+        // Indices handed out are positions in `entries`, so only the unused
+        // tail can go without invalidating them.
+        while let Some(entry) = self.entries.last() {
+            if entry.last_use.is_some() {
+                break;
+            }
+            self.entries.pop();
+        }
+    }
+
+    pub fn get(&self, idx: usize) -> &Framebuffer {
+        &self.entries[idx].framebuffer
+    }
+
+    // The framebuffers behind a list of acquired indices.
+    pub fn map(&self, indices: &[usize]) -> Vec<&Framebuffer> {
+        indices.iter().map(|idx| self.get(*idx)).collect()
+    }
+
+    pub fn framebuffers(&self) -> impl Iterator<Item = &Framebuffer> {
+        self.entries.iter().map(|entry| &entry.framebuffer)
+    }
+
+    // Rebuild every framebuffer for a new render size.
+    pub fn resize(&mut self, width: i32, height: i32) {
+        self.width = width;
+        self.height = height;
+        for entry in &mut self.entries {
+            let (w, h) = entry.desc.size.resolve(width, height);
+            entry.framebuffer = Framebuffer::from_desc(&entry.desc.framebuffer, w, h);
+        }
+    }
+}
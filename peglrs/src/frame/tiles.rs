@@ -0,0 +1,111 @@
+// Splits the history passes into tiles rendered over several frames, so one
+// frame never takes long enough to freeze the desktop or hit a driver timeout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TileConfig {
+    pub width: i32,
+    pub height: i32,
+    // How many tiles to render every frame.
+    pub per_frame: usize,
+}
+
+// A tile in pixels, from the bottom left like the GL viewport.
+pub type TileRect = (i32, i32, i32, i32);
+
+#[derive(Debug)]
+pub struct Tiles {
+    pub config: TileConfig,
+    columns: usize,
+    rows: usize,
+    render_width: i32,
+    render_height: i32,
+    // Samples every tile got, row by row from the bottom.
+    samples: Vec<u32>,
+}
+
+impl Tiles {
+    pub fn new(config: TileConfig, width: i32, height: i32) -> Tiles {
+        let config = TileConfig {
+            width: config.width.max(1),
+            height: config.height.max(1),
+            per_frame: config.per_frame.max(1),
+        };
+        let mut tiles = Tiles {
+            config,
+            columns: 0,
+            rows: 0,
+            render_width: 0,
+            render_height: 0,
+            samples: Vec::new(),
+        };
+        tiles.resize(width, height);
+        tiles
+    }
+
+    // Lay the tiles over a new render size. Every tile starts from the
+    // fewest samples any tile had, which is what a rescaled image has.
+    pub fn resize(&mut self, width: i32, height: i32) {
+        if width == self.render_width && height == self.render_height {
+            return;
+        }
+
+        let min = self.min_samples();
+        self.render_width = width;
+        self.render_height = height;
+        self.columns = ((width + self.config.width - 1) / self.config.width).max(1) as usize;
+        self.rows = ((height + self.config.height - 1) / self.config.height).max(1) as usize;
+        self.samples = vec![min; self.columns * self.rows];
+    }
+
+    pub fn reset(&mut self) {
+        self.mark_all(0);
+    }
+
+    pub fn columns(&self) -> usize {
+        self.columns
+    }
+
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    pub fn samples(&self) -> &[u32] {
+        &self.samples
+    }
+
+    // The number of samples every pixel got at least.
+    pub fn min_samples(&self) -> u32 {
+        self.samples.iter().copied().min().unwrap_or(0)
+    }
+
+    pub fn rect(&self, idx: usize) -> TileRect {
+        let (column, row) = ((idx % self.columns) as i32, (idx / self.columns) as i32);
+        let (x, y) = (column * self.config.width, row * self.config.height);
+        (
+            x,
+            y,
+            self.config.width.min(self.render_width - x),
+            self.config.height.min(self.render_height - y),
+        )
+    }
+
+    // The tiles to render this frame: the ones with the fewest samples, so
+    // they all stay within one sample of each other.
+    pub fn next(&self) -> Vec<usize> {
+        let mut order: Vec<usize> = (0..self.samples.len()).collect();
+        order.sort_by_key(|idx| self.samples[*idx]);
+        order.truncate(self.config.per_frame);
+        order
+    }
+
+    pub fn mark_all(&mut self, samples: u32) {
+        for tile in &mut self.samples {
+            *tile = samples;
+        }
+    }
+
+    pub fn mark_rendered(&mut self, tiles: &[usize]) {
+        for idx in tiles {
+            self.samples[*idx] += 1;
+        }
+    }
+}
@@ -0,0 +1,153 @@
+use std::fmt;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use crate::noise::NoiseMap;
+
+// How often, in samples, the noise of the image is estimated. It needs the
+// accumulated image on the CPU, so it is not done every frame.
+pub const NOISE_CHECK_INTERVAL: u32 = 32;
+
+// When to consider an image done. Every goal set is checked and the first one
+// reached stops the rendering.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RenderGoal {
+    pub samples: Option<u32>,
+    pub time: Option<Duration>,
+    // Average relative standard error of the pixels.
+    pub noise: Option<f32>,
+    // Where to save the image once the goal is reached.
+    pub export: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GoalReason {
+    Samples,
+    Time,
+    Noise,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GoalReport {
+    pub reason: GoalReason,
+    pub samples: u32,
+    pub elapsed: Duration,
+    pub noise: Option<f32>,
+}
+
+impl fmt::Display for GoalReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let reason = match self.reason {
+            GoalReason::Samples => "sample count",
+            GoalReason::Time => "time budget",
+            GoalReason::Noise => "noise threshold",
+        };
+        write!(
+            f,
+            "{} reached: {} samples in {:.2}s",
+            reason,
+            self.samples,
+            self.elapsed.as_secs_f32()
+        )?;
+        if let Some(noise) = self.noise {
+            write!(f, ", noise {:.5}", noise)?;
+        }
+        Ok(())
+    }
+}
+
+pub type GoalCallback = Box<dyn FnMut(&GoalReport)>;
+
+// Where the scene is on its way to the goal. Progress starts over every time
+// the accumulation does.
+pub struct Progress {
+    pub goal: Option<RenderGoal>,
+    pub reached: Option<GoalReport>,
+    pub callback: Option<GoalCallback>,
+    started: Instant,
+    noise: Option<f32>,
+}
+
+impl fmt::Debug for Progress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Progress")
+            .field("goal", &self.goal)
+            .field("reached", &self.reached)
+            .field("started", &self.started)
+            .field("noise", &self.noise)
+            .finish()
+    }
+}
+
+impl Progress {
+    pub fn new() -> Progress {
+        Progress {
+            goal: None,
+            reached: None,
+            callback: None,
+            started: Instant::now(),
+            noise: None,
+        }
+    }
+
+    pub fn restart(&mut self) {
+        self.reached = None;
+        self.started = Instant::now();
+        self.noise = None;
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.reached.is_some()
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    // Last estimate of the noise, if a noise goal is set.
+    pub fn noise(&self) -> Option<f32> {
+        self.noise
+    }
+
+    pub fn wants_noise(&self, samples: u32) -> bool {
+        let wants = self.goal.as_ref().is_some_and(|goal| goal.noise.is_some());
+        wants && samples > 0 && samples.is_multiple_of(NOISE_CHECK_INTERVAL)
+    }
+
+    pub fn check_noise(&mut self, map: &NoiseMap) {
+        self.noise = Some(map.mean_error());
+    }
+
+    // Check the goal after `samples` samples. Returns the report the first
+    // time it is reached.
+    pub fn update(&mut self, samples: u32) -> Option<GoalReport> {
+        if self.reached.is_some() {
+            return None;
+        }
+        let goal = self.goal.as_ref()?;
+
+        let elapsed = self.started.elapsed();
+        let reason = if goal.samples.is_some_and(|target| samples >= target) {
+            Some(GoalReason::Samples)
+        } else if goal.time.is_some_and(|budget| elapsed >= budget) {
+            Some(GoalReason::Time)
+        } else if goal
+            .noise
+            .zip(self.noise)
+            .is_some_and(|(threshold, noise)| noise <= threshold)
+        {
+            Some(GoalReason::Noise)
+        } else {
+            None
+        };
+
+        let report = GoalReport {
+            reason: reason?,
+            samples,
+            elapsed,
+            noise: self.noise,
+        };
+        self.reached = Some(report.clone());
+        Some(report)
+    }
+}
@@ -2,19 +2,60 @@ extern crate cgmath;
 extern crate gl;
 extern crate gl_loader;
 
+pub mod capi;
+pub mod export;
+
 mod camera;
 mod frame;
+mod goal;
 mod mesh;
+mod noise;
+mod osc;
+mod pipeline;
+mod profiler;
+#[cfg(feature = "remote")]
+mod remote;
 mod scene;
 mod shaders;
+mod texture;
+mod timeline;
 mod utils;
+mod world;
 
-use std::{collections::HashMap, path::Path};
+use std::collections::HashMap;
+use std::f32::consts::FRAC_PI_2;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::time::Duration;
 use std::{io::empty, sync::Arc};
 
 use camera::Camera;
-use frame::fbo::Framebuffer;
+use frame::pass::{RenderPass, Source};
+use frame::pingpong::PingPong;
+use frame::pool::{TargetDesc, TargetPool};
+use goal::Progress;
+use pipeline::Pipeline;
+use osc::OscListener;
+use profiler::Profiler;
+#[cfg(feature = "remote")]
+use remote::RemoteServer;
+use texture::envmap::EnvMap;
+use texture::world::WorldTexture;
+use texture::TextureManager;
+use frame::{Frame, Resources, Target};
+
+pub use frame::tiles::{TileConfig, TileRect, Tiles};
+pub use frame::ResizePolicy;
+pub use goal::{GoalReason, GoalReport, RenderGoal};
+pub use noise::NoiseMap;
+pub use osc::{OscConfig, OscMapping};
+pub use profiler::PassTiming;
+pub use shaders::UniformValue;
+pub use timeline::{Easing, Interpolation, Keyframe, Timeline, TimelineState, Track};
+pub use timeline::recording::{CameraSample, Recording};
+pub use world::{Axis, Cuboid, Material, MaterialType, Rect, Sphere, World};
 use shaders::shader_loader::ShaderManager;
+use utils::FileWatcher;
 use shaders::{Program, Shader};
 
 use cgmath::prelude::*;
@@ -25,55 +66,258 @@ use std::ffi::CStr;
 #[derive(Debug)]
 pub struct Scene {
     pub shader_manager: ShaderManager,
-    pub programs: Vec<u32>,
-    pub framebuffers: Vec<Framebuffer>,
-    pub binding: HashMap<u32, Option<usize>>,
+    pub pipeline_watcher: FileWatcher,
+    pub passes: Vec<RenderPass>,
+    pub targets: Vec<TargetDesc>,
+    pub target_map: Vec<usize>,
+    pub pool: TargetPool,
+    pub history: PingPong,
     pub mesh: mesh::Mesh,
     pub size: Vector2<f32>,
+    pub window_size: Vector2<f32>,
+    pub resize_policy: ResizePolicy,
     pub frame_nb: u32,
     pub eye: Vector3<f32>,
     pub target: Vector3<f32>,
     pub up: Vector3<f32>,
     pub focus_pos: Vector2<f32>,
     pub aperture: f32,
+    // Uniforms set by the host, given to every pass.
+    pub uniforms: HashMap<String, UniformValue>,
+    pub progress: Progress,
+    pub tiles: Option<Tiles>,
+    // Seed of the first sample, the next ones count from it. Without one, the
+    // seed is the time, which changes every frame but can't be reproduced.
+    pub seed: Option<u32>,
+    pub profiler: Profiler,
+    pub textures: TextureManager,
+    pub environment: Option<EnvMap>,
+    // What the path tracer renders.
+    pub world: WorldTexture,
+    pub timeline: Option<Timeline>,
+    #[cfg(feature = "remote")]
+    pub remote: Option<RemoteServer>,
+    pub osc: Option<OscListener>,
 }
 
-static mut m_scene: Option<Scene> = None;
+impl Scene {
+    // Set how many samples a pass renders with. More than the driver
+    // supports are clamped.
+    pub fn set_samples(&mut self, pass: usize, samples: i32) -> Option<()> {
+        if pass >= self.passes.len() {
+            eprintln!("[ERR] No pass {}", pass);
+            return None;
+        }
+        if samples < 1 {
+            eprintln!("[ERR] A pass needs at least 1 sample, not {}", samples);
+            return None;
+        }
 
-#[no_mangle]
-pub fn resize_window(width: f64, height: f64, dpi_ratio: f64) {
-    let real_width = dpi_ratio * width;
-    let real_height = dpi_ratio * height;
+        self.passes[pass].samples = samples;
+        self.rebuild_msaa(pass);
+        Some(())
+    }
 
-    unsafe {
-        gl::Viewport(0, 0, real_width as i32, real_height as i32);
+    // Replace every pass and target of the scene. The accumulated image is kept.
+    pub fn set_pipeline(&mut self, pipeline: Pipeline) {
+        for pass in &self.passes {
+            self.shader_manager.rm_program(pass.program);
+        }
+        self.passes = pipeline.passes;
+        self.targets = pipeline.targets;
+        self.evict_textures();
+        self.plan_targets();
+        for pass in 0..self.passes.len() {
+            self.rebuild_msaa(pass);
+        }
+    }
 
-        if let Some(scene) = &mut m_scene {
-            scene.size = Vector2 {
-                x: real_width as f32,
-                y: real_height as f32,
-            };
+    // Rebuild the pipeline if its file changed. Returns true if it was.
+    fn handle_pipeline_reload(&mut self) -> bool {
+        if !self.pipeline_watcher.changed() {
+            return false;
+        }
+
+        let path = self.pipeline_watcher.path.clone();
+        println!("Reloading pipeline: {}", path.display());
+        let pipeline = pipeline::load_file(&path)
+            .and_then(|file| Pipeline::build(&file, &mut self.shader_manager, &mut self.textures));
+        match pipeline {
+            Some(pipeline) => {
+                self.set_pipeline(pipeline);
+                true
+            }
+            None => {
+                eprintln!("[ERR] Couldn't reload pipeline {}", path.display());
+                // The broken pipeline may have loaded textures nothing uses.
+                self.evict_textures();
+                false
+            }
+        }
+    }
+
+    // Free the textures no pass reads anymore.
+    fn evict_textures(&mut self) {
+        let used: Vec<usize> = self
+            .passes
+            .iter()
+            .flat_map(|pass| &pass.inputs)
+            .filter_map(|input| match input.source {
+                Source::Texture(idx) => Some(idx),
+                _ => None,
+            })
+            .collect();
+        self.textures.evict_unused(&used);
+    }
+
+    fn rebuild_msaa(&mut self, pass: usize) {
+        let res = Resources {
+            screen: 0,
+            screen_viewport: self.screen_viewport(),
+            width: self.size.x as i32,
+            height: self.size.y as i32,
+            framebuffers: self.pool.map(&self.target_map),
+            history: &self.history,
+            uniforms: &self.uniforms,
+            textures: &self.textures,
+            environment: self.environment.as_ref(),
+            world: &self.world,
+        };
+        self.passes[pass].rebuild_msaa(&res, self.window_size);
+    }
 
-            let mut new_fbs: Vec<Framebuffer> = Vec::new();
-            for _ in &scene.framebuffers {
-                new_fbs.push(Framebuffer::new_xhdr(real_width as i32, real_height as i32));
-                new_fbs.push(Framebuffer::new_xhdr(real_width as i32, real_height as i32));
+    // Assign a framebuffer from the pool to every target. Targets whose
+    // passes don't overlap can share the same framebuffer.
+    pub fn plan_targets(&mut self) {
+        let mut ranges: Vec<Option<(usize, usize)>> = vec![None; self.targets.len()];
+        for (p, pass) in self.passes.iter().enumerate() {
+            let mut used: Vec<usize> = Vec::new();
+            if let Target::Framebuffer(idx) = pass.target {
+                used.push(idx);
+            }
+            for input in &pass.inputs {
+                if let Source::Framebuffer(idx) = input.source {
+                    used.push(idx);
+                }
+            }
+            for idx in used {
+                ranges[idx] = match ranges[idx] {
+                    Some((first, _)) => Some((first, p)),
+                    None => Some((p, p)),
+                };
+            }
+        }
+
+        // Targets no pass uses still get a framebuffer, after all the others.
+        let unused = (self.passes.len(), self.passes.len());
+        let mut order: Vec<usize> = (0..self.targets.len()).collect();
+        order.sort_by_key(|idx| ranges[*idx].unwrap_or(unused).0);
+
+        self.pool.release_all();
+        self.target_map = vec![0; self.targets.len()];
+        for idx in order {
+            let (first, last) = ranges[idx].unwrap_or(unused);
+            self.target_map[idx] = self.pool.acquire(&self.targets[idx], first, last);
+        }
+        self.pool.trim();
+    }
+
+    // Rebuild every render target at the given size. When `keep` is set,
+    // the accumulated image is scaled into the new targets instead of being
+    // thrown away.
+    fn resize_targets(&mut self, width: i32, height: i32, keep: bool) {
+        self.size = Vector2::new(width as f32, height as f32);
+
+        self.pool.resize(width, height);
+
+        if keep {
+            self.history.rescale(width, height);
+        } else {
+            self.history.resize(width, height);
+            self.frame_nb = 0;
+            if let Some(tiles) = self.tiles.as_mut() {
+                tiles.reset();
+            }
+        }
+    }
+
+    // The part of the window the final image is drawn in. It covers the whole
+    // window unless we render at a fixed resolution, in which case the image is
+    // letterboxed.
+    fn screen_viewport(&self) -> (i32, i32, i32, i32) {
+        let (win_w, win_h) = (self.window_size.x, self.window_size.y);
+        match self.resize_policy {
+            ResizePolicy::Fixed { .. } => {
+                let scale = f32::min(win_w / self.size.x, win_h / self.size.y);
+                let (w, h) = (self.size.x * scale, self.size.y * scale);
+                (
+                    ((win_w - w) / 2.0) as i32,
+                    ((win_h - h) / 2.0) as i32,
+                    w as i32,
+                    h as i32,
+                )
+            }
+            _ => (0, 0, win_w as i32, win_h as i32),
+        }
+    }
+
+    fn apply_resize_policy(&mut self) {
+        match self.resize_policy {
+            ResizePolicy::Reset => {
+                self.resize_targets(self.window_size.x as i32, self.window_size.y as i32, false)
+            }
+            ResizePolicy::Rescale => {
+                self.resize_targets(self.window_size.x as i32, self.window_size.y as i32, true)
+            }
+            ResizePolicy::Fixed { width, height } => {
+                if self.size.x as i32 != width || self.size.y as i32 != height {
+                    self.resize_targets(width, height, true);
+                }
             }
-            drop(&scene.framebuffers);
-            scene.framebuffers = new_fbs;
+        }
 
-            scene.frame_nb = 0;
+        for pass in 0..self.passes.len() {
+            self.rebuild_msaa(pass);
         }
     }
 }
 
-#[no_mangle]
+const DEFAULT_PIPELINE: &str = "data/pipelines/default.toml";
+
+pub fn resize_window(scene: &mut Scene, width: f64, height: f64, dpi_ratio: f64) {
+    let real_width = dpi_ratio * width;
+    let real_height = dpi_ratio * height;
+
+    unsafe {
+        gl::Viewport(0, 0, real_width as i32, real_height as i32);
+    }
+
+    scene.window_size = Vector2 {
+        x: real_width as f32,
+        y: real_height as f32,
+    };
+    scene.apply_resize_policy();
+}
+
+pub fn set_resize_policy(scene: &mut Scene, policy: ResizePolicy) {
+    scene.resize_policy = policy;
+    scene.apply_resize_policy();
+}
+
 pub fn load_gl_symbol() {
     gl_loader::init_gl();
     gl::load_with(|symbol| gl_loader::get_proc_address(symbol) as *const _);
 }
 
-#[no_mangle]
+// Load the GL functions with the loader of the context, for contexts which
+// don't come from libGL like EGL ones.
+pub fn load_gl_with<F>(loader: F)
+where
+    F: FnMut(&'static str) -> *const std::os::raw::c_void,
+{
+    gl::load_with(loader);
+}
+
 pub fn print_gl_info() {
     unsafe {
         let gl_version = gl::GetString(gl::VERSION);
@@ -89,7 +333,6 @@ pub fn print_gl_info() {
     }
 }
 
-#[no_mangle]
 pub fn init_gl(width: f64, height: f64, dpi_ratio: f64) {
     let true_width = width * dpi_ratio;
     let true_height = height * dpi_ratio;
@@ -106,184 +349,572 @@ pub fn init_gl(width: f64, height: f64, dpi_ratio: f64) {
     }
 }
 
-#[no_mangle]
-pub fn init_scene(width: f64, height: f64, dpi_ratio: f64) {
+pub fn init_scene(width: f64, height: f64, dpi_ratio: f64) -> Option<Scene> {
+    init_scene_from_file(DEFAULT_PIPELINE, width, height, dpi_ratio)
+}
+
+// Every scene owns its own GL objects, so the GL context it was created with
+// must be current when calling any function taking it.
+pub fn init_scene_from_file(
+    path: &str,
+    width: f64,
+    height: f64,
+    dpi_ratio: f64,
+) -> Option<Scene> {
     let true_width = width * dpi_ratio;
     let true_height = height * dpi_ratio;
 
-    let mut programs: Vec<u32> = Vec::new();
-    let mut framebuffers: Vec<Framebuffer> = Vec::new();
-    let mut binding: HashMap<u32, Option<usize>> = HashMap::new();
-
-    framebuffers.push(Framebuffer::new_xhdr(true_width as i32, true_height as i32));
-    framebuffers.push(Framebuffer::new_xhdr(true_width as i32, true_height as i32));
-
+    let pipeline_path = Path::new(path);
     let mut shader_manager = ShaderManager::new();
-    let path_tracer = shader_manager.load_program(&vec![
-        Path::new("data/shaders/post/post.vs"),
-        Path::new("data/shaders/post/post.fs"),
-    ]);
-    programs.push(path_tracer);
-    binding.insert(path_tracer, Some(0));
-
-    let grading_program = shader_manager.load_program(&vec![
-        Path::new("data/shaders/grading/grading.vs"),
-        Path::new("data/shaders/grading/grading.fs"),
-    ]);
-    programs.push(grading_program);
-    binding.insert(grading_program, Some(1));
-
-    let denoiser = shader_manager.load_program(&vec![
-        Path::new("data/shaders/tex/tex.vs"),
-        Path::new("data/shaders/tex/tex.fs"),
-    ]);
-    programs.push(denoiser);
-    binding.insert(denoiser, None);
+    let mut textures = TextureManager::new();
+    let world = WorldTexture::new(World::default())?;
+    let pipeline = pipeline::load_file(pipeline_path)
+        .and_then(|file| Pipeline::build(&file, &mut shader_manager, &mut textures));
+    let pipeline = match pipeline {
+        Some(pipeline) => pipeline,
+        None => {
+            eprintln!("[ERR] Couldn't load pipeline {}", pipeline_path.display());
+            return None;
+        }
+    };
+
+    let history = PingPong::new_xhdr(true_width as i32, true_height as i32);
 
     let mut fs_plane = mesh::Mesh::fs_quad();
     fs_plane.ready_up();
 
-    unsafe {
-        m_scene = Some(Scene {
-            shader_manager,
-            programs,
-            framebuffers,
-            binding,
-            mesh: fs_plane,
-            size: Vector2 {
-                x: true_width as f32,
-                y: true_height as f32,
-            },
-            frame_nb: 0,
-            eye: Vector3::new(0.0, 0.0, 0.0),
-            target: Vector3::new(0.0, 0.0, 0.0),
-            up: Vector3::new(0.0, 1.0, 0.0),
-            focus_pos: Vector2::new(0.0, 0.0),
-            aperture: 0.0,
-        })
+    let mut scene = Scene {
+        shader_manager,
+        pipeline_watcher: FileWatcher::new(pipeline_path),
+        passes: Vec::new(),
+        targets: Vec::new(),
+        target_map: Vec::new(),
+        pool: TargetPool::new(true_width as i32, true_height as i32),
+        history,
+        mesh: fs_plane,
+        size: Vector2 {
+            x: true_width as f32,
+            y: true_height as f32,
+        },
+        window_size: Vector2 {
+            x: true_width as f32,
+            y: true_height as f32,
+        },
+        resize_policy: ResizePolicy::Reset,
+        frame_nb: 0,
+        eye: Vector3::new(0.0, 0.0, 0.0),
+        target: Vector3::new(0.0, 0.0, 0.0),
+        up: Vector3::new(0.0, 1.0, 0.0),
+        focus_pos: Vector2::new(0.0, 0.0),
+        aperture: 0.0,
+        uniforms: HashMap::new(),
+        progress: Progress::new(),
+        tiles: None,
+        seed: None,
+        profiler: Profiler::new(),
+        textures,
+        environment: None,
+        world,
+        timeline: None,
+        #[cfg(feature = "remote")]
+        remote: None,
+        osc: None,
+    };
+    scene.set_pipeline(pipeline);
+
+    Some(scene)
+}
+
+// Turn the camera around its eye, the same way dragging the mouse does in
+// glutin_backend.
+pub fn handle_mouse(scene: &mut Scene, dx: f32, dy: f32, speed: f32) {
+    let direction = scene.target - scene.eye;
+    if direction.magnitude2() == 0.0 {
+        return;
     }
+
+    let direction = direction.normalize();
+    let hangle = direction.x.atan2(direction.z) + dx * speed;
+    let vangle = (direction.y.asin() + dy * speed).clamp(-FRAC_PI_2 + 0.01, FRAC_PI_2 - 0.01);
+
+    let direction = Vector3::new(
+        vangle.cos() * hangle.sin(),
+        vangle.sin(),
+        vangle.cos() * hangle.cos(),
+    );
+    let right = Vector3::new((hangle - FRAC_PI_2).sin(), 0.0, (hangle - FRAC_PI_2).cos());
+    scene.target = scene.eye + direction;
+    scene.up = right.cross(direction);
+}
+
+// Set a uniform on every pass of the scene. It overrides the value given in
+// the pipeline file and survives pipeline reloads.
+pub fn set_uniform(scene: &mut Scene, name: &str, value: UniformValue) {
+    scene.uniforms.insert(String::from(name), value);
+}
+
+pub fn remove_uniform(scene: &mut Scene, name: &str) {
+    scene.uniforms.remove(name);
+}
+
+pub fn set_pass_samples(scene: &mut Scene, pass: usize, samples: i32) -> Option<()> {
+    scene.set_samples(pass, samples)
+}
+
+// Light the scene with an equirectangular HDR or EXR image, or go back to
+// the procedural sky with None. Its rotation and intensity are the
+// `env_rotation` and `env_intensity` uniforms. Call `reset` to start the
+// accumulation over with it.
+pub fn set_environment(scene: &mut Scene, path: Option<&Path>) -> Option<()> {
+    scene.environment = match path {
+        Some(path) => {
+            println!("[NFO] Loading environment {}", path.display());
+            Some(EnvMap::load(path)?)
+        }
+        None => None,
+    };
+    Some(())
+}
+
+// Render the objects of a scene file, reloaded whenever it changes, or the
+// built-in scene with None. Call `reset` to start the accumulation over with
+// it.
+pub fn load_world(scene: &mut Scene, path: Option<&Path>) -> Option<()> {
+    scene.world = match path {
+        Some(path) => {
+            println!("[NFO] Loading scene {}", path.display());
+            WorldTexture::load(path)?
+        }
+        None => WorldTexture::new(World::default())?,
+    };
+    Some(())
 }
 
-#[no_mangle]
-pub fn handle_mouse(dx: f32, dy: f32, speed: f32) {}
+// Render `world` instead of the scene file. Call `reset` afterwards.
+pub fn set_world(scene: &mut Scene, world: World) -> Option<()> {
+    scene.world = WorldTexture::new(world)?;
+    Some(())
+}
+
+// Bind an image to a sampler of the pass named `pass`, replacing what the
+// sampler was bound to. sRGB images hold color, others data like normals.
+// Lasts until the pipeline is reloaded.
+pub fn set_pass_texture(
+    scene: &mut Scene,
+    pass: &str,
+    sampler: &str,
+    path: &Path,
+    srgb: bool,
+) -> Option<()> {
+    let idx = match scene.passes.iter().position(|p| p.name == pass) {
+        Some(idx) => idx,
+        None => {
+            eprintln!("[ERR] No pass named {}", pass);
+            return None;
+        }
+    };
+    let texture = scene.textures.get_or_load(path, srgb)?;
+    scene.passes[idx].set_input(sampler, Source::Texture(texture));
+    scene.evict_textures();
+    Some(())
+}
 
-#[no_mangle]
 pub fn update_camera(
+    scene: &mut Scene,
     eye: Vector3<f32>,
     target: Vector3<f32>,
     up: Vector3<f32>,
     focus_pos: Vector2<f32>,
     aperture: f32,
 ) {
-    unsafe {
-        if let Some(scene) = &mut m_scene {
-            scene.eye = eye;
-            scene.target = target;
-            scene.up = up;
-            scene.focus_pos = focus_pos;
-            scene.aperture = aperture;
+    scene.eye = eye;
+    scene.target = target;
+    scene.up = up;
+    scene.focus_pos = focus_pos;
+    scene.aperture = aperture;
+}
+
+// Keyframes driving the camera and uniforms, played with `apply_timeline`.
+pub fn set_timeline(scene: &mut Scene, timeline: Option<Timeline>) {
+    scene.timeline = timeline;
+}
+
+// Move the camera and set the uniforms to where the timeline is at `time`.
+// Values without a track are left alone. Returns true if anything changed,
+// in which case the host should `reset` the accumulation.
+pub fn apply_timeline(scene: &mut Scene, time: f32) -> bool {
+    let state = match &scene.timeline {
+        Some(timeline) => timeline.sample(time),
+        None => return false,
+    };
+
+    let eye = state.eye.unwrap_or(scene.eye);
+    let target = state.target.unwrap_or(scene.target);
+    let up = state.up.unwrap_or(scene.up);
+    let focus_pos = state.focus_pos.unwrap_or(scene.focus_pos);
+    let aperture = state.aperture.unwrap_or(scene.aperture);
+    let mut changed = eye != scene.eye
+        || target != scene.target
+        || up != scene.up
+        || focus_pos != scene.focus_pos
+        || aperture != scene.aperture;
+    update_camera(scene, eye, target, up, focus_pos, aperture);
+
+    for (name, value) in state.uniforms {
+        let value = value.matching(scene.uniforms.get(&name));
+        if scene.uniforms.get(&name) != Some(&value) {
+            changed = true;
+            set_uniform(scene, &name, value);
         }
     }
+    changed
 }
 
-#[no_mangle]
-pub fn quit() {
-    unsafe {
-        m_scene = None;
+// Listen for JSON-RPC commands on a loopback address like "127.0.0.1:9000",
+// or stop with None. Returns the address listened on, port 0 picking a free
+// one. Clients can only export images into `export_dir`, if given. The
+// commands are run by `handle_remote`.
+#[cfg(feature = "remote")]
+pub fn set_remote(
+    scene: &mut Scene,
+    addr: Option<&str>,
+    export_dir: Option<&Path>,
+) -> Option<SocketAddr> {
+    scene.remote = None;
+    let server = RemoteServer::bind(addr?, export_dir)?;
+    let addr = server.addr();
+    scene.remote = Some(server);
+    Some(addr)
+}
+
+// Run the remote commands received since the last call, on the thread of the
+// GL context. Returns true if one of them moved the camera, for hosts that
+// keep their own copy of it.
+#[cfg(feature = "remote")]
+pub fn handle_remote(scene: &mut Scene, fbo: u32) -> bool {
+    let commands = match &scene.remote {
+        Some(server) => server.commands(),
+        None => return false,
+    };
+    let mut changed = false;
+    for command in commands {
+        let result =
+            remote::commands::execute(scene, fbo, &command.method, &command.params, &mut changed);
+        command.reply(result);
     }
+    changed
 }
 
-#[no_mangle]
-pub fn reset(fbo: u32) {
-    unsafe {
-        if let Some(scene) = &mut m_scene {
-            scene.frame_nb = 0;
-            for fb in &scene.framebuffers {
-                gl::BindFramebuffer(gl::FRAMEBUFFER, fb.addr);
-                gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT | gl::STENCIL_BUFFER_BIT);
-                gl::ClearColor(0.0, 0.0, 0.0, 0.0);
-            }
+// Listen for OSC messages on a UDP address, like "0.0.0.0:9001", or stop
+// with None. `config` scales and smooths the values of every address.
+// Returns the address listened on, port 0 picking a free one. The messages
+// are applied by `handle_osc`.
+pub fn set_osc(scene: &mut Scene, addr: Option<&str>, config: OscConfig) -> Option<SocketAddr> {
+    scene.osc = None;
+    let listener = OscListener::bind(addr?, config)?;
+    let addr = listener.addr()?;
+    scene.osc = Some(listener);
+    Some(addr)
+}
+
+// Apply the OSC messages received since the last call and move the smoothed
+// values along, starting the accumulation over if anything changed. Call it
+// every frame. Returns true if the camera moved, for hosts that keep their
+// own copy of it.
+pub fn handle_osc(scene: &mut Scene, fbo: u32) -> bool {
+    let mut osc = match scene.osc.take() {
+        Some(osc) => osc,
+        None => return false,
+    };
+    let changes = osc.update(scene);
+    scene.osc = Some(osc);
+    if changes.scene {
+        reset(scene, fbo);
+    }
+    changes.camera
+}
+
+pub fn quit(scene: Scene) {
+    drop(scene);
+}
 
-            gl::BindFramebuffer(gl::FRAMEBUFFER, fbo);
+pub fn reset(scene: &mut Scene, fbo: u32) {
+    scene.frame_nb = 0;
+    scene.progress.restart();
+    if let Some(tiles) = scene.tiles.as_mut() {
+        tiles.reset();
+    }
+    scene.history.clear();
+    unsafe {
+        for fb in scene.pool.framebuffers() {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, fb.addr);
             gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT | gl::STENCIL_BUFFER_BIT);
             gl::ClearColor(0.0, 0.0, 0.0, 0.0);
         }
+
+        gl::BindFramebuffer(gl::FRAMEBUFFER, fbo);
+        gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT | gl::STENCIL_BUFFER_BIT);
+        gl::ClearColor(0.0, 0.0, 0.0, 0.0);
     }
 }
 
-#[no_mangle]
-pub fn display_loop(time: f64, fbo: u32, reset_on_reload: bool) {
-    unsafe {
-        if let Some(scene) = &mut m_scene {
-            let should_clear = scene.shader_manager.handle_reload();
-            if should_clear && reset_on_reload {
-                reset(fbo);
+// Render the passes writing the history in tiles spread over several
+// frames, or all at once with None.
+pub fn set_tiling(scene: &mut Scene, config: Option<TileConfig>) {
+    let (width, height) = (scene.size.x as i32, scene.size.y as i32);
+    if let Some(tiles) = scene.tiles.take() {
+        // Back to full frames, some pixels may have fewer samples than
+        // frames were rendered.
+        scene.frame_nb = tiles.min_samples();
+    }
+    scene.tiles = config.map(|config| {
+        let mut tiles = Tiles::new(config, width, height);
+        // Switching to tiles doesn't lose what was rendered so far.
+        tiles.mark_all(scene.frame_nb);
+        tiles
+    });
+}
+
+// How many samples every pixel got at least. With tiles, a frame only
+// gives samples to some of the pixels.
+pub fn sample_count(scene: &Scene) -> u32 {
+    match &scene.tiles {
+        Some(tiles) => tiles.min_samples(),
+        None => scene.frame_nb,
+    }
+}
+
+// How converged every pixel of the accumulated image is.
+pub fn noise_map(scene: &Scene) -> NoiseMap {
+    NoiseMap::read(&scene.history)
+}
+
+// Stop accumulating once `goal` is reached, or never with None. Setting a
+// goal doesn't throw away what was rendered so far.
+pub fn set_render_goal(scene: &mut Scene, goal: Option<RenderGoal>) {
+    scene.progress.goal = goal;
+    scene.progress.reached = None;
+}
+
+// Called once every time the goal is reached, after the auto-export.
+pub fn on_goal_reached<F>(scene: &mut Scene, callback: F)
+where
+    F: FnMut(&GoalReport) + 'static,
+{
+    scene.progress.callback = Some(Box::new(callback));
+}
+
+pub fn goal_reached(scene: &Scene) -> Option<&GoalReport> {
+    scene.progress.reached.as_ref()
+}
+
+// True once the goal is reached: `display_loop` then only redraws the last
+// image, and hosts can stop calling it until something changes.
+pub fn is_idle(scene: &Scene) -> bool {
+    scene.progress.is_done()
+}
+
+// Measure the GPU time of every pass. With a `log_interval`, the timings
+// are printed that often.
+pub fn set_profiling(scene: &mut Scene, enabled: bool, log_interval: Option<Duration>) {
+    scene.profiler.set_enabled(enabled, log_interval);
+}
+
+// GPU time of every pass over the last frames, once profiling is on. The
+// results come a couple of frames late.
+pub fn pass_timings(scene: &Scene) -> Vec<PassTiming> {
+    scene.profiler.timings()
+}
+
+// Make the samples reproducible: sample n of an accumulation gets the seed
+// `seed + n`, whatever the time.
+pub fn set_seed(scene: &mut Scene, seed: Option<u32>) {
+    scene.seed = seed;
+}
+
+// The `seed` uniform of the current sample, hashed with the pixel by the
+// shader so every bit of it counts.
+fn sample_seed(scene: &Scene, time: f64) -> u32 {
+    match scene.seed {
+        Some(seed) => seed.wrapping_add(scene.frame_nb),
+        None => (time as f32).to_bits(),
+    }
+}
+
+// Run the passes of the scene. With `present_only`, the passes writing the
+// history are skipped and the others show what it accumulated so far. When
+// `tiles` isn't empty, the passes writing the history only render these
+// tiles and keep the rest of the image as it was.
+fn draw_passes(scene: &mut Scene, time: f64, fbo: u32, present_only: bool, tiles: &[TileRect]) {
+    let seed = sample_seed(scene, time);
+    let res = Resources {
+        screen: fbo,
+        screen_viewport: scene.screen_viewport(),
+        width: scene.size.x as i32,
+        height: scene.size.y as i32,
+        framebuffers: scene.pool.map(&scene.target_map),
+        history: &scene.history,
+        uniforms: &scene.uniforms,
+        textures: &scene.textures,
+        environment: scene.environment.as_ref(),
+        world: &scene.world,
+    };
+    for pass in &scene.passes {
+        let writes_history = pass.target == Target::History;
+        if present_only && writes_history {
+            continue;
+        }
+
+        scene.profiler.begin_pass(&pass.name);
+        let areas: Vec<Option<TileRect>> = if writes_history && !tiles.is_empty() {
+            // Pixels outside the tiles must still be carried over from the
+            // last frame.
+            res.history.previous().scale_into(res.history.current());
+            tiles.iter().copied().map(Some).collect()
+        } else {
+            vec![None]
+        };
+
+        for area in areas {
+            if let Some((x, y, w, h)) = area {
+                unsafe {
+                    gl::Enable(gl::SCISSOR_TEST);
+                    gl::Scissor(x, y, w, h);
+                }
             }
 
-            let mut p = 0;
-            for program in &scene.programs {
-                let bind = scene.binding.get(program).clone().unwrap();
-                if bind.is_some() {
-                    gl::BindFramebuffer(
-                        gl::FRAMEBUFFER,
-                        scene
-                            .framebuffers
-                            .get_unchecked(bind.unwrap() as usize)
-                            .addr,
-                    );
-                } else {
-                    gl::BindFramebuffer(gl::FRAMEBUFFER, fbo);
+            pass.attach_fbo(&res);
+            if let Some(program) = scene.shader_manager.get_program(pass.program) {
+                let prog = program.lock().unwrap();
+                prog.bind();
+                prog.set_vec2("resolution", &scene.size);
+                prog.set_float("frame_nb", scene.frame_nb as f32);
+                prog.set_float("time", time as f32);
+                if prog.uniforms_location.contains_key("seed") {
+                    prog.set_u32("seed", seed);
                 }
-                if p > 0 {
-                    gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT | gl::STENCIL_BUFFER_BIT);
-                    gl::ClearColor(0.0, 0.0, 0.0, 0.0);
-                    gl::Disable(gl::BLEND);
-                } else {
-                    gl::Clear(gl::DEPTH_BUFFER_BIT | gl::STENCIL_BUFFER_BIT);
-                    gl::Enable(gl::BLEND);
-                    gl::BlendFunc(gl::ONE, gl::ONE);
+                if prog.uniforms_location.contains_key("env_enabled") {
+                    let enabled = res.environment.is_some_and(|env| env.total > 0.0);
+                    prog.set_float("env_enabled", if enabled { 1.0 } else { 0.0 });
                 }
-                if let Some(program) = scene.shader_manager.get_program(program.clone()) {
-                    let prog = program.lock().unwrap();
-                    prog.bind();
-                    prog.set_vec2("resolution", &scene.size);
-                    prog.set_float("frame_nb", scene.frame_nb as f32);
-                    prog.set_float("time", time as f32);
-                    if prog.uniforms_location.contains_key("pathbuffer") {
-                        prog.set_i32("pathbuffer", 0);
-                    }
-                    if prog.uniforms_location.contains_key("denoiserbuffer") {
-                        prog.set_i32("denoiserbuffer", 1);
-                    }
-                    if prog.uniforms_location.contains_key("in_eye") {
-                        prog.set_vec3("in_eye", &scene.eye);
-                    }
-                    if prog.uniforms_location.contains_key("in_target") {
-                        prog.set_vec3("in_target", &scene.target);
+                if let Some(env) = res.environment {
+                    if prog.uniforms_location.contains_key("env_size") {
+                        prog.set_vec2("env_size", &Vector2::new(env.width as f32, env.height as f32));
                     }
-                    if prog.uniforms_location.contains_key("in_up") {
-                        prog.set_vec3("in_up", &scene.up);
-                    }
-                    if prog.uniforms_location.contains_key("in_focus_pos") {
-                        prog.set_vec2("in_focus_pos", &scene.focus_pos);
-                    }
-                    if prog.uniforms_location.contains_key("in_aperture") {
-                        prog.set_float("in_aperture", scene.aperture);
+                    if prog.uniforms_location.contains_key("env_total") {
+                        prog.set_float("env_total", env.total);
                     }
                 }
-                let mut i: u32 = 0;
-                for tex in &scene.framebuffers {
-                    gl::ActiveTexture(gl::TEXTURE0 + i);
-                    gl::BindTexture(gl::TEXTURE_2D, tex.color_attachment.unwrap());
-                    i += 1;
+                if prog.uniforms_location.contains_key("world_objects") {
+                    prog.set_float("world_objects", res.world.objects as f32);
+                }
+                if prog.uniforms_location.contains_key("in_eye") {
+                    prog.set_vec3("in_eye", &scene.eye);
+                }
+                if prog.uniforms_location.contains_key("in_target") {
+                    prog.set_vec3("in_target", &scene.target);
+                }
+                if prog.uniforms_location.contains_key("in_up") {
+                    prog.set_vec3("in_up", &scene.up);
                 }
-                scene.mesh.draw();
-                p += 1;
+                if prog.uniforms_location.contains_key("in_focus_pos") {
+                    prog.set_vec2("in_focus_pos", &scene.focus_pos);
+                }
+                if prog.uniforms_location.contains_key("in_aperture") {
+                    prog.set_float("in_aperture", scene.aperture);
+                }
+                pass.draw(&res, &prog, &mut scene.mesh);
             }
+            pass.detach_fbo(&res);
+        }
 
-            // Show scene
-            gl::BindFramebuffer(gl::FRAMEBUFFER, fbo);
-            scene.frame_nb += 1;
+        unsafe {
+            gl::Disable(gl::SCISSOR_TEST);
         }
+        scene.profiler.end_pass();
     }
 }
+
+// Save the image shown in `fbo`.
+pub fn export_image(scene: &Scene, fbo: u32, path: &Path) -> Option<()> {
+    let (x, y, w, h) = scene.screen_viewport();
+    let image = export::Image::read_area(fbo, x, y, w as u32, h as u32);
+    image.save(path)?;
+    println!("Saved {}", path.display());
+    Some(())
+}
+
+// Check the render goal after a frame, and export and notify when reached.
+fn update_progress(scene: &mut Scene, fbo: u32) {
+    if scene.progress.wants_noise(scene.frame_nb) {
+        scene.progress.check_noise(&NoiseMap::read(&scene.history));
+    }
+
+    let report = match scene.progress.update(sample_count(scene)) {
+        Some(report) => report,
+        None => return,
+    };
+    println!("Render goal {}", report);
+
+    let export = scene.progress.goal.as_ref().and_then(|goal| goal.export.clone());
+    if let Some(path) = export {
+        export_image(scene, fbo, &path);
+    }
+
+    if let Some(callback) = scene.progress.callback.as_mut() {
+        callback(&report);
+    }
+}
+
+// Render one frame. Returns true if shaders or the pipeline were reloaded
+// before rendering it.
+pub fn display_loop(scene: &mut Scene, time: f64, fbo: u32, reset_on_reload: bool) -> bool {
+    let shaders_reloaded = scene.shader_manager.handle_reload();
+    let pipeline_reloaded = scene.handle_pipeline_reload();
+    let textures_reloaded = scene.textures.handle_reload()
+        | scene.environment.as_mut().is_some_and(|env| env.handle_reload())
+        | scene.world.handle_reload();
+    let should_clear = shaders_reloaded || pipeline_reloaded || textures_reloaded;
+    if should_clear && reset_on_reload {
+        reset(scene, fbo);
+    }
+    if scene.frame_nb == 0 {
+        scene.progress.restart();
+    }
+
+    scene.profiler.begin_frame();
+    if scene.progress.is_done() {
+        // Show the last accumulated image without adding samples to it.
+        scene.history.swap();
+        draw_passes(scene, time, fbo, true, &[]);
+        scene.history.swap();
+    } else {
+        let (width, height) = (scene.size.x as i32, scene.size.y as i32);
+        let tiles = match scene.tiles.as_mut() {
+            Some(tiles) => {
+                tiles.resize(width, height);
+                tiles.next()
+            }
+            None => Vec::new(),
+        };
+        let rects: Vec<TileRect> = match &scene.tiles {
+            Some(grid) => tiles.iter().map(|idx| grid.rect(*idx)).collect(),
+            None => Vec::new(),
+        };
+
+        draw_passes(scene, time, fbo, false, &rects);
+        if let Some(grid) = scene.tiles.as_mut() {
+            grid.mark_rendered(&tiles);
+        }
+        scene.history.swap();
+        scene.frame_nb += 1;
+        update_progress(scene, fbo);
+    }
+
+    // Show scene
+    unsafe {
+        gl::BindFramebuffer(gl::FRAMEBUFFER, fbo);
+    }
+
+    should_clear
+}
@@ -0,0 +1,84 @@
+use crate::export::Image;
+use crate::frame::pingpong::{PingPong, MOMENTS};
+
+// Same weights as the path tracer uses for the second moment.
+const LUMA: [f32; 3] = [0.2126, 0.7152, 0.0722];
+
+// Keeps dark pixels from having a huge relative error. Must match post.fs.
+const ERROR_BIAS: f32 = 0.01;
+
+// Relative standard error of the mean of a pixel, from the sum of the
+// luminance of its samples, the sum of their square and their count.
+// Must match `relative_error` in post.fs.
+pub fn relative_error(sum: f32, sum_sq: f32, samples: f32) -> f32 {
+    if samples < 2.0 {
+        return f32::INFINITY;
+    }
+
+    let mean = sum / samples;
+    let variance = (sum_sq / samples - mean * mean).max(0.0);
+    (variance / samples).sqrt() / (mean + ERROR_BIAS)
+}
+
+// How converged each pixel of the accumulated image is. Rows are top to
+// bottom, like `Image`.
+#[derive(Debug, Clone)]
+pub struct NoiseMap {
+    pub width: u32,
+    pub height: u32,
+    // Relative standard error of every pixel.
+    pub error: Vec<f32>,
+    // Number of samples every pixel got.
+    pub samples: Vec<f32>,
+}
+
+impl NoiseMap {
+    // Read the image accumulated during the last frame, which is in the
+    // previous buffer once the history is swapped.
+    pub fn read(history: &PingPong) -> NoiseMap {
+        let fb = history.previous();
+        let color = Image::read(fb.addr, fb.width as u32, fb.height as u32);
+        let moments = Image::read_attachment(fb, MOMENTS);
+
+        let (error, samples) = color
+            .pixels
+            .chunks(4)
+            .zip(moments.pixels.chunks(4))
+            .map(|(c, m)| {
+                let sum = LUMA[0] * c[0] + LUMA[1] * c[1] + LUMA[2] * c[2];
+                (relative_error(sum, m[0], c[3]), c[3])
+            })
+            .unzip();
+
+        NoiseMap {
+            width: color.width,
+            height: color.height,
+            error,
+            samples,
+        }
+    }
+
+    // Average error over the image. Pixels with an error above 1 count as 1
+    // so a few unconverged pixels don't hide everything else.
+    pub fn mean_error(&self) -> f32 {
+        let count = self.error.len().max(1) as f32;
+        self.error.iter().map(|e| e.min(1.0)).sum::<f32>() / count
+    }
+
+    // The error as a grey level image, for debugging.
+    pub fn to_image(&self) -> Image {
+        let pixels = self
+            .error
+            .iter()
+            .flat_map(|e| {
+                let v = e.min(1.0);
+                [v, v, v, 1.0]
+            })
+            .collect();
+        Image {
+            width: self.width,
+            height: self.height,
+            pixels,
+        }
+    }
+}
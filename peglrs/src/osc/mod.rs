@@ -0,0 +1,304 @@
+pub mod packet;
+
+use std::collections::{BTreeMap, HashSet};
+use std::net::{SocketAddr, UdpSocket};
+use std::path::Path;
+use std::time::Instant;
+
+use cgmath::{Vector2, Vector3};
+use serde::Deserialize;
+
+use crate::shaders::UniformValue;
+use crate::{utils, Scene};
+use packet::OscMessage;
+
+// Largest UDP payload.
+const MAX_PACKET: usize = 65536;
+
+// Below this, a smoothed value has reached its goal and stops changing, so
+// the accumulation can go on.
+const SETTLED: f32 = 1e-4;
+
+// How the values of an address are scaled and smoothed.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct OscMapping {
+    // Range of the values sent, mapped to `output` and clamped. Either
+    // defaults to [0, 1] when the other is given.
+    pub input: Option<[f32; 2]>,
+    pub output: Option<[f32; 2]>,
+    // Seconds it takes to get about two thirds of the way to a new value. 0
+    // jumps to it.
+    pub smoothing: Option<f32>,
+}
+
+impl OscMapping {
+    fn scale(&self, value: f32) -> f32 {
+        if self.input.is_none() && self.output.is_none() {
+            return value;
+        }
+        let [in_min, in_max] = self.input.unwrap_or([0.0, 1.0]);
+        let [out_min, out_max] = self.output.unwrap_or([0.0, 1.0]);
+        let t = if in_max != in_min {
+            ((value - in_min) / (in_max - in_min)).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        out_min + t * (out_max - out_min)
+    }
+}
+
+// Mappings of the OSC addresses, read from a TOML file:
+//
+//   smoothing = 0.1
+//
+//   [address."/stuffy/uniform/env_intensity"]
+//   input = [0.0, 127.0]
+//   output = [0.0, 4.0]
+//   smoothing = 0.5
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OscConfig {
+    // For the addresses without their own.
+    pub smoothing: f32,
+    pub address: BTreeMap<String, OscMapping>,
+}
+
+impl OscConfig {
+    pub fn load(path: &Path) -> Option<OscConfig> {
+        let src = utils::load_file(path)?;
+        match toml::from_str(&src) {
+            Ok(config) => Some(config),
+            Err(err) => {
+                eprintln!(
+                    "[ERR] Couldn't parse OSC mappings {} : {}",
+                    path.display(),
+                    err
+                );
+                None
+            }
+        }
+    }
+}
+
+// What an address drives.
+#[derive(Debug, Clone, PartialEq)]
+enum Target {
+    Uniform(String),
+    Aperture,
+    FocusPos,
+    Eye,
+    LookAt,
+}
+
+impl Target {
+    fn parse(address: &str) -> Option<Target> {
+        if let Some(name) = address.strip_prefix("/stuffy/uniform/") {
+            return Some(Target::Uniform(String::from(name))).filter(|_| !name.is_empty());
+        }
+        match address {
+            "/stuffy/camera/aperture" => Some(Target::Aperture),
+            "/stuffy/camera/focus" => Some(Target::FocusPos),
+            "/stuffy/camera/eye" => Some(Target::Eye),
+            "/stuffy/camera/target" => Some(Target::LookAt),
+            _ => None,
+        }
+    }
+
+    fn accepts(&self, len: usize) -> bool {
+        match self {
+            Target::Uniform(_) => (1..=4).contains(&len),
+            Target::Aperture => len == 1,
+            Target::FocusPos => len == 2,
+            Target::Eye | Target::LookAt => len == 3,
+        }
+    }
+
+    fn current(&self, scene: &Scene) -> Option<Vec<f32>> {
+        match self {
+            Target::Uniform(name) => match scene.uniforms.get(name)? {
+                UniformValue::Int(v) => Some(vec![*v as f32]),
+                UniformValue::Float(v) => Some(vec![*v]),
+                UniformValue::Vec2(v) => Some(vec![v.x, v.y]),
+                UniformValue::Vec3(v) => Some(vec![v.x, v.y, v.z]),
+                UniformValue::Vec4(v) => Some(vec![v.x, v.y, v.z, v.w]),
+            },
+            Target::Aperture => Some(vec![scene.aperture]),
+            Target::FocusPos => Some(vec![scene.focus_pos.x, scene.focus_pos.y]),
+            Target::Eye => Some(vec![scene.eye.x, scene.eye.y, scene.eye.z]),
+            Target::LookAt => Some(vec![scene.target.x, scene.target.y, scene.target.z]),
+        }
+    }
+
+    // Set the value, returns false if it already was.
+    fn apply(&self, scene: &mut Scene, v: &[f32]) -> bool {
+        if self.current(scene).as_deref() == Some(v) {
+            return false;
+        }
+        match self {
+            Target::Uniform(name) => {
+                // Rounded for an int uniform, which may not change then.
+                let current = scene.uniforms.get(name);
+                match UniformValue::from_floats(v).map(|value| value.matching(current)) {
+                    Some(value) if current != Some(&value) => {
+                        scene.uniforms.insert(name.clone(), value);
+                    }
+                    _ => return false,
+                }
+            }
+            Target::Aperture => scene.aperture = v[0],
+            Target::FocusPos => scene.focus_pos = Vector2::new(v[0], v[1]),
+            Target::Eye => scene.eye = Vector3::new(v[0], v[1], v[2]),
+            Target::LookAt => scene.target = Vector3::new(v[0], v[1], v[2]),
+        }
+        true
+    }
+}
+
+// A value driven by an address, on its way to the last one received.
+#[derive(Debug)]
+struct Channel {
+    target: Target,
+    value: Vec<f32>,
+    goal: Vec<f32>,
+    smoothing: f32,
+}
+
+impl Channel {
+    fn step(&mut self, dt: f32) {
+        if self.value == self.goal {
+            return;
+        }
+        let alpha = if self.smoothing > 0.0 {
+            1.0 - (-dt / self.smoothing).exp()
+        } else {
+            1.0
+        };
+        let mut settled = true;
+        for (value, goal) in self.value.iter_mut().zip(&self.goal) {
+            *value += (goal - *value) * alpha;
+            settled &= (goal - *value).abs() <= SETTLED * goal.abs().max(1.0);
+        }
+        if settled {
+            self.value.clone_from(&self.goal);
+        }
+    }
+}
+
+// What an update changed.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct OscChanges {
+    pub scene: bool,
+    pub camera: bool,
+}
+
+// Listens for OSC messages on a UDP port. Addresses are
+// /stuffy/uniform/<name> with 1 to 4 numbers, and /stuffy/camera/aperture,
+// /stuffy/camera/focus, /stuffy/camera/eye and /stuffy/camera/target.
+#[derive(Debug)]
+pub struct OscListener {
+    socket: UdpSocket,
+    config: OscConfig,
+    channels: BTreeMap<String, Channel>,
+    // Addresses already reported as not understood.
+    ignored: HashSet<String>,
+    last_update: Instant,
+}
+
+impl OscListener {
+    // Port 0 picks a free port, see `addr`.
+    pub fn bind(addr: &str, config: OscConfig) -> Option<OscListener> {
+        let socket = match UdpSocket::bind(addr) {
+            Ok(socket) => socket,
+            Err(err) => {
+                eprintln!("[ERR] Couldn't listen for OSC on {} : {}", addr, err);
+                return None;
+            }
+        };
+        socket.set_nonblocking(true).ok()?;
+        println!("[NFO] OSC listening on {}", socket.local_addr().ok()?);
+        Some(OscListener {
+            socket,
+            config,
+            channels: BTreeMap::new(),
+            ignored: HashSet::new(),
+            last_update: Instant::now(),
+        })
+    }
+
+    pub fn addr(&self) -> Option<SocketAddr> {
+        self.socket.local_addr().ok()
+    }
+
+    fn receive(&self) -> Vec<OscMessage> {
+        let mut messages = Vec::new();
+        let mut buffer = vec![0u8; MAX_PACKET];
+        while let Ok(len) = self.socket.recv(&mut buffer) {
+            packet::decode(&buffer[..len], &mut messages);
+        }
+        messages
+    }
+
+    fn route(&mut self, scene: &Scene, message: OscMessage) {
+        let target = match Target::parse(&message.address) {
+            Some(target) if target.accepts(message.args.len()) => target,
+            _ => {
+                if self.ignored.insert(message.address.clone()) {
+                    eprintln!(
+                        "[ERR] Ignoring OSC message {} with {} values",
+                        message.address,
+                        message.args.len()
+                    );
+                }
+                return;
+            }
+        };
+
+        let mapping = self
+            .config
+            .address
+            .get(&message.address)
+            .cloned()
+            .unwrap_or_default();
+        let goal: Vec<f32> = message.args.iter().map(|v| mapping.scale(*v)).collect();
+        let smoothing = mapping.smoothing.unwrap_or(self.config.smoothing);
+        let channel = self
+            .channels
+            .entry(message.address)
+            .or_insert_with(|| Channel {
+                value: target
+                    .current(scene)
+                    .filter(|v| v.len() == goal.len())
+                    .unwrap_or_else(|| goal.clone()),
+                target,
+                goal: Vec::new(),
+                smoothing,
+            });
+        if channel.value.len() != goal.len() {
+            channel.value.clone_from(&goal);
+        }
+        channel.goal = goal;
+    }
+
+    // Take the messages received since the last update, and move every value
+    // towards the last one received.
+    pub fn update(&mut self, scene: &mut Scene) -> OscChanges {
+        for message in self.receive() {
+            self.route(scene, message);
+        }
+
+        let dt = self.last_update.elapsed().as_secs_f32();
+        self.last_update = Instant::now();
+        let mut changes = OscChanges::default();
+        for channel in self.channels.values_mut() {
+            channel.step(dt);
+            // Senders often repeat the same value, which mustn't restart the
+            // accumulation.
+            if channel.target.apply(scene, &channel.value) {
+                changes.scene = true;
+                changes.camera |= !matches!(channel.target, Target::Uniform(_));
+            }
+        }
+        changes
+    }
+}
@@ -0,0 +1,217 @@
+// Decoding of OSC 1.0 packets: messages, and bundles of them, whose time
+// tags are ignored. Only the numeric arguments are kept, as floats.
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OscMessage {
+    pub address: String,
+    pub args: Vec<f32>,
+}
+
+// Reads the 4 bytes aligned fields of a packet.
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        let bytes = self.data.get(self.pos..self.pos.checked_add(len)?)?;
+        self.pos += len;
+        Some(bytes)
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        let bytes = self.take(4)?;
+        Some(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        Some((self.u32()? as u64) << 32 | self.u32()? as u64)
+    }
+
+    // A string ends with at least one 0, and is padded to 4 bytes.
+    fn string(&mut self) -> Option<&'a str> {
+        let rest = self.data.get(self.pos..)?;
+        let len = rest.iter().position(|b| *b == 0)?;
+        let s = std::str::from_utf8(&rest[..len]).ok()?;
+        self.take((len / 4 + 1) * 4)?;
+        Some(s)
+    }
+
+    // A size followed by as many bytes, padded to 4 bytes.
+    fn blob(&mut self) -> Option<()> {
+        let len = self.u32()? as usize;
+        self.take(len.div_ceil(4) * 4)?;
+        Some(())
+    }
+}
+
+fn decode_message(data: &[u8]) -> Option<OscMessage> {
+    let mut reader = Reader { data, pos: 0 };
+    let address = String::from(reader.string()?);
+    if !address.starts_with('/') {
+        return None;
+    }
+    // Old senders may leave out the type tags, meaning no arguments.
+    if reader.pos == data.len() {
+        return Some(OscMessage {
+            address,
+            args: Vec::new(),
+        });
+    }
+    let tags = reader.string()?.strip_prefix(',')?;
+
+    let mut args = Vec::new();
+    for tag in tags.chars() {
+        match tag {
+            'f' => args.push(f32::from_bits(reader.u32()?)),
+            'i' => args.push(reader.u32()? as i32 as f32),
+            'd' => args.push(f64::from_bits(reader.u64()?) as f32),
+            'h' => args.push(reader.u64()? as i64 as f32),
+            'T' => args.push(1.0),
+            'F' => args.push(0.0),
+            's' | 'S' => {
+                reader.string()?;
+            }
+            'b' => reader.blob()?,
+            'c' | 'r' | 'm' => {
+                reader.u32()?;
+            }
+            't' => {
+                reader.u64()?;
+            }
+            'N' | 'I' | '[' | ']' => (),
+            _ => return None,
+        }
+    }
+    Some(OscMessage { address, args })
+}
+
+// Every message of a packet, in order. A malformed packet gives what could
+// be read before the error.
+pub fn decode(data: &[u8], messages: &mut Vec<OscMessage>) {
+    if !data.starts_with(b"#bundle\0") {
+        messages.extend(decode_message(data));
+        return;
+    }
+
+    // "#bundle", then the time tag, then the size of every element.
+    let mut reader = Reader { data, pos: 16 };
+    while let Some(len) = reader.u32() {
+        match reader.take(len as usize) {
+            Some(element) => decode(element, messages),
+            None => return,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn string(s: &str) -> Vec<u8> {
+        let mut data = s.as_bytes().to_vec();
+        data.resize((s.len() / 4 + 1) * 4, 0);
+        data
+    }
+
+    fn message(address: &str, tags: &str, args: &[u8]) -> Vec<u8> {
+        let mut data = string(address);
+        data.extend(string(tags));
+        data.extend_from_slice(args);
+        data
+    }
+
+    fn bundle(elements: &[Vec<u8>]) -> Vec<u8> {
+        let mut data = string("#bundle");
+        data.extend_from_slice(&1u64.to_be_bytes());
+        for element in elements {
+            data.extend_from_slice(&(element.len() as u32).to_be_bytes());
+            data.extend_from_slice(element);
+        }
+        data
+    }
+
+    fn decoded(data: &[u8]) -> Vec<OscMessage> {
+        let mut messages = Vec::new();
+        decode(data, &mut messages);
+        messages
+    }
+
+    fn osc(address: &str, args: &[f32]) -> OscMessage {
+        OscMessage {
+            address: String::from(address),
+            args: args.to_vec(),
+        }
+    }
+
+    #[test]
+    fn decodes_floats_and_ints() {
+        let mut args = 0.5f32.to_be_bytes().to_vec();
+        args.extend_from_slice(&(-3i32).to_be_bytes());
+        let data = message("/stuffy/aperture", ",fi", &args);
+        assert_eq!(decoded(&data), vec![osc("/stuffy/aperture", &[0.5, -3.0])]);
+    }
+
+    #[test]
+    fn decodes_doubles_longs_and_booleans() {
+        let mut args = 0.25f64.to_be_bytes().to_vec();
+        args.extend_from_slice(&(-7i64).to_be_bytes());
+        let data = message("/a", ",dhTF", &args);
+        assert_eq!(decoded(&data), vec![osc("/a", &[0.25, -7.0, 1.0, 0.0])]);
+    }
+
+    #[test]
+    fn skips_strings_and_blobs() {
+        let mut args = string("label");
+        args.extend_from_slice(&5u32.to_be_bytes());
+        args.extend_from_slice(&[1, 2, 3, 4, 5, 0, 0, 0]);
+        args.extend_from_slice(&2.0f32.to_be_bytes());
+        let data = message("/a", ",sbf", &args);
+        assert_eq!(decoded(&data), vec![osc("/a", &[2.0])]);
+    }
+
+    #[test]
+    fn no_type_tags_means_no_arguments() {
+        assert_eq!(decoded(&string("/ping")), vec![osc("/ping", &[])]);
+    }
+
+    #[test]
+    fn rejects_bad_messages() {
+        let args = 1.0f32.to_be_bytes();
+        // Not an address, an unknown tag and a missing argument.
+        assert!(decoded(&message("nope", ",f", &args)).is_empty());
+        assert!(decoded(&message("/a", ",fx", &args)).is_empty());
+        assert!(decoded(&message("/a", ",ff", &args)).is_empty());
+        assert!(decoded(b"/a").is_empty());
+    }
+
+    #[test]
+    fn decodes_nested_bundles_in_order() {
+        let first = message("/first", ",f", &1.0f32.to_be_bytes());
+        let second = message("/second", ",f", &2.0f32.to_be_bytes());
+        let third = message("/third", ",i", &3i32.to_be_bytes());
+        let data = bundle(&[first, bundle(&[second, third])]);
+        assert_eq!(
+            decoded(&data),
+            vec![
+                osc("/first", &[1.0]),
+                osc("/second", &[2.0]),
+                osc("/third", &[3.0]),
+            ]
+        );
+    }
+
+    #[test]
+    fn a_truncated_bundle_keeps_the_complete_messages() {
+        let first = message("/first", ",f", &1.0f32.to_be_bytes());
+        let second = message("/second", ",f", &2.0f32.to_be_bytes());
+        let data = bundle(&[first, second]);
+        assert_eq!(
+            decoded(&data[..data.len() - 2]),
+            vec![osc("/first", &[1.0])]
+        );
+        // Cut in the time tag.
+        assert!(decoded(&data[..12]).is_empty());
+    }
+}
@@ -0,0 +1,348 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use cgmath::{Vector2, Vector3, Vector4};
+use serde::Deserialize;
+
+use crate::frame::fbo::{ColorAttachment, DepthStencilAttachment, FramebufferDesc};
+use crate::frame::graph::RenderGraph;
+use crate::frame::pass::{Blend, RenderPass, Source};
+use crate::frame::pool::{TargetDesc, TargetSize};
+use crate::frame::Target;
+use crate::shaders::shader_loader::ShaderManager;
+use crate::shaders::UniformValue;
+use crate::texture::TextureManager;
+use crate::utils;
+
+// Reserved target names.
+pub const SCREEN: &str = "screen";
+pub const HISTORY: &str = "history";
+pub const PREVIOUS_HISTORY: &str = "history.previous";
+pub const MOMENTS: &str = "history.moments";
+pub const PREVIOUS_MOMENTS: &str = "history.previous.moments";
+// The environment map set by the host and its sampling tables.
+pub const ENVIRONMENT: &str = "environment";
+pub const ENV_MARGINAL: &str = "environment.marginal";
+pub const ENV_CONDITIONAL: &str = "environment.conditional";
+// The objects and materials of the scene file, see load_world.
+pub const WORLD: &str = "world";
+
+// The moments are written by the same pass as the history, so for ordering
+// they are the same resource.
+fn graph_resource(name: &str) -> &str {
+    match name {
+        MOMENTS => HISTORY,
+        PREVIOUS_MOMENTS => PREVIOUS_HISTORY,
+        name => name,
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TargetFile {
+    #[serde(default = "default_format")]
+    pub format: ColorAttachment,
+    // Either a scale relative to the render size, or a size in pixels.
+    pub scale: Option<f32>,
+    pub size: Option<[i32; 2]>,
+    #[serde(default = "default_samples")]
+    pub samples: i32,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum UniformFile {
+    Int(i32),
+    Float(f32),
+    Vector(Vec<f32>),
+}
+
+// An image bound to a sampler, either just its path or a table to say it
+// holds data rather than color: `{ path = "normals.png", srgb = false }`.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum TextureFile {
+    Path(String),
+    Image {
+        path: String,
+        #[serde(default = "default_srgb")]
+        srgb: bool,
+    },
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PassFile {
+    pub name: String,
+    pub shaders: Vec<String>,
+    pub output: String,
+    #[serde(default)]
+    pub inputs: HashMap<String, String>,
+    #[serde(default = "default_blend")]
+    pub blend: Blend,
+    #[serde(default = "default_clear")]
+    pub clear: bool,
+    #[serde(default = "default_samples")]
+    pub samples: i32,
+    #[serde(default)]
+    pub uniforms: HashMap<String, UniformFile>,
+    #[serde(default)]
+    pub textures: HashMap<String, TextureFile>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PipelineFile {
+    #[serde(default)]
+    pub targets: HashMap<String, TargetFile>,
+    pub passes: Vec<PassFile>,
+}
+
+fn default_format() -> ColorAttachment {
+    ColorAttachment::RGBA_32F
+}
+
+fn default_samples() -> i32 {
+    1
+}
+
+fn default_blend() -> Blend {
+    Blend::Disabled
+}
+
+fn default_clear() -> bool {
+    true
+}
+
+fn default_srgb() -> bool {
+    true
+}
+
+// A pipeline ready to be used by a scene.
+#[derive(Debug)]
+pub struct Pipeline {
+    pub passes: Vec<RenderPass>,
+    pub targets: Vec<TargetDesc>,
+    pub target_names: Vec<String>,
+}
+
+impl UniformFile {
+    fn to_value(&self) -> Option<UniformValue> {
+        match self {
+            UniformFile::Int(v) => Some(UniformValue::Int(*v)),
+            UniformFile::Float(v) => Some(UniformValue::Float(*v)),
+            UniformFile::Vector(v) => match v.len() {
+                1 => Some(UniformValue::Float(v[0])),
+                2 => Some(UniformValue::Vec2(Vector2::new(v[0], v[1]))),
+                3 => Some(UniformValue::Vec3(Vector3::new(v[0], v[1], v[2]))),
+                4 => Some(UniformValue::Vec4(Vector4::new(v[0], v[1], v[2], v[3]))),
+                _ => None,
+            },
+        }
+    }
+}
+
+impl TextureFile {
+    fn path_and_srgb(&self) -> (&Path, bool) {
+        match self {
+            TextureFile::Path(path) => (Path::new(path), true),
+            TextureFile::Image { path, srgb } => (Path::new(path), *srgb),
+        }
+    }
+}
+
+impl TargetFile {
+    fn to_desc(&self) -> TargetDesc {
+        let size = match (self.size, self.scale) {
+            (Some([w, h]), _) => TargetSize::Absolute(w, h),
+            (None, Some(scale)) => TargetSize::Relative(scale),
+            (None, None) => TargetSize::Relative(1.0),
+        };
+
+        TargetDesc {
+            size,
+            framebuffer: FramebufferDesc {
+                color: self.format,
+                depth_stencil: DepthStencilAttachment::DEPTH24_STENCIL8,
+                samples: self.samples,
+            },
+        }
+    }
+}
+
+pub fn load_file(path: &Path) -> Option<PipelineFile> {
+    let src = utils::load_file(path)?;
+    match toml::from_str(&src) {
+        Ok(file) => Some(file),
+        Err(err) => {
+            eprintln!("[ERR] Couldn't parse pipeline {} : {}", path.display(), err);
+            None
+        }
+    }
+}
+
+fn find_target(names: &[String], name: &str) -> Option<usize> {
+    names.iter().position(|n| n == name)
+}
+
+impl Pipeline {
+    // Order the passes of a pipeline file so each runs after the passes
+    // writing its inputs.
+    fn order_passes(file: &PipelineFile) -> Option<Vec<&PassFile>> {
+        let mut graph = RenderGraph::new();
+        graph.add_external(PREVIOUS_HISTORY);
+        graph.add_external(ENVIRONMENT);
+        graph.add_external(ENV_MARGINAL);
+        graph.add_external(ENV_CONDITIONAL);
+        graph.add_external(WORLD);
+        for pass_file in &file.passes {
+            let inputs: Vec<&str> = pass_file
+                .inputs
+                .values()
+                .map(|s| graph_resource(s))
+                .collect();
+            graph.add_pass(&pass_file.name, &inputs, &pass_file.output);
+        }
+
+        match graph.order() {
+            Ok(order) => Some(order.iter().map(|idx| &file.passes[*idx]).collect()),
+            Err(err) => {
+                eprintln!("[ERR] Invalid pipeline: {}", err);
+                None
+            }
+        }
+    }
+
+    // Compile the programs of a pipeline file and resolve its target names.
+    // Nothing is kept if any pass is invalid.
+    pub fn build(
+        file: &PipelineFile,
+        shader_manager: &mut ShaderManager,
+        textures: &mut TextureManager,
+    ) -> Option<Pipeline> {
+        let ordered = Pipeline::order_passes(file)?;
+
+        let mut target_names: Vec<String> = file.targets.keys().cloned().collect();
+        target_names.sort();
+        let targets: Vec<TargetDesc> = target_names
+            .iter()
+            .map(|name| file.targets[name].to_desc())
+            .collect();
+
+        let mut passes: Vec<RenderPass> = Vec::with_capacity(file.passes.len());
+        let mut ok = true;
+        for pass_file in ordered {
+            let target = match pass_file.output.as_str() {
+                SCREEN => Some(Target::Screen),
+                HISTORY => Some(Target::History),
+                name => find_target(&target_names, name).map(Target::Framebuffer),
+            };
+            let target = match target {
+                Some(target) => target,
+                None => {
+                    eprintln!(
+                        "[ERR] Pass {} writes to unknown target {}",
+                        pass_file.name, pass_file.output
+                    );
+                    ok = false;
+                    break;
+                }
+            };
+
+            let shaders: Vec<&Path> = pass_file.shaders.iter().map(Path::new).collect();
+            let program = match shader_manager.try_load_program(&shaders) {
+                Some(program) => program,
+                None => {
+                    eprintln!("[ERR] Couldn't build program for pass {}", pass_file.name);
+                    ok = false;
+                    break;
+                }
+            };
+
+            let mut pass = RenderPass::new(&pass_file.name, program, target);
+            pass.clear = pass_file.clear;
+            pass.blend = pass_file.blend;
+            pass.samples = pass_file.samples;
+            passes.push(pass);
+            let pass = passes.last_mut().unwrap();
+
+            let mut samplers: Vec<&String> = pass_file.inputs.keys().collect();
+            samplers.sort();
+            for sampler in samplers {
+                let input = &pass_file.inputs[sampler];
+                let source = match input.as_str() {
+                    HISTORY => Some(Source::History),
+                    PREVIOUS_HISTORY => Some(Source::PreviousHistory),
+                    MOMENTS => Some(Source::Moments),
+                    PREVIOUS_MOMENTS => Some(Source::PreviousMoments),
+                    ENVIRONMENT => Some(Source::Environment),
+                    ENV_MARGINAL => Some(Source::EnvMarginal),
+                    ENV_CONDITIONAL => Some(Source::EnvConditional),
+                    WORLD => Some(Source::World),
+                    name => find_target(&target_names, name).map(Source::Framebuffer),
+                };
+                match source {
+                    // A multisampled texture can't be read by a sampler2D.
+                    Some(Source::Framebuffer(idx)) if targets[idx].framebuffer.samples > 1 => {
+                        eprintln!(
+                            "[ERR] Pass {} reads multisampled target {}",
+                            pass_file.name, input
+                        );
+                        ok = false;
+                    }
+                    Some(source) => pass.add_input(sampler, source),
+                    None => {
+                        eprintln!(
+                            "[ERR] Pass {} reads unknown target {}",
+                            pass_file.name, input
+                        );
+                        ok = false;
+                    }
+                }
+            }
+
+            let mut samplers: Vec<&String> = pass_file.textures.keys().collect();
+            samplers.sort();
+            for sampler in samplers {
+                let (path, srgb) = pass_file.textures[sampler].path_and_srgb();
+                match textures.get_or_load(path, srgb) {
+                    Some(texture) => pass.add_input(sampler, Source::Texture(texture)),
+                    None => {
+                        eprintln!(
+                            "[ERR] Pass {} couldn't load texture {}",
+                            pass_file.name,
+                            path.display()
+                        );
+                        ok = false;
+                    }
+                }
+            }
+
+            for (name, uniform) in &pass_file.uniforms {
+                match uniform.to_value() {
+                    Some(value) => pass.set_uniform(name, value),
+                    None => {
+                        eprintln!(
+                            "[ERR] Pass {} has an invalid value for uniform {}",
+                            pass_file.name, name
+                        );
+                        ok = false;
+                    }
+                }
+            }
+        }
+
+        if !ok {
+            for pass in &passes {
+                shader_manager.rm_program(pass.program);
+            }
+            return None;
+        }
+
+        Some(Pipeline {
+            passes,
+            targets,
+            target_names,
+        })
+    }
+}
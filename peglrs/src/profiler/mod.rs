@@ -0,0 +1,298 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+// How many frames the min/avg/max of a pass are computed over.
+pub const TIMING_WINDOW: usize = 120;
+
+// GPU time of a pass over the last frames.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PassTiming {
+    pub name: String,
+    pub min: Duration,
+    pub avg: Duration,
+    pub max: Duration,
+    // Number of frames measured, up to TIMING_WINDOW.
+    pub frames: usize,
+}
+
+impl fmt::Display for PassTiming {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+        write!(
+            f,
+            "{} {:.2}ms ({:.2}-{:.2})",
+            self.name,
+            ms(self.avg),
+            ms(self.min),
+            ms(self.max)
+        )
+    }
+}
+
+#[derive(Debug)]
+struct PassHistory {
+    name: String,
+    times: VecDeque<Duration>,
+}
+
+// The queries issued during one frame, in pass order.
+#[derive(Debug, Default)]
+struct FrameQueries {
+    queries: Vec<u32>,
+    names: Vec<String>,
+    used: usize,
+}
+
+// Sets of queries in flight. A set is only reused once the GPU is done with
+// it, or its results are dropped rather than waited for.
+const FRAMES_IN_FLIGHT: usize = 3;
+
+impl FrameQueries {
+    // Queries finish in order, so the frame is done when its last one is.
+    fn available(&self) -> bool {
+        if self.used == 0 {
+            return false;
+        }
+        let mut available: u32 = 0;
+        unsafe {
+            gl::GetQueryObjectuiv(
+                self.queries[self.used - 1],
+                gl::QUERY_RESULT_AVAILABLE,
+                &mut available,
+            );
+        }
+        available != 0
+    }
+}
+
+// Measures how long every pass takes on the GPU with GL_TIME_ELAPSED queries.
+// Results are read a few frames late, once they are available, so waiting
+// for them doesn't stall the pipeline.
+#[derive(Debug)]
+pub struct Profiler {
+    enabled: bool,
+    log_interval: Option<Duration>,
+    last_log: Instant,
+    frames: [FrameQueries; FRAMES_IN_FLIGHT],
+    current: usize,
+    active: bool,
+    passes: Vec<PassHistory>,
+}
+
+impl Profiler {
+    pub fn new() -> Profiler {
+        Profiler {
+            enabled: false,
+            log_interval: None,
+            last_log: Instant::now(),
+            frames: Default::default(),
+            current: 0,
+            active: false,
+            passes: Vec::new(),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    // With a `log_interval`, the timings are printed that often.
+    pub fn set_enabled(&mut self, enabled: bool, log_interval: Option<Duration>) {
+        if !enabled {
+            // Results of queries still in flight are dropped.
+            for frame in &mut self.frames {
+                frame.used = 0;
+            }
+            self.passes.clear();
+        }
+        self.enabled = enabled;
+        self.log_interval = log_interval;
+        self.last_log = Instant::now();
+    }
+
+    // Start a new frame: collect the results of the frames the GPU is done
+    // with, oldest first, and move to the next set of queries.
+    pub fn begin_frame(&mut self) {
+        if !self.enabled {
+            return;
+        }
+
+        let mut results = Vec::new();
+        for i in 1..=FRAMES_IN_FLIGHT {
+            let frame = &mut self.frames[(self.current + i) % FRAMES_IN_FLIGHT];
+            if frame.used == 0 {
+                continue;
+            }
+            if !frame.available() {
+                break;
+            }
+            for (query, name) in frame.queries.iter().zip(&frame.names).take(frame.used) {
+                let mut nanos: u64 = 0;
+                unsafe {
+                    gl::GetQueryObjectui64v(*query, gl::QUERY_RESULT, &mut nanos);
+                }
+                results.push((name.clone(), Duration::from_nanos(nanos)));
+            }
+            frame.used = 0;
+        }
+
+        // Still in flight after all the others, its results are lost.
+        self.current = (self.current + 1) % FRAMES_IN_FLIGHT;
+        self.frames[self.current].used = 0;
+
+        for (name, time) in results {
+            self.record(name, time);
+        }
+        self.log();
+    }
+
+    pub fn begin_pass(&mut self, name: &str) {
+        if !self.enabled {
+            return;
+        }
+
+        let frame = &mut self.frames[self.current];
+        if frame.used == frame.queries.len() {
+            let mut query = 0;
+            unsafe {
+                gl::GenQueries(1, &mut query);
+            }
+            frame.queries.push(query);
+            frame.names.push(String::new());
+        }
+        frame.names[frame.used].clear();
+        frame.names[frame.used].push_str(name);
+        unsafe {
+            gl::BeginQuery(gl::TIME_ELAPSED, frame.queries[frame.used]);
+        }
+        frame.used += 1;
+        self.active = true;
+    }
+
+    pub fn end_pass(&mut self) {
+        if self.active {
+            unsafe {
+                gl::EndQuery(gl::TIME_ELAPSED);
+            }
+            self.active = false;
+        }
+    }
+
+    fn record(&mut self, name: String, time: Duration) {
+        let pass = match self.passes.iter().position(|pass| pass.name == name) {
+            Some(idx) => &mut self.passes[idx],
+            None => {
+                self.passes.push(PassHistory {
+                    name,
+                    times: VecDeque::with_capacity(TIMING_WINDOW),
+                });
+                self.passes.last_mut().unwrap()
+            }
+        };
+        if pass.times.len() == TIMING_WINDOW {
+            pass.times.pop_front();
+        }
+        pass.times.push_back(time);
+    }
+
+    // Timings of every pass measured so far, in the order they first ran.
+    pub fn timings(&self) -> Vec<PassTiming> {
+        self.passes
+            .iter()
+            .filter(|pass| !pass.times.is_empty())
+            .map(|pass| PassTiming {
+                name: pass.name.clone(),
+                min: pass.times.iter().min().copied().unwrap_or_default(),
+                avg: pass.times.iter().sum::<Duration>() / pass.times.len() as u32,
+                max: pass.times.iter().max().copied().unwrap_or_default(),
+                frames: pass.times.len(),
+            })
+            .collect()
+    }
+
+    fn log(&mut self) {
+        let interval = match self.log_interval {
+            Some(interval) => interval,
+            None => return,
+        };
+        if self.last_log.elapsed() < interval {
+            return;
+        }
+        self.last_log = Instant::now();
+
+        let timings = self.timings();
+        if timings.is_empty() {
+            return;
+        }
+        let line: Vec<String> = timings.iter().map(|t| t.to_string()).collect();
+        println!("[NFO] GPU time: {}", line.join(" | "));
+    }
+}
+
+impl Drop for Profiler {
+    fn drop(&mut self) {
+        for frame in &self.frames {
+            if !frame.queries.is_empty() {
+                unsafe {
+                    gl::DeleteQueries(frame.queries.len() as i32, frame.queries.as_ptr());
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn aggregates_every_pass_in_the_order_it_first_ran() {
+        let mut profiler = Profiler::new();
+        assert!(profiler.timings().is_empty());
+        for time in [2, 6, 4] {
+            profiler.record(String::from("trace"), ms(time));
+            profiler.record(String::from("tonemap"), ms(1));
+        }
+        profiler.record(String::from("bloom"), ms(3));
+
+        let timings = profiler.timings();
+        let names: Vec<&str> = timings.iter().map(|t| t.name.as_str()).collect();
+        assert_eq!(names, ["trace", "tonemap", "bloom"]);
+        assert_eq!(
+            timings[0],
+            PassTiming {
+                name: String::from("trace"),
+                min: ms(2),
+                avg: ms(4),
+                max: ms(6),
+                frames: 3,
+            }
+        );
+        assert_eq!(timings[1].frames, 3);
+        assert_eq!(timings[2].frames, 1);
+    }
+
+    #[test]
+    fn keeps_the_last_frames_only() {
+        let mut profiler = Profiler::new();
+        profiler.record(String::from("trace"), ms(100));
+        for _ in 0..TIMING_WINDOW - 1 {
+            profiler.record(String::from("trace"), ms(1));
+        }
+        let timings = profiler.timings();
+        assert_eq!(timings[0].frames, TIMING_WINDOW);
+        assert_eq!(timings[0].max, ms(100));
+
+        // The slow first frame falls out of the window.
+        profiler.record(String::from("trace"), ms(1));
+        let timings = profiler.timings();
+        assert_eq!(timings[0].frames, TIMING_WINDOW);
+        assert_eq!(timings[0].max, ms(1));
+        assert_eq!(timings[0].avg, ms(1));
+    }
+}
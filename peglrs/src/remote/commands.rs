@@ -0,0 +1,180 @@
+use std::path::Path;
+
+use cgmath::{Vector2, Vector3};
+use serde_json::{json, Value};
+
+use super::{RpcError, COMMAND_FAILED, INVALID_PARAMS, METHOD_NOT_FOUND};
+use crate::{utils, Scene, UniformValue};
+
+fn invalid(message: &str) -> RpcError {
+    RpcError::new(INVALID_PARAMS, message)
+}
+
+fn floats(value: &Value) -> Option<Vec<f32>> {
+    match value {
+        Value::Number(n) => Some(vec![n.as_f64()? as f32]),
+        Value::Array(values) => values
+            .iter()
+            .map(|v| v.as_f64().map(|v| v as f32))
+            .collect(),
+        _ => None,
+    }
+}
+
+// An optional vector parameter: None when missing, an error when malformed.
+fn param<T>(
+    params: &Value,
+    name: &str,
+    convert: fn(&[f32]) -> Option<T>,
+) -> Result<Option<T>, RpcError> {
+    match params.get(name) {
+        None | Some(Value::Null) => Ok(None),
+        Some(value) => floats(value)
+            .as_deref()
+            .and_then(convert)
+            .map(Some)
+            .ok_or_else(|| invalid(&format!("invalid {}", name))),
+    }
+}
+
+fn vec2(v: &[f32]) -> Option<Vector2<f32>> {
+    match v {
+        [x, y] => Some(Vector2::new(*x, *y)),
+        _ => None,
+    }
+}
+
+fn vec3(v: &[f32]) -> Option<Vector3<f32>> {
+    match v {
+        [x, y, z] => Some(Vector3::new(*x, *y, *z)),
+        _ => None,
+    }
+}
+
+fn scalar(v: &[f32]) -> Option<f32> {
+    match v {
+        [x] => Some(*x),
+        _ => None,
+    }
+}
+
+fn string<'a>(params: &'a Value, name: &str) -> Result<&'a str, RpcError> {
+    params
+        .get(name)
+        .and_then(Value::as_str)
+        .ok_or_else(|| invalid(&format!("missing {}", name)))
+}
+
+fn camera(scene: &Scene) -> Value {
+    json!({
+        "eye": [scene.eye.x, scene.eye.y, scene.eye.z],
+        "target": [scene.target.x, scene.target.y, scene.target.z],
+        "up": [scene.up.x, scene.up.y, scene.up.z],
+        "focus_pos": [scene.focus_pos.x, scene.focus_pos.y],
+        "aperture": scene.aperture,
+    })
+}
+
+// ok, pending when a file changed but wasn't reloaded yet, or failed when
+// the last reload didn't compile.
+fn program_status(scene: &Scene, program: u32) -> (Vec<String>, &'static str) {
+    let program = match scene.shader_manager.get_program(program) {
+        Some(program) => program,
+        None => return (Vec::new(), "missing"),
+    };
+    let program = program.lock().unwrap();
+    let mut pending = false;
+    let mut shaders = Vec::with_capacity(program.shaders.len());
+    for shader in &program.shaders {
+        let shader = shader.lock().unwrap();
+        pending |= utils::last_modified(Path::new(&shader.path))
+            .is_some_and(|modified| modified > shader.last_modified);
+        shaders.push(shader.path.clone());
+    }
+    let status = if program.reload_failed {
+        "failed"
+    } else if pending {
+        "pending"
+    } else {
+        "ok"
+    };
+    (shaders, status)
+}
+
+// Run a command on the scene. `changed` is set when it moved the camera.
+pub fn execute(
+    scene: &mut Scene,
+    fbo: u32,
+    method: &str,
+    params: &Value,
+    changed: &mut bool,
+) -> Result<Value, RpcError> {
+    match method {
+        "set_camera" => {
+            let eye = param(params, "eye", vec3)?.unwrap_or(scene.eye);
+            let target = param(params, "target", vec3)?.unwrap_or(scene.target);
+            let up = param(params, "up", vec3)?.unwrap_or(scene.up);
+            let focus_pos = param(params, "focus_pos", vec2)?.unwrap_or(scene.focus_pos);
+            let aperture = param(params, "aperture", scalar)?.unwrap_or(scene.aperture);
+            crate::update_camera(scene, eye, target, up, focus_pos, aperture);
+            crate::reset(scene, fbo);
+            *changed = true;
+            Ok(camera(scene))
+        }
+        "camera" => Ok(camera(scene)),
+        "set_uniform" => {
+            let name = string(params, "name")?;
+            let value = param(params, "value", UniformValue::from_floats)?
+                .ok_or_else(|| invalid("missing value"))?;
+            let value = value.matching(scene.uniforms.get(name));
+            crate::set_uniform(scene, name, value);
+            crate::reset(scene, fbo);
+            Ok(Value::Bool(true))
+        }
+        "reset" => {
+            crate::reset(scene, fbo);
+            Ok(Value::Bool(true))
+        }
+        "samples" => Ok(json!(crate::sample_count(scene))),
+        "timings" => {
+            let ms = |d: std::time::Duration| d.as_secs_f64() * 1000.0;
+            let timings: Vec<Value> = crate::pass_timings(scene)
+                .iter()
+                .map(|t| {
+                    json!({
+                        "name": t.name,
+                        "avg": ms(t.avg),
+                        "min": ms(t.min),
+                        "max": ms(t.max),
+                        "frames": t.frames,
+                    })
+                })
+                .collect();
+            Ok(Value::Array(timings))
+        }
+        "export" => {
+            let path = match &scene.remote {
+                Some(server) => server.export_path(string(params, "path")?)?,
+                None => return Err(RpcError::new(COMMAND_FAILED, "exports are disabled")),
+            };
+            crate::export_image(scene, fbo, &path)
+                .map(|()| Value::String(path.display().to_string()))
+                .ok_or_else(|| RpcError::new(COMMAND_FAILED, "couldn't save the image"))
+        }
+        "programs" => {
+            let programs: Vec<Value> = scene
+                .passes
+                .iter()
+                .map(|pass| {
+                    let (shaders, status) = program_status(scene, pass.program);
+                    json!({ "pass": pass.name, "shaders": shaders, "status": status })
+                })
+                .collect();
+            Ok(Value::Array(programs))
+        }
+        _ => Err(RpcError::new(
+            METHOD_NOT_FOUND,
+            &format!("unknown method {}", method),
+        )),
+    }
+}
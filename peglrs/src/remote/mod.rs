@@ -0,0 +1,390 @@
+pub mod commands;
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use serde_json::{json, Value};
+use tungstenite::handshake::server::{ErrorResponse, Request, Response};
+use tungstenite::http::StatusCode;
+use tungstenite::Message;
+
+// JSON-RPC 2.0 error codes.
+pub const PARSE_ERROR: i64 = -32700;
+pub const INVALID_REQUEST: i64 = -32600;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+// The command was understood but couldn't be done.
+pub const COMMAND_FAILED: i64 = -32000;
+
+// How long the listener sleeps when nobody is connecting, which is also how
+// long it takes to notice the server was dropped.
+const ACCEPT_INTERVAL: Duration = Duration::from_millis(50);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+}
+
+impl RpcError {
+    pub fn new(code: i64, message: &str) -> RpcError {
+        RpcError {
+            code,
+            message: String::from(message),
+        }
+    }
+}
+
+// A request waiting for the GL thread, which answers it with `reply`.
+#[derive(Debug)]
+pub struct Command {
+    pub method: String,
+    pub params: Value,
+    reply: Sender<Result<Value, RpcError>>,
+}
+
+impl Command {
+    pub fn reply(self, result: Result<Value, RpcError>) {
+        // The client may be gone already.
+        let _ = self.reply.send(result);
+    }
+}
+
+// Listens on a local TCP port for JSON-RPC 2.0 requests, one per line, or
+// one per message once a WebSocket handshake was made. Connections are
+// handled on their own threads, which queue the commands for the GL thread
+// and wait for it to answer.
+//
+// Anything running on the machine can connect, so the server only listens
+// on loopback addresses, only takes WebSockets from local pages and only
+// exports images into `export_dir`.
+#[derive(Debug)]
+pub struct RemoteServer {
+    addr: SocketAddr,
+    commands: Receiver<Command>,
+    stop: Arc<AtomicBool>,
+    export_dir: Option<PathBuf>,
+}
+
+impl RemoteServer {
+    // Port 0 picks a free port, see `addr`. Without `export_dir`, the export
+    // command is refused.
+    pub fn bind(addr: &str, export_dir: Option<&Path>) -> Option<RemoteServer> {
+        let addrs: Vec<SocketAddr> = match addr.to_socket_addrs() {
+            Ok(addrs) => addrs.collect(),
+            Err(err) => {
+                eprintln!("[ERR] Couldn't listen on {} : {}", addr, err);
+                return None;
+            }
+        };
+        if addrs.is_empty() || addrs.iter().any(|addr| !addr.ip().is_loopback()) {
+            eprintln!(
+                "[ERR] The remote control only listens on loopback addresses, not {}",
+                addr
+            );
+            return None;
+        }
+
+        let listener = match TcpListener::bind(&addrs[..]) {
+            Ok(listener) => listener,
+            Err(err) => {
+                eprintln!("[ERR] Couldn't listen on {} : {}", addr, err);
+                return None;
+            }
+        };
+        let addr = listener.local_addr().ok()?;
+        listener.set_nonblocking(true).ok()?;
+
+        let (sender, commands) = mpsc::channel();
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = stop.clone();
+        thread::spawn(move || {
+            while !thread_stop.load(Ordering::Relaxed) {
+                match listener.accept() {
+                    Ok((stream, _)) => {
+                        let sender = sender.clone();
+                        thread::spawn(move || serve(stream, sender));
+                    }
+                    Err(_) => thread::sleep(ACCEPT_INTERVAL),
+                }
+            }
+        });
+
+        println!("[NFO] Remote control listening on {}", addr);
+        Some(RemoteServer {
+            addr,
+            commands,
+            stop,
+            export_dir: export_dir.map(Path::to_path_buf),
+        })
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    // Where `path`, given by a client, is exported to: a file of the export
+    // directory, or one of its subdirectories.
+    pub fn export_path(&self, path: &str) -> Result<PathBuf, RpcError> {
+        let dir = self
+            .export_dir
+            .as_ref()
+            .ok_or_else(|| RpcError::new(COMMAND_FAILED, "exports are disabled"))?;
+        let path = Path::new(path);
+        let inside = path.components().next().is_some()
+            && path
+                .components()
+                .all(|component| matches!(component, Component::Normal(_)));
+        if !inside {
+            return Err(RpcError::new(
+                INVALID_PARAMS,
+                "path must be relative to the export directory",
+            ));
+        }
+        Ok(dir.join(path))
+    }
+
+    // The commands received since the last call.
+    pub fn commands(&self) -> Vec<Command> {
+        self.commands.try_iter().collect()
+    }
+}
+
+impl Drop for RemoteServer {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+fn response(id: Value, result: Result<Value, RpcError>) -> String {
+    let response = match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err(err) => json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "error": { "code": err.code, "message": err.message },
+        }),
+    };
+    response.to_string()
+}
+
+// Answer one request. None for notifications, which get no response.
+fn handle(request: &str, sender: &Sender<Command>) -> Option<String> {
+    let request: Value = match serde_json::from_str(request) {
+        Ok(request) => request,
+        Err(err) => {
+            let err = RpcError::new(PARSE_ERROR, &err.to_string());
+            return Some(response(Value::Null, Err(err)));
+        }
+    };
+    let id = request.get("id").cloned();
+    let method = match request.get("method").and_then(Value::as_str) {
+        Some(method) => String::from(method),
+        None => {
+            let err = RpcError::new(INVALID_REQUEST, "missing method");
+            return Some(response(id.unwrap_or(Value::Null), Err(err)));
+        }
+    };
+    let params = request.get("params").cloned().unwrap_or(Value::Null);
+
+    let (reply, result) = mpsc::channel();
+    let command = Command {
+        method,
+        params,
+        reply,
+    };
+    let result = match sender.send(command) {
+        Ok(()) => result
+            .recv()
+            .unwrap_or_else(|_| Err(RpcError::new(COMMAND_FAILED, "the scene is gone"))),
+        Err(_) => Err(RpcError::new(COMMAND_FAILED, "the scene is gone")),
+    };
+    id.map(|id| response(id, result))
+}
+
+fn serve(stream: TcpStream, sender: Sender<Command>) {
+    let _ = stream.set_nonblocking(false);
+    // Browsers and WebSocket libraries start with an HTTP upgrade request.
+    let mut start = [0u8; 4];
+    let is_websocket = matches!(stream.peek(&mut start), Ok(4) if &start == b"GET ");
+    if is_websocket {
+        serve_websocket(stream, sender);
+    } else {
+        serve_lines(stream, sender);
+    }
+}
+
+fn serve_lines(stream: TcpStream, sender: Sender<Command>) {
+    let mut writer = match stream.try_clone() {
+        Ok(writer) => writer,
+        Err(_) => return,
+    };
+    for (i, line) in BufReader::new(stream).lines().enumerate() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => return,
+        };
+        // Any web page can POST to a local port, with commands in the body.
+        if i == 0 && line.contains(" HTTP/1.") {
+            return;
+        }
+        if line.trim().is_empty() {
+            continue;
+        }
+        if let Some(response) = handle(&line, &sender) {
+            if writeln!(writer, "{}", response).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+// Pages served from the machine itself. Clients which aren't browsers
+// don't send an origin.
+fn is_local_origin(origin: &str) -> bool {
+    let host = match origin.split_once("://") {
+        Some((_, rest)) if rest.starts_with('[') => rest.split_inclusive(']').next(),
+        Some((_, rest)) => rest.split(':').next(),
+        None => None,
+    };
+    matches!(host, Some("localhost" | "127.0.0.1" | "[::1]"))
+}
+
+// The signature of a tungstenite handshake callback.
+#[allow(clippy::result_large_err)]
+fn check_origin(request: &Request, response: Response) -> Result<Response, ErrorResponse> {
+    let origin = match request.headers().get("Origin") {
+        Some(origin) => origin,
+        None => return Ok(response),
+    };
+    // An origin which isn't ASCII can't be a local one.
+    match origin.to_str() {
+        Ok(origin) if origin.is_empty() || is_local_origin(origin) => Ok(response),
+        _ => {
+            let origin = String::from_utf8_lossy(origin.as_bytes());
+            eprintln!("[ERR] Refused WebSocket from {}", origin);
+            let mut refused = ErrorResponse::new(Some(String::from("origin not allowed")));
+            *refused.status_mut() = StatusCode::FORBIDDEN;
+            Err(refused)
+        }
+    }
+}
+
+fn serve_websocket(stream: TcpStream, sender: Sender<Command>) {
+    let mut socket = match tungstenite::accept_hdr(stream, check_origin) {
+        Ok(socket) => socket,
+        Err(err) => {
+            eprintln!("[ERR] WebSocket handshake failed : {}", err);
+            return;
+        }
+    };
+    loop {
+        let request = match socket.read() {
+            Ok(Message::Text(text)) => text,
+            Ok(Message::Close(_)) | Err(_) => return,
+            // Pings are answered by tungstenite.
+            Ok(_) => continue,
+        };
+        if let Some(response) = handle(&request, &sender) {
+            if socket.send(Message::Text(response)).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn only_binds_loopback_addresses() {
+        assert!(RemoteServer::bind("0.0.0.0:0", None).is_none());
+        let server = RemoteServer::bind("127.0.0.1:0", None).unwrap();
+        assert!(server.addr().ip().is_loopback());
+    }
+
+    #[test]
+    fn knows_local_origins() {
+        assert!(is_local_origin("http://localhost:8000"));
+        assert!(is_local_origin("http://127.0.0.1"));
+        assert!(is_local_origin("https://[::1]:8443"));
+        assert!(!is_local_origin("https://example.com"));
+        assert!(!is_local_origin("http://localhost.example.com"));
+        assert!(!is_local_origin("null"));
+    }
+
+    // The status line the server answers a WebSocket handshake with.
+    fn handshake(addr: SocketAddr, origin: &str) -> String {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(
+            stream,
+            "GET / HTTP/1.1\r\nHost: {}\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+             Sec-WebSocket-Version: 13\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n{}\r\n",
+            addr, origin
+        )
+        .unwrap();
+        let mut response = [0u8; 12];
+        stream.read_exact(&mut response).unwrap();
+        String::from_utf8_lossy(&response).into_owned()
+    }
+
+    #[test]
+    fn refuses_websockets_from_other_origins() {
+        let server = RemoteServer::bind("127.0.0.1:0", None).unwrap();
+        let addr = server.addr();
+        assert_eq!(
+            handshake(addr, "Origin: https://example.com\r\n"),
+            "HTTP/1.1 403"
+        );
+        assert_eq!(
+            handshake(addr, "Origin: http://localhost:8000\r\n"),
+            "HTTP/1.1 101"
+        );
+        assert_eq!(
+            handshake(addr, "Origin: http://löcalhost\r\n"),
+            "HTTP/1.1 403"
+        );
+        assert_eq!(handshake(addr, ""), "HTTP/1.1 101");
+    }
+
+    #[test]
+    fn ignores_plain_http_requests() {
+        let server = RemoteServer::bind("127.0.0.1:0", None).unwrap();
+        let mut stream = TcpStream::connect(server.addr()).unwrap();
+        let request = r#"{"jsonrpc": "2.0", "id": 1, "method": "reset"}"#;
+        let post = format!("POST / HTTP/1.1\r\n\r\n{}\n", request);
+        // The server may hang up before reading everything, which resets
+        // the connection.
+        let _ = stream.write_all(post.as_bytes());
+        let mut response = String::new();
+        let _ = stream.read_to_string(&mut response);
+        assert_eq!(response, "");
+        assert!(server.commands().is_empty());
+    }
+
+    #[test]
+    fn exports_into_the_export_directory() {
+        let server = RemoteServer::bind("127.0.0.1:0", Some(Path::new("renders"))).unwrap();
+        assert_eq!(
+            server.export_path("shots/a.png"),
+            Ok(PathBuf::from("renders/shots/a.png"))
+        );
+        for path in ["/tmp/a.png", "../a.png", "shots/../../a.png", ""] {
+            assert_eq!(server.export_path(path).unwrap_err().code, INVALID_PARAMS);
+        }
+
+        let server = RemoteServer::bind("127.0.0.1:0", None).unwrap();
+        assert_eq!(
+            server.export_path("a.png").unwrap_err().code,
+            COMMAND_FAILED
+        );
+    }
+}
@@ -9,6 +9,8 @@ use std::time::SystemTime;
 
 use gl;
 
+use cgmath::{Vector2, Vector3, Vector4};
+
 #[derive(Debug)]
 pub enum ShaderType {
     VERTEX,
@@ -29,6 +31,39 @@ impl fmt::Display for ShaderType {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UniformValue {
+    Int(i32),
+    Float(f32),
+    Vec2(Vector2<f32>),
+    Vec3(Vector3<f32>),
+    Vec4(Vector4<f32>),
+}
+
+impl UniformValue {
+    // A float or a vector, from 1 to 4 numbers.
+    pub fn from_floats(v: &[f32]) -> Option<UniformValue> {
+        match v {
+            [x] => Some(UniformValue::Float(*x)),
+            [x, y] => Some(UniformValue::Vec2(Vector2::new(*x, *y))),
+            [x, y, z] => Some(UniformValue::Vec3(Vector3::new(*x, *y, *z))),
+            [x, y, z, w] => Some(UniformValue::Vec4(Vector4::new(*x, *y, *z, *w))),
+            _ => None,
+        }
+    }
+
+    // The value to replace `current` with. An int uniform stays one, rounded,
+    // as it can't be set with a float.
+    pub fn matching(self, current: Option<&UniformValue>) -> UniformValue {
+        match (self, current) {
+            (UniformValue::Float(x), Some(UniformValue::Int(_))) => {
+                UniformValue::Int(x.round() as i32)
+            }
+            (value, _) => value,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct Shader {
     pub addr: u32,
@@ -49,6 +84,9 @@ pub struct Program {
     pub addr: u32,
     pub shaders: Vec<Arc<Mutex<Shader>>>,
     pub uniforms_location: HashMap<String, i32>,
+    // The last reload didn't compile or link, the program still runs the
+    // version before it.
+    pub reload_failed: bool,
 }
 
 impl Drop for Shader {
@@ -86,3 +124,22 @@ pub fn get_gl_shader_type(shader_type: &ShaderType) -> Option<u32> {
         ShaderType::COMPUTE => Some(gl::COMPUTE_SHADER),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn int_uniforms_stay_ints() {
+        let int = UniformValue::Int(2);
+        let float = UniformValue::Float(3.6);
+        assert_eq!(float.matching(Some(&int)), UniformValue::Int(4));
+        assert_eq!(float.matching(Some(&UniformValue::Float(2.0))), float);
+        assert_eq!(float.matching(None), float);
+        let vec2 = UniformValue::from_floats(&[1.0, 2.0]).unwrap();
+        assert_eq!(vec2, UniformValue::Vec2(Vector2::new(1.0, 2.0)));
+        assert_eq!(vec2.matching(Some(&int)), vec2);
+        assert_eq!(UniformValue::from_floats(&[]), None);
+        assert_eq!(UniformValue::from_floats(&[1.0; 5]), None);
+    }
+}
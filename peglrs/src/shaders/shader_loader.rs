@@ -47,6 +47,8 @@ impl ProgramDB {
 pub struct ShaderManager {
     pub db: Arc<Mutex<ProgramDB>>,
     pub watcher: thread::JoinHandle<()>,
+    // Tells the watcher thread to stop, set when the manager is dropped.
+    stop: Arc<AtomicBool>,
     pub sender: Sender<Arc<Mutex<Program>>>,
     pub receiver: Receiver<Arc<Mutex<Program>>>,
 }
@@ -80,8 +82,11 @@ impl ShaderManager {
         let db_borrow = program_db.lock().unwrap();
         let program_borrow = &db_borrow.programs;
         for (id, program) in program_borrow.iter() {
-            if ShaderManager::check_program_for_reload(program) {
-                sender.send(program.clone()).unwrap();
+            // The manager may be gone, its thread stops on the next tick.
+            if ShaderManager::check_program_for_reload(program)
+                && sender.send(program.clone()).is_err()
+            {
+                return;
             }
         }
     }
@@ -92,14 +97,19 @@ impl ShaderManager {
 
         let db_clone = db.clone();
         let thread_sender = mpsc::Sender::clone(&sender);
-        let watcher = thread::spawn(move || loop {
-            ShaderManager::flag_program_for_reload(&db_clone, &thread_sender);
-            thread::sleep(Duration::from_millis(1000));
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = stop.clone();
+        let watcher = thread::spawn(move || {
+            while !thread_stop.load(Ordering::Relaxed) {
+                ShaderManager::flag_program_for_reload(&db_clone, &thread_sender);
+                thread::sleep(Duration::from_millis(1000));
+            }
         });
 
         ShaderManager {
             db,
             watcher,
+            stop,
             receiver,
             sender,
         }
@@ -133,16 +143,20 @@ impl ShaderManager {
         has_reload
     }
 
-    pub fn load_program(&mut self, shaders_path: &Vec<&Path>) -> u32 {
+    pub fn try_load_program(&mut self, shaders_path: &Vec<&Path>) -> Option<u32> {
         let mut shaders: Vec<Arc<Mutex<Shader>>> = Vec::with_capacity(shaders_path.len());
         for shader_path in shaders_path {
-            let shd = Shader::load_shader(shader_path).unwrap();
+            let shd = Shader::load_shader(shader_path)?;
             shaders.push(Arc::new(Mutex::new(shd)));
         }
 
-        let program = Program::load_program(&shaders).unwrap();
+        let program = Program::load_program(&shaders)?;
         let mut db = self.db.lock().unwrap();
-        db.add(Arc::new(Mutex::new(program)))
+        Some(db.add(Arc::new(Mutex::new(program))))
+    }
+
+    pub fn load_program(&mut self, shaders_path: &Vec<&Path>) -> u32 {
+        self.try_load_program(shaders_path).unwrap()
     }
 
     pub fn rm_program(&mut self, id: u32) -> Option<Arc<Mutex<Program>>> {
@@ -160,6 +174,17 @@ impl ShaderManager {
     }
 }
 
+impl Drop for ShaderManager {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        // The programs must be deleted here, where the GL context is current,
+        // and not by the watcher thread when it lets go of the db. It only
+        // sends programs while holding the lock, so none come after this.
+        self.db.lock().unwrap().programs.clear();
+        self.receiver.try_iter().count();
+    }
+}
+
 impl Shader {
     fn parse_uniforms(src: &str) -> Vec<String> {
         let mut uniforms: Vec<String> = Vec::new();
@@ -252,13 +277,15 @@ impl Shader {
         })
     }
 
-    pub fn reload(&mut self) {
+    // Returns false if the new source couldn't be compiled, keeping the old
+    // shader.
+    pub fn reload(&mut self) -> bool {
         let path = Path::new(&self.path);
         let src = utils::load_file(path);
         if src.is_none() {
             #[cfg(feature = "debug")]
             eprintln!("[ERR] Couldn't load source for shader {}", path.display());
-            return;
+            return false;
         }
 
         let src = src.unwrap();
@@ -270,8 +297,10 @@ impl Shader {
                 self.addr = addr;
                 self.last_modified = stat.modified().unwrap();
             }
+            true
         } else {
             eprintln!("[ERR] Couldn't reload shader {}", path.display());
+            false
         }
     }
 }
@@ -295,6 +324,12 @@ impl Program {
         }
     }
 
+    pub fn set_u32(&self, name: &str, value: u32) {
+        unsafe {
+            gl::Uniform1ui(self.uniforms_location[name], value);
+        }
+    }
+
     pub fn set_float(&self, name: &str, value: f32) {
         unsafe {
             gl::Uniform1f(self.uniforms_location[name], value);
@@ -325,6 +360,21 @@ impl Program {
         }
     }
 
+    // Set a uniform if the program uses it, ignore it otherwise.
+    pub fn set_uniform(&self, name: &str, value: &UniformValue) {
+        if !self.uniforms_location.contains_key(name) {
+            return;
+        }
+
+        match value {
+            UniformValue::Int(v) => self.set_i32(name, *v),
+            UniformValue::Float(v) => self.set_float(name, *v),
+            UniformValue::Vec2(v) => self.set_vec2(name, v),
+            UniformValue::Vec3(v) => self.set_vec3(name, v),
+            UniformValue::Vec4(v) => self.set_vec4(name, v),
+        }
+    }
+
     pub fn link_program(shaders: &Vec<Arc<Mutex<Shader>>>) -> Option<u32> {
         unsafe {
             let addr = gl::CreateProgram();
@@ -374,6 +424,7 @@ impl Program {
                 addr,
                 shaders: Vec::with_capacity(shaders.len()),
                 uniforms_location: HashMap::new(),
+                reload_failed: false,
             };
 
             for shader in shaders.into_iter() {
@@ -395,12 +446,14 @@ impl Program {
     }
 
     pub fn reload(&mut self) {
+        let mut failed = false;
         for shader in &self.shaders {
             let mut shad = shader.lock().unwrap();
-            shad.reload();
+            failed |= !shad.reload();
         }
 
         let program_addr = Program::link_program(&self.shaders);
+        self.reload_failed = failed || program_addr.is_none();
         if let Some(addr) = program_addr {
             unsafe {
                 gl::DeleteProgram(self.addr);
@@ -0,0 +1,251 @@
+use std::f32::consts::PI;
+use std::path::{Path, PathBuf};
+
+use crate::utils::FileWatcher;
+
+// Same weights as the path tracer uses for the luminance.
+const LUMA: [f32; 3] = [0.2126, 0.7152, 0.0722];
+
+// An equirectangular environment map lighting the scene, with the tables to
+// pick directions in proportion to how much light comes from them.
+//
+// Rows go from the bottom (straight down) to the top (straight up). A pixel
+// is picked with probability luminance * sin(theta) / total, sin(theta)
+// accounting for the rows near the poles covering less of the sphere.
+#[derive(Debug)]
+pub struct EnvMap {
+    pub path: PathBuf,
+    pub width: u32,
+    pub height: u32,
+    // Sum of luminance * sin(theta) over the pixels. 0 for a black map,
+    // which can't be importance sampled.
+    pub total: f32,
+    // RGB32F radiance.
+    pub texture: u32,
+    // R32F, for every row the CDF of its pixels.
+    pub conditional: u32,
+    // R32F, height x 1, the CDF of the rows.
+    pub marginal: u32,
+    watcher: FileWatcher,
+}
+
+// The CDFs of an image, built on the CPU.
+struct Distribution {
+    conditional: Vec<f32>,
+    marginal: Vec<f32>,
+    total: f32,
+}
+
+fn distribution(width: usize, height: usize, rgb: &[f32]) -> Distribution {
+    let mut conditional = vec![0.0; width * height];
+    let mut row_sums = vec![0.0; height];
+    for y in 0..height {
+        let theta = PI * (1.0 - (y as f32 + 0.5) / height as f32);
+        let row = &mut conditional[y * width..(y + 1) * width];
+        let mut sum = 0.0;
+        for (x, cdf) in row.iter_mut().enumerate() {
+            let p = &rgb[(y * width + x) * 3..];
+            let luma = LUMA[0] * p[0] + LUMA[1] * p[1] + LUMA[2] * p[2];
+            sum += luma.max(0.0) * theta.sin();
+            *cdf = sum;
+        }
+        normalize(row, sum);
+        row_sums[y] = sum;
+    }
+
+    let mut marginal = Vec::with_capacity(height);
+    let mut total = 0.0;
+    for sum in &row_sums {
+        total += sum;
+        marginal.push(total);
+    }
+    normalize(&mut marginal, total);
+
+    Distribution {
+        conditional,
+        marginal,
+        total,
+    }
+}
+
+// Scale a running sum to a CDF ending at 1. A sum of 0 becomes a uniform
+// CDF, so rows that are all black are never picked but still search fine.
+fn normalize(cdf: &mut [f32], sum: f32) {
+    let count = cdf.len() as f32;
+    for (i, v) in cdf.iter_mut().enumerate() {
+        *v = if sum > 0.0 {
+            *v / sum
+        } else {
+            (i + 1) as f32 / count
+        };
+    }
+    if let Some(last) = cdf.last_mut() {
+        *last = 1.0;
+    }
+}
+
+unsafe fn upload(texture: u32, internal: u32, format: u32, width: u32, height: u32, data: &[f32]) {
+    gl::BindTexture(gl::TEXTURE_2D, texture);
+    gl::PixelStorei(gl::UNPACK_ALIGNMENT, 4);
+    gl::TexImage2D(
+        gl::TEXTURE_2D,
+        0,
+        internal as i32,
+        width as i32,
+        height as i32,
+        0,
+        format,
+        gl::FLOAT,
+        data.as_ptr() as *const _,
+    );
+    // Read with texelFetch, the pixel picked must be the one whose
+    // probability is known.
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST as i32);
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as i32);
+    gl::BindTexture(gl::TEXTURE_2D, 0);
+}
+
+impl EnvMap {
+    pub fn load(path: &Path) -> Option<EnvMap> {
+        let mut textures = [0u32; 3];
+        unsafe {
+            gl::GenTextures(3, textures.as_mut_ptr());
+        }
+        let mut env = EnvMap {
+            path: path.to_path_buf(),
+            width: 0,
+            height: 0,
+            total: 0.0,
+            texture: textures[0],
+            conditional: textures[1],
+            marginal: textures[2],
+            watcher: FileWatcher::new(path),
+        };
+        // Dropping it deletes the textures if the image is broken.
+        env.upload()?;
+        Some(env)
+    }
+
+    fn upload(&mut self) -> Option<()> {
+        let img = super::open(&self.path)?;
+        let (width, height) = (img.width(), img.height());
+        let rgb = img.to_rgb32f().into_raw();
+        let dist = distribution(width as usize, height as usize, &rgb);
+
+        unsafe {
+            upload(self.texture, gl::RGB32F, gl::RGB, width, height, &rgb);
+            upload(
+                self.conditional,
+                gl::R32F,
+                gl::RED,
+                width,
+                height,
+                &dist.conditional,
+            );
+            upload(self.marginal, gl::R32F, gl::RED, height, 1, &dist.marginal);
+        }
+        self.width = width;
+        self.height = height;
+        self.total = dist.total;
+        Some(())
+    }
+
+    // Load the image again if its file changed. A broken file keeps the old
+    // one.
+    pub fn handle_reload(&mut self) -> bool {
+        if !self.watcher.changed() {
+            return false;
+        }
+
+        println!("[NFO] Reloading environment {}", self.path.display());
+        self.upload().is_some()
+    }
+}
+
+impl Drop for EnvMap {
+    fn drop(&mut self) {
+        let textures = [self.texture, self.conditional, self.marginal];
+        unsafe {
+            gl::DeleteTextures(3, textures.as_ptr());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: &[f32], b: &[f32]) {
+        assert_eq!(a.len(), b.len());
+        for (x, y) in a.iter().zip(b) {
+            assert!((x - y).abs() < 1e-5, "{:?} != {:?}", a, b);
+        }
+    }
+
+    // A grey image, with `bright` pixels set to another luminance.
+    fn image(width: usize, height: usize, grey: f32, bright: &[(usize, f32)]) -> Vec<f32> {
+        let mut rgb = vec![grey; width * height * 3];
+        for (pixel, value) in bright {
+            rgb[pixel * 3..pixel * 3 + 3].fill(*value);
+        }
+        rgb
+    }
+
+    #[test]
+    fn a_uniform_map_has_linear_cdfs() {
+        // With two rows both are as far from the equator.
+        let dist = distribution(4, 2, &image(4, 2, 1.0, &[]));
+        assert_close(
+            &dist.conditional,
+            &[0.25, 0.5, 0.75, 1.0, 0.25, 0.5, 0.75, 1.0],
+        );
+        assert_close(&dist.marginal, &[0.5, 1.0]);
+        assert!((dist.total - 8.0 * (PI / 4.0).sin()).abs() < 1e-5);
+    }
+
+    #[test]
+    fn a_bright_pixel_takes_most_of_its_row_and_the_map() {
+        let dist = distribution(4, 2, &image(4, 2, 0.01, &[(5, 100.0)]));
+        let row = &dist.conditional[4..];
+        assert!(row[0] < 0.001);
+        assert!(row[1] > 0.999);
+        assert_eq!(row[3], 1.0);
+        assert!(dist.marginal[0] < 0.001);
+        assert_eq!(dist.marginal[1], 1.0);
+    }
+
+    #[test]
+    fn black_rows_and_maps_stay_finite() {
+        let mut rgb = image(2, 2, 1.0, &[]);
+        rgb[..6].fill(0.0);
+        let dist = distribution(2, 2, &rgb);
+        assert_close(&dist.conditional[..2], &[0.5, 1.0]);
+        assert_close(&dist.marginal, &[0.0, 1.0]);
+
+        let dist = distribution(2, 2, &image(2, 2, 0.0, &[]));
+        assert_eq!(dist.total, 0.0);
+        assert_close(&dist.conditional, &[0.5, 1.0, 0.5, 1.0]);
+        assert_close(&dist.marginal, &[0.5, 1.0]);
+    }
+
+    #[test]
+    fn rows_are_weighted_by_sin_theta() {
+        let height = 4;
+        let dist = distribution(1, height, &image(1, height, 1.0, &[]));
+        let weights: Vec<f32> = (0..height)
+            .map(|y| (PI * (1.0 - (y as f32 + 0.5) / height as f32)).sin())
+            .collect();
+        let total: f32 = weights.iter().sum();
+        let mut sum = 0.0;
+        let expected: Vec<f32> = weights
+            .iter()
+            .map(|w| {
+                sum += w / total;
+                sum
+            })
+            .collect();
+        assert_close(&dist.marginal, &expected);
+        // The rows next to the equator weigh more than the ones at the poles.
+        assert!(dist.marginal[0] < dist.marginal[1] - dist.marginal[0]);
+    }
+}
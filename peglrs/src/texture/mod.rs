@@ -0,0 +1,224 @@
+pub mod envmap;
+pub mod world;
+
+use std::path::{Path, PathBuf};
+
+use crate::utils::FileWatcher;
+
+// Pixels decoded from an image file, bottom row first like GL expects.
+enum Pixels {
+    Rgba8(Vec<u8>),
+    RgbF32(Vec<f32>),
+}
+
+struct Decoded {
+    width: u32,
+    height: u32,
+    pixels: Pixels,
+}
+
+// HDR and EXR images hold linear float values.
+fn is_float(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("hdr") || ext.eq_ignore_ascii_case("exr"))
+}
+
+// Open an image, bottom row first.
+fn open(path: &Path) -> Option<image::DynamicImage> {
+    match image::open(path) {
+        Ok(img) => Some(img.flipv()),
+        Err(err) => {
+            eprintln!("[ERR] Couldn't load image {} : {}", path.display(), err);
+            None
+        }
+    }
+}
+
+fn decode(path: &Path) -> Option<Decoded> {
+    let img = open(path)?;
+    let (width, height) = (img.width(), img.height());
+    let pixels = if is_float(path) {
+        Pixels::RgbF32(img.to_rgb32f().into_raw())
+    } else {
+        Pixels::Rgba8(img.to_rgba8().into_raw())
+    };
+    Some(Decoded {
+        width,
+        height,
+        pixels,
+    })
+}
+
+// A 2D texture loaded from a PNG, JPEG, HDR or EXR image, with mipmaps. 8
+// bits images are color in sRGB unless said otherwise, so sampling them gives
+// linear values. HDR and EXR images are always linear.
+#[derive(Debug)]
+pub struct Texture {
+    pub id: u32,
+    pub width: u32,
+    pub height: u32,
+    pub path: PathBuf,
+    pub srgb: bool,
+    watcher: FileWatcher,
+}
+
+impl Texture {
+    pub fn load(path: &Path, srgb: bool) -> Option<Texture> {
+        let decoded = decode(path)?;
+        let mut id = 0;
+        unsafe {
+            gl::GenTextures(1, &mut id);
+        }
+
+        let mut texture = Texture {
+            id,
+            width: 0,
+            height: 0,
+            path: path.to_path_buf(),
+            srgb,
+            watcher: FileWatcher::new(path),
+        };
+        texture.upload(&decoded);
+        Some(texture)
+    }
+
+    fn upload(&mut self, decoded: &Decoded) {
+        self.width = decoded.width;
+        self.height = decoded.height;
+        let (internal, format, kind, data) = match &decoded.pixels {
+            Pixels::Rgba8(data) => {
+                let internal = if self.srgb {
+                    gl::SRGB8_ALPHA8
+                } else {
+                    gl::RGBA8
+                };
+                (
+                    internal,
+                    gl::RGBA,
+                    gl::UNSIGNED_BYTE,
+                    data.as_ptr() as *const _,
+                )
+            }
+            Pixels::RgbF32(data) => (gl::RGB32F, gl::RGB, gl::FLOAT, data.as_ptr() as *const _),
+        };
+
+        unsafe {
+            gl::BindTexture(gl::TEXTURE_2D, self.id);
+            gl::PixelStorei(gl::UNPACK_ALIGNMENT, 1);
+            gl::TexImage2D(
+                gl::TEXTURE_2D,
+                0,
+                internal as i32,
+                decoded.width as i32,
+                decoded.height as i32,
+                0,
+                format,
+                kind,
+                data,
+            );
+            gl::GenerateMipmap(gl::TEXTURE_2D);
+            gl::TexParameteri(
+                gl::TEXTURE_2D,
+                gl::TEXTURE_MIN_FILTER,
+                gl::LINEAR_MIPMAP_LINEAR as i32,
+            );
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as i32);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::REPEAT as i32);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::REPEAT as i32);
+            gl::PixelStorei(gl::UNPACK_ALIGNMENT, 4);
+            gl::BindTexture(gl::TEXTURE_2D, 0);
+        }
+    }
+
+    // Load the image again if its file changed. The texture keeps its id, so
+    // passes using it don't need to know. A broken file keeps the old image.
+    pub fn handle_reload(&mut self) -> bool {
+        if !self.watcher.changed() {
+            return false;
+        }
+
+        println!("[NFO] Reloading texture {}", self.path.display());
+        match decode(&self.path) {
+            Some(decoded) => {
+                self.upload(&decoded);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl Drop for Texture {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteTextures(1, &self.id);
+        }
+    }
+}
+
+// Every texture used by the passes, each image being loaded once. Indexes
+// stay valid until the texture is evicted, then the slot gets reused.
+#[derive(Debug, Default)]
+pub struct TextureManager {
+    textures: Vec<Option<Texture>>,
+}
+
+impl TextureManager {
+    pub fn new() -> TextureManager {
+        TextureManager {
+            textures: Vec::new(),
+        }
+    }
+
+    // The index of the texture for an image, loading it the first time.
+    pub fn get_or_load(&mut self, path: &Path, srgb: bool) -> Option<usize> {
+        let found = self.textures.iter().position(|texture| {
+            texture
+                .as_ref()
+                .is_some_and(|texture| texture.path == path && texture.srgb == srgb)
+        });
+        if found.is_some() {
+            return found;
+        }
+
+        println!("[NFO] Loading texture {}", path.display());
+        let texture = Some(Texture::load(path, srgb)?);
+        match self.textures.iter().position(Option::is_none) {
+            Some(idx) => {
+                self.textures[idx] = texture;
+                Some(idx)
+            }
+            None => {
+                self.textures.push(texture);
+                Some(self.textures.len() - 1)
+            }
+        }
+    }
+
+    pub fn get(&self, idx: usize) -> Option<&Texture> {
+        self.textures.get(idx).and_then(Option::as_ref)
+    }
+
+    // Free the textures which aren't in `used`, and stop watching their
+    // files.
+    pub fn evict_unused(&mut self, used: &[usize]) {
+        for (idx, slot) in self.textures.iter_mut().enumerate() {
+            if used.contains(&idx) {
+                continue;
+            }
+            if let Some(texture) = slot.take() {
+                println!("[NFO] Unloading texture {}", texture.path.display());
+            }
+        }
+    }
+
+    // Reload the textures whose file changed. Returns true if any was.
+    pub fn handle_reload(&mut self) -> bool {
+        let mut reloaded = false;
+        for texture in self.textures.iter_mut().flatten() {
+            reloaded |= texture.handle_reload();
+        }
+        reloaded
+    }
+}
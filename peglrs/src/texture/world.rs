@@ -0,0 +1,96 @@
+use std::path::Path;
+
+use crate::utils::FileWatcher;
+use crate::world::World;
+
+// The objects and materials of a world in an RGBA32F texture, read by the
+// path tracer with texelFetch: row 0 holds the materials, row 1 the objects.
+#[derive(Debug)]
+pub struct WorldTexture {
+    pub world: World,
+    pub texture: u32,
+    pub objects: u32,
+    // Set when the world comes from a file, to reload it when it changes.
+    watcher: Option<FileWatcher>,
+}
+
+impl WorldTexture {
+    pub fn new(world: World) -> Option<WorldTexture> {
+        let mut texture = 0;
+        unsafe {
+            gl::GenTextures(1, &mut texture);
+        }
+        let mut tex = WorldTexture {
+            world: World::empty(),
+            texture,
+            objects: 0,
+            watcher: None,
+        };
+        tex.upload(world)?;
+        Some(tex)
+    }
+
+    pub fn load(path: &Path) -> Option<WorldTexture> {
+        let mut tex = WorldTexture::new(World::load(path)?)?;
+        tex.watcher = Some(FileWatcher::new(path));
+        Some(tex)
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.watcher.as_ref().map(|watcher| watcher.path.as_path())
+    }
+
+    // Replace what the texture holds. A world that can't be encoded keeps
+    // the old one.
+    pub fn upload(&mut self, world: World) -> Option<()> {
+        let encoded = world.encode()?;
+        let width = encoded.materials.len().max(encoded.objects.len()).max(1);
+        let mut texels = vec![[0.0f32; 4]; width * 2];
+        texels[..encoded.materials.len()].copy_from_slice(&encoded.materials);
+        texels[width..width + encoded.objects.len()].copy_from_slice(&encoded.objects);
+
+        unsafe {
+            gl::BindTexture(gl::TEXTURE_2D, self.texture);
+            gl::PixelStorei(gl::UNPACK_ALIGNMENT, 4);
+            gl::TexImage2D(
+                gl::TEXTURE_2D,
+                0,
+                gl::RGBA32F as i32,
+                width as i32,
+                2,
+                0,
+                gl::RGBA,
+                gl::FLOAT,
+                texels.as_ptr() as *const _,
+            );
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST as i32);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as i32);
+            gl::BindTexture(gl::TEXTURE_2D, 0);
+        }
+        self.objects = world.object_count() as u32;
+        self.world = world;
+        Some(())
+    }
+
+    // Load the file again if it changed. A broken file keeps the old world.
+    pub fn handle_reload(&mut self) -> bool {
+        let path = match &self.watcher {
+            Some(watcher) if watcher.changed() => watcher.path.clone(),
+            _ => return false,
+        };
+
+        println!("[NFO] Reloading scene {}", path.display());
+        match World::load(&path) {
+            Some(world) => self.upload(world).is_some(),
+            None => false,
+        }
+    }
+}
+
+impl Drop for WorldTexture {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteTextures(1, &self.texture);
+        }
+    }
+}
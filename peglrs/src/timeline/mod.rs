@@ -0,0 +1,501 @@
+pub mod recording;
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use cgmath::{InnerSpace, Vector2, Vector3};
+use serde::{Deserialize, Serialize};
+
+use crate::shaders::UniformValue;
+use crate::utils;
+
+// How the value goes from a keyframe to the next one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Interpolation {
+    // Keep the value until the next keyframe.
+    Step,
+    #[default]
+    Linear,
+    // Smooth curve through the keyframes, tangents from their neighbours.
+    CatmullRom,
+    // Cubic curve shaped by the handles of the keyframes. Without handles it
+    // is the same curve as CatmullRom.
+    Bezier,
+}
+
+// How time flows from a keyframe to the next one: `in` starts slowly, `out`
+// arrives slowly.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Easing {
+    #[default]
+    Linear,
+    In,
+    Out,
+    InOut,
+}
+
+impl Easing {
+    fn apply(self, t: f32) -> f32 {
+        match self {
+            Easing::Linear => t,
+            Easing::In => t * t * t,
+            Easing::Out => 1.0 - (1.0 - t).powi(3),
+            Easing::InOut => t * t * (3.0 - 2.0 * t),
+        }
+    }
+}
+
+fn is_default<T: Default + PartialEq>(value: &T) -> bool {
+    *value == T::default()
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Keyframe {
+    pub time: f32,
+    pub value: Vec<f32>,
+    // Both apply to the way to the next keyframe.
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub interpolation: Interpolation,
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub easing: Easing,
+    // Bezier control points, as offsets from the value, before and after
+    // the keyframe.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub in_handle: Option<Vec<f32>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub out_handle: Option<Vec<f32>>,
+}
+
+impl Keyframe {
+    pub fn new(time: f32, value: Vec<f32>, interpolation: Interpolation) -> Keyframe {
+        Keyframe {
+            time,
+            value,
+            interpolation,
+            easing: Easing::Linear,
+            in_handle: None,
+            out_handle: None,
+        }
+    }
+}
+
+// The keyframes of one value, sorted by time. Every value has the same
+// number of components.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Track {
+    pub keys: Vec<Keyframe>,
+}
+
+fn lerp(a: &[f32], b: &[f32], t: f32) -> Vec<f32> {
+    a.iter().zip(b).map(|(a, b)| a + (b - a) * t).collect()
+}
+
+fn component(v: Option<&Vec<f32>>, i: usize) -> Option<f32> {
+    v.and_then(|v| v.get(i)).copied()
+}
+
+impl Track {
+    // Add a keyframe, replacing the one at the same time if any.
+    pub fn insert(&mut self, key: Keyframe) {
+        match self.keys.iter().position(|k| k.time >= key.time) {
+            Some(idx) if self.keys[idx].time == key.time => self.keys[idx] = key,
+            Some(idx) => self.keys.insert(idx, key),
+            None => self.keys.push(key),
+        }
+    }
+
+    fn dimension(&self) -> usize {
+        self.keys.first().map_or(0, |key| key.value.len())
+    }
+
+    // Slope of the curve at a keyframe, per second, from its neighbours.
+    fn tangent(&self, idx: usize, i: usize) -> f32 {
+        let prev = &self.keys[idx.saturating_sub(1)];
+        let next = &self.keys[(idx + 1).min(self.keys.len() - 1)];
+        let dt = next.time - prev.time;
+        if dt <= 0.0 {
+            return 0.0;
+        }
+        (next.value[i] - prev.value[i]) / dt
+    }
+
+    pub fn sample(&self, time: f32) -> Option<Vec<f32>> {
+        let first = self.keys.first()?;
+        let last = self.keys.last()?;
+        if time <= first.time {
+            return Some(first.value.clone());
+        }
+        if time >= last.time {
+            return Some(last.value.clone());
+        }
+
+        let idx = self.keys.iter().rposition(|key| key.time <= time)?;
+        let (a, b) = (&self.keys[idx], &self.keys[idx + 1]);
+        let dt = b.time - a.time;
+        let t = a.easing.apply((time - a.time) / dt);
+
+        let value = match a.interpolation {
+            Interpolation::Step => a.value.clone(),
+            Interpolation::Linear => lerp(&a.value, &b.value, t),
+            Interpolation::CatmullRom | Interpolation::Bezier => (0..self.dimension())
+                .map(|i| {
+                    // Bezier control points, a third of the tangents away by
+                    // default, which is the Catmull-Rom curve.
+                    let bezier = a.interpolation == Interpolation::Bezier;
+                    let out_handle = component(a.out_handle.as_ref(), i).filter(|_| bezier);
+                    let in_handle = component(b.in_handle.as_ref(), i).filter(|_| bezier);
+                    let p0 = a.value[i];
+                    let p3 = b.value[i];
+                    let p1 = p0 + out_handle.unwrap_or(self.tangent(idx, i) * dt / 3.0);
+                    let p2 = p3 + in_handle.unwrap_or(-self.tangent(idx + 1, i) * dt / 3.0);
+                    let u = 1.0 - t;
+                    u * u * u * p0 + 3.0 * u * u * t * p1 + 3.0 * u * t * t * p2 + t * t * t * p3
+                })
+                .collect(),
+        };
+        Some(value)
+    }
+
+    pub fn end(&self) -> f32 {
+        self.keys.last().map_or(0.0, |key| key.time)
+    }
+
+    // Sort the keyframes of a track read from a file, and check their values
+    // can be interpolated.
+    fn validate(&mut self, name: &str) -> Option<()> {
+        if self.keys.iter().any(|key| !key.time.is_finite()) {
+            eprintln!("[ERR] Track {} has a keyframe without a valid time", name);
+            return None;
+        }
+        self.keys.sort_by(|a, b| a.time.total_cmp(&b.time));
+
+        let dimension = self.dimension();
+        if dimension == 0 && !self.keys.is_empty() {
+            eprintln!("[ERR] Track {} has a keyframe without a value", name);
+            return None;
+        }
+        if let Some(key) = self.keys.iter().find(|key| key.value.len() != dimension) {
+            eprintln!(
+                "[ERR] Track {} has {} values at {}s, expected {}",
+                name,
+                key.value.len(),
+                key.time,
+                dimension
+            );
+            return None;
+        }
+        Some(())
+    }
+}
+
+// The values of a timeline at some time. Values without a track are None.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TimelineState {
+    pub eye: Option<Vector3<f32>>,
+    pub target: Option<Vector3<f32>>,
+    pub up: Option<Vector3<f32>>,
+    pub focus_pos: Option<Vector2<f32>>,
+    pub aperture: Option<f32>,
+    pub uniforms: Vec<(String, UniformValue)>,
+}
+
+fn vec2(v: &[f32]) -> Option<Vector2<f32>> {
+    match v {
+        [x, y] => Some(Vector2::new(*x, *y)),
+        _ => None,
+    }
+}
+
+fn vec3(v: &[f32]) -> Option<Vector3<f32>> {
+    match v {
+        [x, y, z] => Some(Vector3::new(*x, *y, *z)),
+        _ => None,
+    }
+}
+
+// Keyframed camera and uniforms, to play fly-throughs and animate shader
+// parameters. Saved as TOML.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Timeline {
+    // Start over once the last keyframe is reached.
+    #[serde(default, rename = "loop")]
+    pub looping: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub eye: Option<Track>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<Track>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub up: Option<Track>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub focus_pos: Option<Track>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub aperture: Option<Track>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub uniforms: BTreeMap<String, Track>,
+}
+
+impl Timeline {
+    pub fn new() -> Timeline {
+        Timeline::default()
+    }
+
+    pub fn load(path: &Path) -> Option<Timeline> {
+        let src = utils::load_file(path)?;
+        let mut timeline: Timeline = match toml::from_str(&src) {
+            Ok(timeline) => timeline,
+            Err(err) => {
+                eprintln!("[ERR] Couldn't parse timeline {} : {}", path.display(), err);
+                return None;
+            }
+        };
+        timeline.validate()?;
+        Some(timeline)
+    }
+
+    fn validate(&mut self) -> Option<()> {
+        let camera = [
+            ("eye", &mut self.eye),
+            ("target", &mut self.target),
+            ("up", &mut self.up),
+            ("focus_pos", &mut self.focus_pos),
+            ("aperture", &mut self.aperture),
+        ];
+        for (name, track) in camera {
+            if let Some(track) = track {
+                track.validate(name)?;
+            }
+        }
+        for (name, track) in &mut self.uniforms {
+            track.validate(name)?;
+        }
+        Some(())
+    }
+
+    pub fn save(&self, path: &Path) -> Option<()> {
+        // Going through a Value puts the tables after the plain values, as
+        // TOML wants.
+        let src = toml::Value::try_from(self).and_then(|value| toml::to_string(&value));
+        let res = match src {
+            Ok(src) => fs::write(path, src).map_err(|err| err.to_string()),
+            Err(err) => Err(err.to_string()),
+        };
+        match res {
+            Ok(()) => Some(()),
+            Err(err) => {
+                eprintln!("[ERR] Couldn't save timeline {} : {}", path.display(), err);
+                None
+            }
+        }
+    }
+
+    fn tracks(&self) -> Vec<&Track> {
+        let camera = [
+            &self.eye,
+            &self.target,
+            &self.up,
+            &self.focus_pos,
+            &self.aperture,
+        ];
+        camera
+            .iter()
+            .filter_map(|track| track.as_ref())
+            .chain(self.uniforms.values())
+            .collect()
+    }
+
+    // Time of the last keyframe.
+    pub fn duration(&self) -> f32 {
+        self.tracks()
+            .into_iter()
+            .map(Track::end)
+            .fold(0.0, f32::max)
+    }
+
+    // Key the whole camera at `time`, on a smooth curve through the other
+    // keyframes.
+    pub fn key_camera(
+        &mut self,
+        time: f32,
+        eye: Vector3<f32>,
+        target: Vector3<f32>,
+        up: Vector3<f32>,
+        focus_pos: Vector2<f32>,
+        aperture: f32,
+    ) {
+        let key = |track: &mut Option<Track>, value: Vec<f32>| {
+            track
+                .get_or_insert_with(Track::default)
+                .insert(Keyframe::new(time, value, Interpolation::CatmullRom));
+        };
+        key(&mut self.eye, vec![eye.x, eye.y, eye.z]);
+        key(&mut self.target, vec![target.x, target.y, target.z]);
+        key(&mut self.up, vec![up.x, up.y, up.z]);
+        key(&mut self.focus_pos, vec![focus_pos.x, focus_pos.y]);
+        key(&mut self.aperture, vec![aperture]);
+    }
+
+    pub fn key_uniform(
+        &mut self,
+        time: f32,
+        name: &str,
+        value: Vec<f32>,
+        interpolation: Interpolation,
+    ) {
+        self.uniforms
+            .entry(String::from(name))
+            .or_default()
+            .insert(Keyframe::new(time, value, interpolation));
+    }
+
+    pub fn sample(&self, time: f32) -> TimelineState {
+        let duration = self.duration();
+        let time = if self.looping && duration > 0.0 {
+            time.rem_euclid(duration)
+        } else {
+            time
+        };
+        let sample = |track: &Option<Track>| track.as_ref().and_then(|track| track.sample(time));
+
+        TimelineState {
+            eye: sample(&self.eye).as_deref().and_then(vec3),
+            target: sample(&self.target).as_deref().and_then(vec3),
+            up: sample(&self.up)
+                .as_deref()
+                .and_then(vec3)
+                .map(|up| up.normalize()),
+            focus_pos: sample(&self.focus_pos).as_deref().and_then(vec2),
+            aperture: sample(&self.aperture).and_then(|v| v.first().copied()),
+            uniforms: self
+                .uniforms
+                .iter()
+                .filter_map(|(name, track)| {
+                    let value = UniformValue::from_floats(&track.sample(time)?)?;
+                    Some((name.clone(), value))
+                })
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn track(keys: &[(f32, f32)], interpolation: Interpolation) -> Track {
+        Track {
+            keys: keys
+                .iter()
+                .map(|(time, value)| Keyframe::new(*time, vec![*value], interpolation))
+                .collect(),
+        }
+    }
+
+    fn sample(track: &Track, time: f32) -> f32 {
+        track.sample(time).unwrap()[0]
+    }
+
+    #[test]
+    fn holds_the_first_and_last_values() {
+        let track = track(&[(1.0, 2.0), (3.0, 4.0)], Interpolation::Linear);
+        assert_eq!(sample(&track, 0.0), 2.0);
+        assert_eq!(sample(&track, 5.0), 4.0);
+        assert_eq!(Track::default().sample(1.0), None);
+    }
+
+    #[test]
+    fn interpolates_between_keyframes() {
+        let keys = [(0.0, 0.0), (2.0, 4.0)];
+        assert_eq!(sample(&track(&keys, Interpolation::Step), 1.5), 0.0);
+        assert_eq!(sample(&track(&keys, Interpolation::Linear), 0.5), 1.0);
+
+        // On a straight line, the smooth curves are the line too.
+        let keys = [(0.0, 0.0), (1.0, 1.0), (2.0, 2.0), (3.0, 3.0)];
+        let smooth = track(&keys, Interpolation::CatmullRom);
+        assert!((sample(&smooth, 1.5) - 1.5).abs() < 1e-5);
+        let bezier = track(&keys, Interpolation::Bezier);
+        assert_eq!(sample(&bezier, 1.5), sample(&smooth, 1.5));
+    }
+
+    #[test]
+    fn passes_through_every_keyframe() {
+        let keys = [(0.0, 1.0), (1.0, -2.0), (2.5, 3.0), (4.0, 0.5)];
+        let track = track(&keys, Interpolation::CatmullRom);
+        for (time, value) in &keys {
+            assert!((sample(&track, *time) - value).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn uses_the_bezier_handles() {
+        let mut track = track(&[(0.0, 0.0), (1.0, 0.0)], Interpolation::Bezier);
+        track.keys[0].out_handle = Some(vec![1.0]);
+        track.keys[1].in_handle = Some(vec![1.0]);
+        assert!((sample(&track, 0.5) - 0.75).abs() < 1e-5);
+    }
+
+    #[test]
+    fn eases_the_time() {
+        let mut track = track(&[(0.0, 0.0), (1.0, 1.0)], Interpolation::Linear);
+        track.keys[0].easing = Easing::In;
+        assert!((sample(&track, 0.5) - 0.125).abs() < 1e-5);
+        track.keys[0].easing = Easing::Out;
+        assert!((sample(&track, 0.5) - 0.875).abs() < 1e-5);
+    }
+
+    #[test]
+    fn insert_keeps_the_keys_sorted() {
+        let mut track = track(&[(0.0, 0.0), (2.0, 2.0)], Interpolation::Linear);
+        track.insert(Keyframe::new(1.0, vec![5.0], Interpolation::Linear));
+        track.insert(Keyframe::new(2.0, vec![6.0], Interpolation::Linear));
+        let times: Vec<f32> = track.keys.iter().map(|key| key.time).collect();
+        assert_eq!(times, vec![0.0, 1.0, 2.0]);
+        assert_eq!(sample(&track, 2.0), 6.0);
+    }
+
+    #[test]
+    fn loops_over_the_duration() {
+        let mut timeline = Timeline::new();
+        timeline.key_uniform(0.0, "x", vec![0.0], Interpolation::Linear);
+        timeline.key_uniform(2.0, "x", vec![2.0], Interpolation::Linear);
+        timeline.looping = true;
+        let state = timeline.sample(3.0);
+        assert_eq!(
+            state.uniforms,
+            vec![(String::from("x"), UniformValue::Float(1.0))]
+        );
+        assert_eq!(state.eye, None);
+    }
+
+    fn parse(src: &str) -> Option<Timeline> {
+        let mut timeline: Timeline = toml::from_str(src).unwrap();
+        timeline.validate()?;
+        Some(timeline)
+    }
+
+    #[test]
+    fn sorts_the_keyframes_of_a_file() {
+        let timeline = parse(
+            "[[aperture.keys]]\ntime = 2.0\nvalue = [1.0]\n\
+             [[aperture.keys]]\ntime = 0.0\nvalue = [0.0]\n",
+        )
+        .unwrap();
+        assert_eq!(timeline.sample(1.0).aperture, Some(0.5));
+    }
+
+    #[test]
+    fn rejects_keyframes_which_cant_be_interpolated() {
+        assert!(parse("[[eye.keys]]\ntime = 0.0\nvalue = []\n").is_none());
+        assert!(parse(
+            "[[uniforms.tint.keys]]\ntime = 0.0\nvalue = [1.0, 1.0]\n\
+             [[uniforms.tint.keys]]\ntime = 1.0\nvalue = [1.0]\n"
+        )
+        .is_none());
+        assert!(parse("[[aperture.keys]]\ntime = nan\nvalue = [1.0]\n").is_none());
+    }
+}
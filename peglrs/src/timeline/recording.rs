@@ -0,0 +1,359 @@
+use std::fs;
+use std::path::Path;
+
+use cgmath::{InnerSpace, Vector2, Vector3};
+use serde::{Deserialize, Serialize};
+
+use super::{Interpolation, Keyframe, Timeline, Track};
+use crate::utils;
+
+// The camera at one frame of a recording, `time` seconds after it started.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CameraSample {
+    pub time: f32,
+    pub eye: [f32; 3],
+    pub direction: [f32; 3],
+    pub up: [f32; 3],
+    pub focus_pos: [f32; 2],
+    pub aperture: f32,
+}
+
+impl CameraSample {
+    pub fn new(
+        time: f32,
+        eye: Vector3<f32>,
+        direction: Vector3<f32>,
+        up: Vector3<f32>,
+        focus_pos: Vector2<f32>,
+        aperture: f32,
+    ) -> CameraSample {
+        CameraSample {
+            time,
+            eye: eye.into(),
+            direction: direction.into(),
+            up: up.into(),
+            focus_pos: focus_pos.into(),
+            aperture,
+        }
+    }
+
+    pub fn eye(&self) -> Vector3<f32> {
+        self.eye.into()
+    }
+
+    pub fn direction(&self) -> Vector3<f32> {
+        self.direction.into()
+    }
+
+    pub fn up(&self) -> Vector3<f32> {
+        self.up.into()
+    }
+
+    pub fn focus_pos(&self) -> Vector2<f32> {
+        self.focus_pos.into()
+    }
+
+    fn same_camera(&self, other: &CameraSample) -> bool {
+        CameraSample {
+            time: other.time,
+            ..*self
+        } == *other
+    }
+
+    // How far another sample is from this one, each part on its own: the
+    // eye, focus position and aperture by their distance, the direction and
+    // up vector by their angle in radians.
+    fn error(&self, other: &CameraSample) -> f32 {
+        let focus = self.focus_pos() - other.focus_pos();
+        [
+            (self.eye() - other.eye()).magnitude(),
+            angle(self.direction(), other.direction()),
+            angle(self.up(), other.up()),
+            focus.magnitude(),
+            (self.aperture - other.aperture).abs(),
+        ]
+        .iter()
+        .fold(0.0, |worst, error| worst.max(*error))
+    }
+
+    // The camera a timeline keyed at this sample and `other` gives at
+    // `time`, with linear interpolation.
+    fn keyed(&self, other: &CameraSample, time: f32) -> CameraSample {
+        let sample = self.lerp(other, time);
+        let target = |s: &CameraSample| s.eye() + s.direction();
+        let t = if other.time > self.time {
+            (time - self.time) / (other.time - self.time)
+        } else {
+            0.0
+        };
+        let target = target(self) + (target(other) - target(self)) * t;
+        CameraSample {
+            direction: (target - sample.eye()).into(),
+            ..sample
+        }
+    }
+
+    fn lerp(&self, other: &CameraSample, time: f32) -> CameraSample {
+        let dt = other.time - self.time;
+        let t = if dt > 0.0 {
+            (time - self.time) / dt
+        } else {
+            0.0
+        };
+        let mix = |a: f32, b: f32| a + (b - a) * t;
+        let mix3 = |a: [f32; 3], b: [f32; 3]| [mix(a[0], b[0]), mix(a[1], b[1]), mix(a[2], b[2])];
+        CameraSample {
+            time,
+            eye: mix3(self.eye, other.eye),
+            direction: Vector3::from(mix3(self.direction, other.direction))
+                .normalize()
+                .into(),
+            up: Vector3::from(mix3(self.up, other.up)).normalize().into(),
+            focus_pos: [
+                mix(self.focus_pos[0], other.focus_pos[0]),
+                mix(self.focus_pos[1], other.focus_pos[1]),
+            ],
+            aperture: mix(self.aperture, other.aperture),
+        }
+    }
+}
+
+// Angle between two vectors, 0 if one of them has no length.
+fn angle(a: Vector3<f32>, b: Vector3<f32>) -> f32 {
+    if a.magnitude2() == 0.0 || b.magnitude2() == 0.0 {
+        return 0.0;
+    }
+    a.normalize().dot(b.normalize()).clamp(-1.0, 1.0).acos()
+}
+
+// The camera of every frame of an interactive session, to replay a move as
+// it was or turn it into a timeline. Saved as TOML.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Recording {
+    pub samples: Vec<CameraSample>,
+}
+
+impl Recording {
+    pub fn new() -> Recording {
+        Recording::default()
+    }
+
+    // Add the camera of a frame. Frames where it didn't move are skipped, a
+    // still camera is two samples.
+    pub fn push(&mut self, sample: CameraSample) {
+        let n = self.samples.len();
+        let still = |idx: usize| self.samples[idx].same_camera(&sample);
+        if n >= 2 && still(n - 1) && still(n - 2) {
+            self.samples[n - 1] = sample;
+        } else {
+            self.samples.push(sample);
+        }
+    }
+
+    pub fn duration(&self) -> f32 {
+        self.samples.last().map_or(0.0, |sample| sample.time)
+    }
+
+    pub fn load(path: &Path) -> Option<Recording> {
+        let src = utils::load_file(path)?;
+        match toml::from_str(&src) {
+            Ok(recording) => Some(recording),
+            Err(err) => {
+                eprintln!(
+                    "[ERR] Couldn't parse recording {} : {}",
+                    path.display(),
+                    err
+                );
+                None
+            }
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Option<()> {
+        let res = toml::to_string(self)
+            .map_err(|err| err.to_string())
+            .and_then(|src| fs::write(path, src).map_err(|err| err.to_string()));
+        match res {
+            Ok(()) => Some(()),
+            Err(err) => {
+                eprintln!("[ERR] Couldn't save recording {} : {}", path.display(), err);
+                None
+            }
+        }
+    }
+
+    // The camera at `time`, between the frames around it.
+    pub fn sample(&self, time: f32) -> Option<CameraSample> {
+        let first = self.samples.first()?;
+        let last = self.samples.last()?;
+        if time <= first.time {
+            return Some(*first);
+        }
+        if time >= last.time {
+            return Some(*last);
+        }
+
+        let idx = self.samples.iter().rposition(|s| s.time <= time)?;
+        Some(self.samples[idx].lerp(&self.samples[idx + 1], time))
+    }
+
+    // Keep the frames needed for the timeline made of them to stay within
+    // `tolerance` of the recording, see CameraSample::error
+    // (Ramer-Douglas-Peucker on the time).
+    fn simplify(&self, tolerance: f32) -> Vec<usize> {
+        if self.samples.len() <= 2 {
+            return (0..self.samples.len()).collect();
+        }
+
+        let mut keep = vec![false; self.samples.len()];
+        keep[0] = true;
+        keep[self.samples.len() - 1] = true;
+        let mut spans = vec![(0, self.samples.len() - 1)];
+        while let Some((start, end)) = spans.pop() {
+            let (a, b) = (&self.samples[start], &self.samples[end]);
+            let mut worst = (0.0, start);
+            for idx in start + 1..end {
+                let sample = &self.samples[idx];
+                let error = sample.error(&a.keyed(b, sample.time));
+                if error > worst.0 {
+                    worst = (error, idx);
+                }
+            }
+            if worst.0 > tolerance {
+                keep[worst.1] = true;
+                spans.push((start, worst.1));
+                spans.push((worst.1, end));
+            }
+        }
+        (0..self.samples.len()).filter(|idx| keep[*idx]).collect()
+    }
+
+    // A timeline with a keyframe wherever the path strays by more than
+    // `tolerance` from a straight line, so it can be edited by hand. The keys
+    // are linear, as the error is measured against straight lines.
+    pub fn to_timeline(&self, tolerance: f32) -> Timeline {
+        let mut eye = Track::default();
+        let mut target = Track::default();
+        let mut up = Track::default();
+        let mut focus_pos = Track::default();
+        let mut aperture = Track::default();
+        for idx in self.simplify(tolerance) {
+            let sample = &self.samples[idx];
+            let key = |value: Vec<f32>| Keyframe::new(sample.time, value, Interpolation::Linear);
+            let look_at = sample.eye() + sample.direction();
+            eye.insert(key(sample.eye.to_vec()));
+            target.insert(key(vec![look_at.x, look_at.y, look_at.z]));
+            up.insert(key(sample.up.to_vec()));
+            focus_pos.insert(key(sample.focus_pos.to_vec()));
+            aperture.insert(key(vec![sample.aperture]));
+        }
+
+        let track = |track: Track| Some(track).filter(|track| !track.keys.is_empty());
+        Timeline {
+            eye: track(eye),
+            target: track(target),
+            up: track(up),
+            focus_pos: track(focus_pos),
+            aperture: track(aperture),
+            ..Timeline::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(time: f32, eye: [f32; 3], direction: [f32; 3], aperture: f32) -> CameraSample {
+        CameraSample {
+            time,
+            eye,
+            direction,
+            up: [0.0, 1.0, 0.0],
+            focus_pos: [0.5, 0.5],
+            aperture,
+        }
+    }
+
+    fn recording(samples: Vec<CameraSample>) -> Recording {
+        Recording { samples }
+    }
+
+    const FORWARD: [f32; 3] = [0.0, 0.0, -1.0];
+
+    #[test]
+    fn keeps_the_ends_of_a_straight_move() {
+        let moving = recording(
+            (0..10)
+                .map(|i| sample(i as f32, [i as f32, 0.0, 0.0], FORWARD, 0.1))
+                .collect(),
+        );
+        assert_eq!(moving.simplify(0.01), vec![0, 9]);
+    }
+
+    #[test]
+    fn keeps_the_corners() {
+        let mut samples: Vec<CameraSample> = (0..5)
+            .map(|i| sample(i as f32, [i as f32, 0.0, 0.0], FORWARD, 0.1))
+            .collect();
+        samples.extend((1..5).map(|i| sample(4.0 + i as f32, [4.0, 0.0, i as f32], FORWARD, 0.1)));
+        assert_eq!(recording(samples).simplify(0.01), vec![0, 4, 8]);
+    }
+
+    #[test]
+    fn keeps_turns_of_a_still_camera() {
+        let samples = (0..9)
+            .map(|i| {
+                let a = i as f32 / 8.0 * std::f32::consts::PI;
+                sample(i as f32, [0.0; 3], [a.sin(), 0.0, -a.cos()], 0.1)
+            })
+            .collect();
+        let keys = recording(samples).simplify(0.01);
+        assert!(keys.len() > 2, "{:?}", keys);
+    }
+
+    #[test]
+    fn measures_each_part_on_its_own() {
+        // The eye and the aperture both stray by less than the tolerance,
+        // though their sum is more.
+        let samples = vec![
+            sample(0.0, [0.0; 3], FORWARD, 0.1),
+            sample(1.0, [0.5, 0.008, 0.0], FORWARD, 0.108),
+            sample(2.0, [1.0, 0.0, 0.0], FORWARD, 0.1),
+        ];
+        assert_eq!(recording(samples).simplify(0.01), vec![0, 2]);
+    }
+
+    #[test]
+    fn makes_a_linear_timeline_through_the_kept_samples() {
+        let samples = vec![
+            sample(0.0, [0.0; 3], FORWARD, 0.0),
+            sample(1.0, [1.0, 0.0, 0.0], FORWARD, 0.1),
+            sample(2.0, [2.0, 0.0, 0.0], FORWARD, 0.2),
+        ];
+        let timeline = recording(samples).to_timeline(0.01);
+        let eye = timeline.eye.as_ref().unwrap();
+        assert_eq!(eye.keys.len(), 2);
+        assert!(eye
+            .keys
+            .iter()
+            .all(|key| key.interpolation == Interpolation::Linear));
+
+        let state = timeline.sample(2.0);
+        assert_eq!(state.eye, Some(Vector3::new(2.0, 0.0, 0.0)));
+        assert_eq!(state.target, Some(Vector3::new(2.0, 0.0, -1.0)));
+        assert_eq!(state.aperture, Some(0.2));
+    }
+
+    #[test]
+    fn a_still_camera_is_two_samples() {
+        let mut still = Recording::new();
+        for i in 0..5 {
+            still.push(sample(i as f32, [0.0; 3], FORWARD, 0.1));
+        }
+        let times: Vec<f32> = still.samples.iter().map(|s| s.time).collect();
+        assert_eq!(times, vec![0.0, 4.0]);
+    }
+}
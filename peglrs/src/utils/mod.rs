@@ -1,5 +1,11 @@
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::sync::mpsc::Receiver;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, SystemTime};
 
 pub fn load_file(file: &Path) -> Option<String> {
     let contents = fs::read_to_string(file);
@@ -13,3 +19,56 @@ pub fn load_file(file: &Path) -> Option<String> {
         }
     }
 }
+
+pub fn last_modified(file: &Path) -> Option<SystemTime> {
+    fs::metadata(file).and_then(|stat| stat.modified()).ok()
+}
+
+// Poll a file in the background and get notified when it changes. The
+// thread stops once the watcher is dropped.
+#[derive(Debug)]
+pub struct FileWatcher {
+    pub path: PathBuf,
+    pub receiver: Receiver<()>,
+    stop: Arc<AtomicBool>,
+}
+
+impl FileWatcher {
+    pub fn new(path: &Path) -> FileWatcher {
+        let (sender, receiver) = mpsc::channel();
+        let watched = path.to_path_buf();
+        let mut last = last_modified(&watched);
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = stop.clone();
+        thread::spawn(move || loop {
+            thread::sleep(Duration::from_millis(1000));
+            if thread_stop.load(Ordering::Relaxed) {
+                break;
+            }
+            let modified = last_modified(&watched);
+            if modified > last {
+                last = modified;
+                if sender.send(()).is_err() {
+                    // Nobody is listening anymore.
+                    break;
+                }
+            }
+        });
+
+        FileWatcher {
+            path: path.to_path_buf(),
+            receiver,
+            stop,
+        }
+    }
+
+    pub fn changed(&self) -> bool {
+        self.receiver.try_iter().count() > 0
+    }
+}
+
+impl Drop for FileWatcher {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
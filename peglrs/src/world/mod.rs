@@ -0,0 +1,438 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::utils;
+
+// Texels taken by a material and by an object in the world texture. Must
+// match world_material and hit_object in post.fs.
+pub const MATERIAL_TEXELS: usize = 3;
+pub const OBJECT_TEXELS: usize = 3;
+
+// Smallest GL_MAX_TEXTURE_SIZE an OpenGL 4.1 driver may have.
+const MAX_WIDTH: usize = 16384;
+
+// Must match the defines of post.fs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MaterialType {
+    #[default]
+    Lambertian,
+    Metal,
+    Dielectric,
+    // A constant density fog filling the object it is on.
+    Volume,
+    // Only emits light, never scatters.
+    Emissive,
+}
+
+impl MaterialType {
+    fn id(self) -> f32 {
+        match self {
+            MaterialType::Lambertian => 0.0,
+            MaterialType::Metal => 1.0,
+            MaterialType::Dielectric => 2.0,
+            MaterialType::Volume => 3.0,
+            MaterialType::Emissive => 99.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Material {
+    #[serde(rename = "type")]
+    pub kind: MaterialType,
+    pub albedo: [f32; 3],
+    // Light given off by the front faces, whatever the type.
+    pub emission: [f32; 3],
+    // How blurry the reflections of a metal are.
+    pub fuzz: f32,
+    // Index of refraction of a dielectric.
+    pub refraction: f32,
+    // How thick the fog of a volume is.
+    pub density: f32,
+}
+
+impl Default for Material {
+    fn default() -> Material {
+        Material {
+            kind: MaterialType::Lambertian,
+            albedo: [0.8, 0.8, 0.8],
+            emission: [0.0, 0.0, 0.0],
+            fuzz: 0.0,
+            refraction: 1.5,
+            density: 0.1,
+        }
+    }
+}
+
+impl Material {
+    pub fn new(kind: MaterialType, albedo: [f32; 3]) -> Material {
+        Material {
+            kind,
+            albedo,
+            ..Material::default()
+        }
+    }
+}
+
+// A negative radius turns the normals inside, making a hollow glass sphere
+// when put in another one.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Sphere {
+    pub center: [f32; 3],
+    pub radius: f32,
+    pub material: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Axis {
+    Xy,
+    Xz,
+    Yz,
+}
+
+impl Axis {
+    // The axis the rect faces, its normal.
+    fn normal(self) -> f32 {
+        match self {
+            Axis::Yz => 0.0,
+            Axis::Xz => 1.0,
+            Axis::Xy => 2.0,
+        }
+    }
+}
+
+// An axis aligned rectangle in the plane `axis` at `k` along the third
+// axis, facing its positive side. `min` and `max` are in the order of the
+// plane's axes.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Rect {
+    pub axis: Axis,
+    pub min: [f32; 2],
+    pub max: [f32; 2],
+    pub k: f32,
+    pub material: String,
+}
+
+// An axis aligned box.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Cuboid {
+    pub min: [f32; 3],
+    pub max: [f32; 3],
+    pub material: String,
+}
+
+// What the path tracer renders, read from a TOML file:
+//
+//   [materials.light]
+//   type = "emissive"
+//   emission = [8.0, 8.0, 8.0]
+//
+//   [[spheres]]
+//   center = [0.0, 5.0, 3.0]
+//   radius = 0.4
+//   material = "light"
+//
+// Objects refer to the materials by name. Rects and boxes are written as
+// [[rects]] and [[boxes]]. The missing tables are empty rather than those
+// of the built-in scene.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct World {
+    #[serde(default)]
+    pub materials: BTreeMap<String, Material>,
+    #[serde(default)]
+    pub spheres: Vec<Sphere>,
+    #[serde(default)]
+    pub rects: Vec<Rect>,
+    #[serde(default)]
+    pub boxes: Vec<Cuboid>,
+}
+
+// The texels of a world, see World::encode.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Encoded {
+    pub materials: Vec<[f32; 4]>,
+    pub objects: Vec<[f32; 4]>,
+}
+
+impl Default for World {
+    // The scene the path tracer always had.
+    fn default() -> World {
+        let mut world = World::empty();
+        let mut material = |name: &str, material: Material| {
+            world.materials.insert(String::from(name), material);
+        };
+        material(
+            "ground",
+            Material::new(MaterialType::Lambertian, [1.0, 1.0, 1.0]),
+        );
+        material(
+            "light",
+            Material {
+                emission: [8.0, 8.0, 8.0],
+                ..Material::new(MaterialType::Emissive, [0.0, 0.0, 0.0])
+            },
+        );
+        material(
+            "blue_metal",
+            Material {
+                fuzz: 0.05,
+                ..Material::new(MaterialType::Metal, [0.1, 0.2, 0.5])
+            },
+        );
+        material(
+            "amber_glass",
+            Material {
+                refraction: 1.2,
+                ..Material::new(MaterialType::Dielectric, [0.8, 0.6, 0.2])
+            },
+        );
+        material(
+            "bubble",
+            Material {
+                refraction: 0.95,
+                ..Material::new(MaterialType::Dielectric, [0.0, 0.0, 0.0])
+            },
+        );
+        material(
+            "lens",
+            Material {
+                refraction: 0.4,
+                ..Material::new(MaterialType::Dielectric, [0.0, 0.0, 0.0])
+            },
+        );
+        material(
+            "copper",
+            Material::new(MaterialType::Metal, [1.0, 0.5, 0.4]),
+        );
+        material(
+            "fog",
+            Material {
+                density: 0.06,
+                ..Material::new(MaterialType::Volume, [1.0, 1.0, 1.0])
+            },
+        );
+        material(
+            "pink_light",
+            Material {
+                emission: [2.4, 0.6, 1.2],
+                ..Material::new(MaterialType::Emissive, [0.0, 0.0, 0.0])
+            },
+        );
+
+        let spheres = [
+            ([0.0, 0.0, 0.0], 30.0, "fog"),
+            ([0.0, -100.5, 0.0], 100.0, "ground"),
+            ([0.0, 5.0, 3.0], 0.4, "light"),
+            ([-1.0, 0.4, -3.8], 0.4, "blue_metal"),
+            ([2.0, 1.0, -1.0], 1.0, "amber_glass"),
+            ([-1.0, 0.4, -3.8], 1.0, "lens"),
+            ([2.0, 1.2, -1.0], -1.5, "bubble"),
+            ([-34.0, 1.0, 0.0], 32.0, "copper"),
+        ];
+        for (center, radius, material) in spheres.iter() {
+            world.spheres.push(Sphere {
+                center: *center,
+                radius: *radius,
+                material: String::from(*material),
+            });
+        }
+        world.rects.push(Rect {
+            axis: Axis::Xy,
+            min: [-2.0, -1.0],
+            max: [2.0, 3.0],
+            k: -5.0,
+            material: String::from("pink_light"),
+        });
+        world
+    }
+}
+
+impl World {
+    // Nothing in it, only the sky.
+    pub fn empty() -> World {
+        World {
+            materials: BTreeMap::new(),
+            spheres: Vec::new(),
+            rects: Vec::new(),
+            boxes: Vec::new(),
+        }
+    }
+
+    pub fn load(path: &Path) -> Option<World> {
+        let src = utils::load_file(path)?;
+        let world: World = match toml::from_str(&src) {
+            Ok(world) => world,
+            Err(err) => {
+                eprintln!("[ERR] Couldn't parse scene {} : {}", path.display(), err);
+                return None;
+            }
+        };
+        // Catch the unknown materials now rather than on upload.
+        world.encode()?;
+        Some(world)
+    }
+
+    pub fn save(&self, path: &Path) -> Option<()> {
+        // Through a Value, which puts the tables after the plain values.
+        let src = toml::Value::try_from(self)
+            .and_then(|value| toml::to_string_pretty(&value))
+            .ok()?;
+        match std::fs::write(path, src) {
+            Ok(()) => Some(()),
+            Err(err) => {
+                eprintln!("[ERR] Couldn't save scene {} : {}", path.display(), err);
+                None
+            }
+        }
+    }
+
+    pub fn object_count(&self) -> usize {
+        self.spheres.len() + self.rects.len() + self.boxes.len()
+    }
+
+    // The texels of the materials and of the objects. A material is
+    // (albedo, type), (emission, fuzz), (refraction, density, 0, 0). An
+    // object is (kind, material, 0, 0) then two texels depending on its kind:
+    // (center, radius) for a sphere, (min, max) then (k, axis) for a rect and
+    // min then max for a box.
+    pub fn encode(&self) -> Option<Encoded> {
+        let names: Vec<&String> = self.materials.keys().collect();
+        let material = |name: &str| match names.iter().position(|n| *n == name) {
+            Some(idx) => Some(idx as f32),
+            None => {
+                eprintln!("[ERR] Unknown material {}", name);
+                None
+            }
+        };
+
+        let mut materials = Vec::with_capacity(self.materials.len() * MATERIAL_TEXELS);
+        for m in self.materials.values() {
+            let [r, g, b] = m.albedo;
+            let [er, eg, eb] = m.emission;
+            materials.push([r, g, b, m.kind.id()]);
+            materials.push([er, eg, eb, m.fuzz]);
+            materials.push([m.refraction, m.density, 0.0, 0.0]);
+        }
+
+        let mut objects = Vec::with_capacity(self.object_count() * OBJECT_TEXELS);
+        for s in &self.spheres {
+            let [x, y, z] = s.center;
+            objects.push([0.0, material(&s.material)?, 0.0, 0.0]);
+            objects.push([x, y, z, s.radius]);
+            objects.push([0.0; 4]);
+        }
+        for r in &self.rects {
+            objects.push([1.0, material(&r.material)?, 0.0, 0.0]);
+            objects.push([r.min[0], r.max[0], r.min[1], r.max[1]]);
+            objects.push([r.k, r.axis.normal(), 0.0, 0.0]);
+        }
+        for b in &self.boxes {
+            let ([x0, y0, z0], [x1, y1, z1]) = (b.min, b.max);
+            objects.push([2.0, material(&b.material)?, 0.0, 0.0]);
+            objects.push([x0, y0, z0, 0.0]);
+            objects.push([x1, y1, z1, 0.0]);
+        }
+
+        if materials.len().max(objects.len()) > MAX_WIDTH {
+            eprintln!(
+                "[ERR] Scene too big, it can have {} objects and materials",
+                MAX_WIDTH / OBJECT_TEXELS
+            );
+            return None;
+        }
+        Some(Encoded { materials, objects })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn world() -> World {
+        let mut world = World::empty();
+        world.materials.insert(
+            String::from("glass"),
+            Material::new(MaterialType::Dielectric, [1.0, 1.0, 1.0]),
+        );
+        world.materials.insert(
+            String::from("floor"),
+            Material::new(MaterialType::Lambertian, [0.5, 0.5, 0.5]),
+        );
+        world.spheres.push(Sphere {
+            center: [1.0, 2.0, 3.0],
+            radius: 0.5,
+            material: String::from("glass"),
+        });
+        world.rects.push(Rect {
+            axis: Axis::Xz,
+            min: [-1.0, -2.0],
+            max: [1.0, 2.0],
+            k: 0.0,
+            material: String::from("floor"),
+        });
+        world
+    }
+
+    #[test]
+    fn encodes_objects_with_their_material_index() {
+        let encoded = world().encode().unwrap();
+        assert_eq!(encoded.materials.len(), 2 * MATERIAL_TEXELS);
+        assert_eq!(encoded.objects.len(), 2 * OBJECT_TEXELS);
+        // Materials are sorted by name: floor, then glass.
+        assert_eq!(encoded.materials[0], [0.5, 0.5, 0.5, 0.0]);
+        assert_eq!(encoded.materials[3], [1.0, 1.0, 1.0, 2.0]);
+        assert_eq!(encoded.objects[0], [0.0, 1.0, 0.0, 0.0]);
+        assert_eq!(encoded.objects[1], [1.0, 2.0, 3.0, 0.5]);
+        assert_eq!(encoded.objects[3], [1.0, 0.0, 0.0, 0.0]);
+        assert_eq!(encoded.objects[4], [-1.0, 1.0, -2.0, 2.0]);
+        assert_eq!(encoded.objects[5], [0.0, 1.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn unknown_materials_are_refused() {
+        let mut sphere = world();
+        sphere.spheres[0].material = String::from("gold");
+        assert_eq!(sphere.encode(), None);
+
+        let mut rect = world();
+        rect.rects[0].material = String::from("gold");
+        assert_eq!(rect.encode(), None);
+
+        let mut cuboid = world();
+        cuboid.boxes.push(Cuboid {
+            min: [0.0; 3],
+            max: [1.0; 3],
+            material: String::from("gold"),
+        });
+        assert_eq!(cuboid.encode(), None);
+    }
+
+    #[test]
+    fn the_built_in_scene_encodes() {
+        let world = World::default();
+        let encoded = world.encode().unwrap();
+        assert_eq!(encoded.objects.len(), world.object_count() * OBJECT_TEXELS);
+        assert_eq!(
+            encoded.materials.len(),
+            world.materials.len() * MATERIAL_TEXELS
+        );
+    }
+
+    #[test]
+    fn the_example_scene_encodes() {
+        let src = include_str!("../../../data/scenes/cornell.toml");
+        let world: World = toml::from_str(src).unwrap();
+        assert!(world.object_count() > 0);
+        let encoded = world.encode().unwrap();
+        assert_eq!(encoded.objects.len(), world.object_count() * OBJECT_TEXELS);
+    }
+}
@@ -0,0 +1,424 @@
+// Python bindings of peglrs, used by the editor.
+//
+// A GL context must be current on the calling thread when creating a Scene
+// and when calling any of its methods.
+
+// The code pyo3 generates for methods returning PyResult trips this lint.
+#![allow(clippy::useless_conversion)]
+
+use std::path::PathBuf;
+use std::time::Duration;
+
+use cgmath::{Vector2, Vector3, Vector4};
+use pyo3::exceptions::{PyRuntimeError, PyValueError};
+use pyo3::prelude::*;
+
+type Vec2 = (f32, f32);
+type Vec3 = (f32, f32, f32);
+
+#[derive(FromPyObject)]
+enum Uniform {
+    Int(i32),
+    Float(f32),
+    Vec2(Vec2),
+    Vec3(Vec3),
+    Vec4((f32, f32, f32, f32)),
+}
+
+impl From<Uniform> for peglrs::UniformValue {
+    fn from(uniform: Uniform) -> peglrs::UniformValue {
+        match uniform {
+            Uniform::Int(v) => peglrs::UniformValue::Int(v),
+            Uniform::Float(v) => peglrs::UniformValue::Float(v),
+            Uniform::Vec2((x, y)) => peglrs::UniformValue::Vec2(Vector2::new(x, y)),
+            Uniform::Vec3((x, y, z)) => peglrs::UniformValue::Vec3(Vector3::new(x, y, z)),
+            Uniform::Vec4((x, y, z, w)) => peglrs::UniformValue::Vec4(Vector4::new(x, y, z, w)),
+        }
+    }
+}
+
+// (reason, samples, seconds) as given to goal callbacks.
+fn report_tuple(report: &peglrs::GoalReport) -> (&'static str, u32, f32) {
+    let reason = match report.reason {
+        peglrs::GoalReason::Samples => "samples",
+        peglrs::GoalReason::Time => "time",
+        peglrs::GoalReason::Noise => "noise",
+    };
+    (reason, report.samples, report.elapsed.as_secs_f32())
+}
+
+fn vec3(v: Vec3) -> Vector3<f32> {
+    Vector3::new(v.0, v.1, v.2)
+}
+
+fn tuple3(v: Vector3<f32>) -> Vec3 {
+    (v.x, v.y, v.z)
+}
+
+#[pyclass(unsendable, module = "peglrs_py")]
+struct Scene {
+    scene: peglrs::Scene,
+    on_reload: Vec<PyObject>,
+}
+
+#[pymethods]
+impl Scene {
+    // Load the GL functions from the current context and create a scene.
+    // The default pipeline is used when `pipeline` is None.
+    #[new]
+    #[pyo3(signature = (width, height, dpi_ratio = 1.0, pipeline = None))]
+    fn new(width: f64, height: f64, dpi_ratio: f64, pipeline: Option<&str>) -> PyResult<Scene> {
+        peglrs::load_gl_symbol();
+        peglrs::init_gl(width, height, dpi_ratio);
+
+        let scene = match pipeline {
+            Some(path) => peglrs::init_scene_from_file(path, width, height, dpi_ratio),
+            None => peglrs::init_scene(width, height, dpi_ratio),
+        };
+        match scene {
+            Some(scene) => Ok(Scene {
+                scene,
+                on_reload: Vec::new(),
+            }),
+            None => Err(PyRuntimeError::new_err("couldn't create the scene")),
+        }
+    }
+
+    // Render one frame into `fbo`. Returns True if shaders or the pipeline
+    // were reloaded, after calling the reload callbacks.
+    #[pyo3(signature = (time, fbo = 0, reset_on_reload = true))]
+    fn render(&mut self, py: Python, time: f64, fbo: u32, reset_on_reload: bool) -> PyResult<bool> {
+        let reloaded = peglrs::display_loop(&mut self.scene, time, fbo, reset_on_reload);
+        if reloaded {
+            for callback in &self.on_reload {
+                callback.call0(py)?;
+            }
+        }
+        Ok(reloaded)
+    }
+
+    #[pyo3(signature = (width, height, dpi_ratio = 1.0))]
+    fn resize(&mut self, width: f64, height: f64, dpi_ratio: f64) {
+        peglrs::resize_window(&mut self.scene, width, height, dpi_ratio);
+    }
+
+    #[pyo3(signature = (fbo = 0))]
+    fn reset(&mut self, fbo: u32) {
+        peglrs::reset(&mut self.scene, fbo);
+    }
+
+    fn set_camera(&mut self, eye: Vec3, target: Vec3, up: Vec3, focus_pos: Vec2, aperture: f32) {
+        peglrs::update_camera(
+            &mut self.scene,
+            vec3(eye),
+            vec3(target),
+            vec3(up),
+            Vector2::new(focus_pos.0, focus_pos.1),
+            aperture,
+        );
+    }
+
+    // (eye, target, up, focus_pos, aperture)
+    fn camera(&self) -> (Vec3, Vec3, Vec3, Vec2, f32) {
+        let scene = &self.scene;
+        (
+            tuple3(scene.eye),
+            tuple3(scene.target),
+            tuple3(scene.up),
+            (scene.focus_pos.x, scene.focus_pos.y),
+            scene.aperture,
+        )
+    }
+
+    // Turn the camera from a mouse drag of (dx, dy) pixels.
+    #[pyo3(signature = (dx, dy, speed = 0.01))]
+    fn handle_mouse(&mut self, dx: f32, dy: f32, speed: f32) {
+        peglrs::handle_mouse(&mut self.scene, dx, dy, speed);
+    }
+
+    // `value` is an int, a float or a tuple of 2 to 4 floats.
+    fn set_uniform(&mut self, name: &str, value: Uniform) {
+        peglrs::set_uniform(&mut self.scene, name, value.into());
+    }
+
+    // The value set with set_uniform, or by a timeline or OSC. None if unset.
+    fn uniform(&self, py: Python, name: &str) -> Option<PyObject> {
+        let value = match self.scene.uniforms.get(name)? {
+            peglrs::UniformValue::Int(v) => v.into_py(py),
+            peglrs::UniformValue::Float(v) => v.into_py(py),
+            peglrs::UniformValue::Vec2(v) => (v.x, v.y).into_py(py),
+            peglrs::UniformValue::Vec3(v) => (v.x, v.y, v.z).into_py(py),
+            peglrs::UniformValue::Vec4(v) => (v.x, v.y, v.z, v.w).into_py(py),
+        };
+        Some(value)
+    }
+
+    fn remove_uniform(&mut self, name: &str) {
+        peglrs::remove_uniform(&mut self.scene, name);
+    }
+
+    // More samples than the driver supports are clamped.
+    fn set_pass_samples(&mut self, pass: usize, samples: i32) -> PyResult<()> {
+        peglrs::set_pass_samples(&mut self.scene, pass, samples).ok_or_else(|| {
+            PyValueError::new_err(format!("can't render pass {} with {} samples", pass, samples))
+        })
+    }
+
+    // Light the scene with an equirectangular .hdr or .exr image, or go back
+    // to the procedural sky with None. Rotate it and scale it with the
+    // env_rotation (radians) and env_intensity uniforms.
+    #[pyo3(signature = (path = None))]
+    fn set_environment(&mut self, path: Option<PathBuf>) -> PyResult<()> {
+        peglrs::set_environment(&mut self.scene, path.as_deref()).ok_or_else(|| {
+            PyValueError::new_err(format!("couldn't load environment {:?}", path))
+        })
+    }
+
+    // Render the spheres, rects and boxes of a scene file, reloaded when it
+    // changes, or the built-in scene with None.
+    #[pyo3(signature = (path = None))]
+    fn load_world(&mut self, path: Option<PathBuf>) -> PyResult<()> {
+        peglrs::load_world(&mut self.scene, path.as_deref())
+            .ok_or_else(|| PyValueError::new_err(format!("couldn't load scene {:?}", path)))
+    }
+
+    // Number of spheres, rects and boxes rendered.
+    #[getter]
+    fn world_objects(&self) -> u32 {
+        self.scene.world.objects
+    }
+
+    // Play the keyframes of a timeline file with apply_timeline, or drop the
+    // timeline with None.
+    #[pyo3(signature = (path = None))]
+    fn set_timeline(&mut self, path: Option<PathBuf>) -> PyResult<()> {
+        let timeline = match &path {
+            Some(path) => Some(peglrs::Timeline::load(path).ok_or_else(|| {
+                PyValueError::new_err(format!("couldn't load timeline {}", path.display()))
+            })?),
+            None => None,
+        };
+        peglrs::set_timeline(&mut self.scene, timeline);
+        Ok(())
+    }
+
+    // Key the current camera at `time`, starting a timeline if there is none.
+    fn key_camera(&mut self, time: f32) {
+        let scene = &mut self.scene;
+        scene.timeline.get_or_insert_with(peglrs::Timeline::new).key_camera(
+            time,
+            scene.eye,
+            scene.target,
+            scene.up,
+            scene.focus_pos,
+            scene.aperture,
+        );
+    }
+
+    fn save_timeline(&self, path: PathBuf) -> PyResult<()> {
+        self.scene
+            .timeline
+            .as_ref()
+            .and_then(|timeline| timeline.save(&path))
+            .ok_or_else(|| PyValueError::new_err(format!("couldn't save timeline {}", path.display())))
+    }
+
+    // Move the camera and uniforms to where the timeline is at `time`. True
+    // if anything changed, the accumulation then needs a reset.
+    fn apply_timeline(&mut self, time: f32) -> bool {
+        peglrs::apply_timeline(&mut self.scene, time)
+    }
+
+    // Accept JSON-RPC commands on a loopback address, port 0 picking a free
+    // one, or stop with None. Returns the port listened on. The export
+    // command saves into `export_dir`, and is refused without it. The
+    // commands only run when calling handle_remote.
+    #[pyo3(signature = (addr = Some(String::from("127.0.0.1:0")), export_dir = None))]
+    fn serve(&mut self, addr: Option<String>, export_dir: Option<PathBuf>) -> PyResult<Option<u16>> {
+        match addr {
+            Some(addr) => peglrs::set_remote(&mut self.scene, Some(&addr), export_dir.as_deref())
+                .map(|addr| Some(addr.port()))
+                .ok_or_else(|| PyValueError::new_err(format!("couldn't listen on {}", addr))),
+            None => {
+                peglrs::set_remote(&mut self.scene, None, None);
+                Ok(None)
+            }
+        }
+    }
+
+    // Run the remote commands received so far. True if one moved the camera.
+    #[pyo3(signature = (fbo = 0))]
+    fn handle_remote(&mut self, fbo: u32) -> bool {
+        peglrs::handle_remote(&mut self.scene, fbo)
+    }
+
+    // Listen for OSC messages on a UDP address, port 0 picking a free one,
+    // or stop with None. `mappings` is a TOML file scaling and smoothing the
+    // values. Returns the port listened on. The messages only apply when
+    // calling handle_osc.
+    #[pyo3(signature = (addr = Some(String::from("127.0.0.1:0")), mappings = None))]
+    fn listen_osc(&mut self, addr: Option<String>, mappings: Option<PathBuf>) -> PyResult<Option<u16>> {
+        let config = match &mappings {
+            Some(path) => peglrs::OscConfig::load(path).ok_or_else(|| {
+                PyValueError::new_err(format!("couldn't load OSC mappings {}", path.display()))
+            })?,
+            None => peglrs::OscConfig::default(),
+        };
+        match addr {
+            Some(addr) => peglrs::set_osc(&mut self.scene, Some(&addr), config)
+                .map(|addr| Some(addr.port()))
+                .ok_or_else(|| PyValueError::new_err(format!("couldn't listen on {}", addr))),
+            None => {
+                peglrs::set_osc(&mut self.scene, None, config);
+                Ok(None)
+            }
+        }
+    }
+
+    // Apply the OSC messages received so far. True if the camera moved.
+    #[pyo3(signature = (fbo = 0))]
+    fn handle_osc(&mut self, fbo: u32) -> bool {
+        peglrs::handle_osc(&mut self.scene, fbo)
+    }
+
+    // Bind an image to a sampler of the pass named `pass`. sRGB images hold
+    // color, others data like normals.
+    #[pyo3(signature = (pass, sampler, path, srgb = true))]
+    fn set_pass_texture(&mut self, pass: &str, sampler: &str, path: PathBuf, srgb: bool) -> PyResult<()> {
+        peglrs::set_pass_texture(&mut self.scene, pass, sampler, &path, srgb).ok_or_else(|| {
+            PyValueError::new_err(format!("couldn't bind {} to {}.{}", path.display(), pass, sampler))
+        })
+    }
+
+    // Stop accumulating after `samples` samples, `time` seconds or once the
+    // noise estimate is below `noise`, whichever comes first, and save the
+    // image to `export`. With no argument, accumulate forever.
+    #[pyo3(signature = (samples = None, time = None, noise = None, export = None))]
+    fn set_goal(
+        &mut self,
+        samples: Option<u32>,
+        time: Option<f32>,
+        noise: Option<f32>,
+        export: Option<PathBuf>,
+    ) {
+        let goal = peglrs::RenderGoal {
+            samples,
+            time: time.map(|secs| Duration::from_secs_f32(secs.max(0.0))),
+            noise,
+            export,
+        };
+        let goal = if goal == peglrs::RenderGoal::default() {
+            None
+        } else {
+            Some(goal)
+        };
+        peglrs::set_render_goal(&mut self.scene, goal);
+    }
+
+    // Call `callback(reason, samples, seconds)` when the goal is reached.
+    // `reason` is "samples", "time" or "noise".
+    fn on_goal(&mut self, callback: PyObject) {
+        peglrs::on_goal_reached(&mut self.scene, move |report| {
+            Python::with_gil(|py| {
+                if let Err(err) = callback.call1(py, report_tuple(report)) {
+                    err.print(py);
+                }
+            });
+        });
+    }
+
+    // (reason, samples, seconds) once the goal is reached, else None.
+    #[getter]
+    fn goal_reached(&self) -> Option<(&'static str, u32, f32)> {
+        peglrs::goal_reached(&self.scene).map(report_tuple)
+    }
+
+    // True once the goal is reached: rendering only shows the last image.
+    #[getter]
+    fn idle(&self) -> bool {
+        peglrs::is_idle(&self.scene)
+    }
+
+    // (width, height, errors): the relative error of every pixel, rows from
+    // top to bottom.
+    fn noise_map(&self) -> (u32, u32, Vec<f32>) {
+        let map = peglrs::noise_map(&self.scene);
+        (map.width, map.height, map.error)
+    }
+
+    // Average relative error of the image, as used by the noise goal.
+    fn noise(&self) -> f32 {
+        peglrs::noise_map(&self.scene).mean_error()
+    }
+
+    // Call `callback` with no argument every time shaders or the pipeline
+    // get reloaded.
+    fn on_reload(&mut self, callback: PyObject) {
+        self.on_reload.push(callback);
+    }
+
+    // Render the accumulation in tiles of width by height pixels, a few every
+    // frame. Without a size, render it all at once again.
+    #[pyo3(signature = (width = None, height = None, per_frame = 1))]
+    fn set_tiling(&mut self, width: Option<i32>, height: Option<i32>, per_frame: usize) {
+        let config = width.zip(height).map(|(width, height)| peglrs::TileConfig {
+            width,
+            height,
+            per_frame,
+        });
+        peglrs::set_tiling(&mut self.scene, config);
+    }
+
+    // With a seed, the same scene renders the same image whatever the time
+    // given to render(). None goes back to seeding with the time.
+    #[pyo3(signature = (seed = None))]
+    fn set_seed(&mut self, seed: Option<u32>) {
+        peglrs::set_seed(&mut self.scene, seed);
+    }
+
+    // Measure the GPU time of every pass, printing it every `log_interval`
+    // seconds if given.
+    #[pyo3(signature = (enabled = true, log_interval = None))]
+    fn set_profiling(&mut self, enabled: bool, log_interval: Option<f32>) {
+        let interval = log_interval.map(|secs| Duration::from_secs_f32(secs.max(0.0)));
+        peglrs::set_profiling(&mut self.scene, enabled, interval);
+    }
+
+    // [(name, min, avg, max)] in milliseconds, over the last frames.
+    fn pass_timings(&self) -> Vec<(String, f64, f64, f64)> {
+        let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+        peglrs::pass_timings(&self.scene)
+            .into_iter()
+            .map(|t| (t.name, ms(t.min), ms(t.avg), ms(t.max)))
+            .collect()
+    }
+
+    #[getter]
+    fn frame_nb(&self) -> u32 {
+        self.scene.frame_nb
+    }
+
+    // Samples every pixel got at least, which differs from frame_nb with
+    // tiles.
+    #[getter]
+    fn samples(&self) -> u32 {
+        peglrs::sample_count(&self.scene)
+    }
+
+    #[getter]
+    fn size(&self) -> (f32, f32) {
+        (self.scene.size.x, self.scene.size.y)
+    }
+}
+
+#[pyfunction]
+fn print_gl_info() {
+    peglrs::print_gl_info();
+}
+
+#[pymodule]
+fn peglrs_py(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<Scene>()?;
+    m.add_function(wrap_pyfunction!(print_gl_info, m)?)?;
+    Ok(())
+}
@@ -0,0 +1,235 @@
+# Smoke test of the Python module, run with pytest from the repository root
+# after `maturin develop -m peglrs_py/Cargo.toml`. It only checks the bindings
+# reach the library, what they do is tested by the Rust unit tests.
+#
+# It renders with a surfaceless EGL context, so it also works with Mesa's
+# software renderer on a machine without display or GPU:
+#   EGL_PLATFORM=surfaceless LIBGL_ALWAYS_SOFTWARE=1 pytest peglrs_py/tests
+import json
+import os
+import socket
+import struct
+import threading
+import time
+from ctypes import CDLL, POINTER, byref, c_int, c_void_p
+
+import pytest
+
+peglrs_py = pytest.importorskip('peglrs_py')
+
+ROOT = os.path.join(os.path.dirname(__file__), '..', '..')
+
+EGL_PLATFORM_SURFACELESS_MESA = 0x31DD
+EGL_SURFACE_TYPE = 0x3033
+EGL_RENDERABLE_TYPE = 0x3040
+EGL_OPENGL_BIT = 0x0008
+EGL_NONE = 0x3038
+EGL_OPENGL_API = 0x30A2
+EGL_CONTEXT_MAJOR_VERSION = 0x3098
+EGL_CONTEXT_MINOR_VERSION = 0x30FB
+EGL_CONTEXT_OPENGL_PROFILE_MASK = 0x30FD
+EGL_CONTEXT_OPENGL_CORE_PROFILE_BIT = 0x0001
+
+
+def attribs(*values):
+    return (c_int * len(values))(*values)
+
+
+@pytest.fixture
+def gl_context():
+    try:
+        egl = CDLL('libEGL.so.1')
+    except OSError:
+        pytest.skip('libEGL is not available')
+
+    egl.eglGetPlatformDisplay.restype = c_void_p
+    egl.eglGetPlatformDisplay.argtypes = [c_int, c_void_p, c_void_p]
+    egl.eglInitialize.argtypes = [c_void_p, c_void_p, c_void_p]
+    egl.eglChooseConfig.argtypes = [c_void_p, POINTER(c_int), POINTER(c_void_p), c_int, POINTER(c_int)]
+    egl.eglCreateContext.restype = c_void_p
+    egl.eglCreateContext.argtypes = [c_void_p, c_void_p, c_void_p, POINTER(c_int)]
+    egl.eglMakeCurrent.argtypes = [c_void_p, c_void_p, c_void_p, c_void_p]
+    egl.eglDestroyContext.argtypes = [c_void_p, c_void_p]
+    egl.eglTerminate.argtypes = [c_void_p]
+
+    display = egl.eglGetPlatformDisplay(EGL_PLATFORM_SURFACELESS_MESA, None, None)
+    if not display or not egl.eglInitialize(display, None, None):
+        pytest.skip('no surfaceless EGL display')
+
+    config = c_void_p()
+    count = c_int()
+    config_attribs = attribs(EGL_SURFACE_TYPE, 0,
+                             EGL_RENDERABLE_TYPE, EGL_OPENGL_BIT,
+                             EGL_NONE)
+    if not egl.eglChooseConfig(display, config_attribs, byref(config), 1, byref(count)) or count.value == 0:
+        pytest.skip('no EGL config supporting desktop OpenGL')
+
+    egl.eglBindAPI(EGL_OPENGL_API)
+    context_attribs = attribs(EGL_CONTEXT_MAJOR_VERSION, 4,
+                              EGL_CONTEXT_MINOR_VERSION, 1,
+                              EGL_CONTEXT_OPENGL_PROFILE_MASK, EGL_CONTEXT_OPENGL_CORE_PROFILE_BIT,
+                              EGL_NONE)
+    context = egl.eglCreateContext(display, config, None, context_attribs)
+    if not context or not egl.eglMakeCurrent(display, None, None, context):
+        pytest.skip('couldn\'t create an OpenGL 4.1 core context')
+
+    yield
+
+    egl.eglMakeCurrent(display, None, None, None)
+    egl.eglDestroyContext(display, context)
+    egl.eglTerminate(display)
+
+
+def osc_string(s):
+    data = s.encode() + b'\0'
+    return data + b'\0' * (-len(data) % 4)
+
+
+def osc_message(address, *values):
+    tags = ',' + ''.join('i' if isinstance(v, int) else 'f' for v in values)
+    args = b''.join(struct.pack('>i' if isinstance(v, int) else '>f', v) for v in values)
+    return osc_string(address) + osc_string(tags) + args
+
+
+def thread_count():
+    return len(os.listdir('/proc/self/task'))
+
+
+def touch(path):
+    stat = path.stat()
+    os.utime(path, (stat.st_atime, stat.st_mtime + 10))
+
+
+def test_scene(gl_context, monkeypatch, tmp_path):
+    monkeypatch.chdir(ROOT)
+
+    # Dropped scenes free their programs and stop their watcher threads,
+    # which check every second.
+    threads = thread_count()
+    scenes = [peglrs_py.Scene(32, 16) for _ in range(2)]
+    for other in scenes:
+        other.render(0.0)
+    assert thread_count() > threads
+    del scenes, other
+    time.sleep(1.5)
+    assert thread_count() == threads
+
+    scene = peglrs_py.Scene(32, 16)
+    assert scene.size == (32.0, 16.0)
+    with pytest.raises(RuntimeError):
+        peglrs_py.Scene(32, 16, pipeline='does/not/exist.toml')
+
+    # Camera and uniforms.
+    up = (0.0, 1.0, 0.0)
+    scene.set_camera((0.0, 0.0, 0.0), (0.0, 0.0, -1.0), up, (0.5, 0.5), 0.0)
+    scene.handle_mouse(10.0, 0.0)
+    eye, target, _, focus_pos, _ = scene.camera()
+    assert eye == (0.0, 0.0, 0.0) and target != (0.0, 0.0, -1.0)
+    assert focus_pos == (0.5, 0.5)
+    scene.set_uniform('env_intensity', 1.5)
+    assert scene.uniform('env_intensity') == 1.5
+    scene.set_uniform('tint', (1.0, 0.5, 0.25))
+    with pytest.raises(ValueError):
+        scene.set_pass_samples(100, 4)
+
+    # Accumulation, resizing and the noise of the image.
+    reloads = []
+    scene.on_reload(lambda: reloads.append(True))
+    for frame in range(3):
+        scene.render(frame / 60.0)
+    assert scene.frame_nb == 3 and reloads == []
+    scene.resize(16, 8)
+    assert scene.size == (16.0, 8.0)
+    width, height, errors = scene.noise_map()
+    assert (width, height) == (16, 8) and len(errors) == 16 * 8
+    assert 0.0 <= scene.noise() <= 1.0
+    scene.reset()
+    assert scene.frame_nb == 0
+
+    # Render goals, tiling, seeds and profiling.
+    reports = []
+    scene.on_goal(lambda reason, samples, seconds: reports.append((reason, samples)))
+    scene.set_goal(samples=2)
+    for frame in range(3):
+        scene.render(frame / 60.0)
+    assert scene.idle and reports == [('samples', 2)]
+    scene.set_goal()
+    scene.set_tiling(8, 8, per_frame=1)
+    scene.render(1.0)
+    scene.set_tiling()
+    scene.set_seed(7)
+    scene.set_profiling()
+    scene.reset()
+    for frame in range(4):
+        scene.render(frame / 60.0)
+    assert 'path_tracer' in [name for name, _, _, _ in scene.pass_timings()]
+    scene.set_profiling(False)
+    scene.set_seed(None)
+
+    # Files: a pass texture, an environment, a timeline and a scene.
+    image = tmp_path / 'particle.png'
+    with open('data/images/particle.png', 'rb') as src:
+        image.write_bytes(src.read())
+    scene.set_pass_texture('denoiser', 'denoiserbuffer', str(image))
+    with pytest.raises(ValueError):
+        scene.set_pass_texture('nope', 'denoiserbuffer', str(image))
+
+    env = tmp_path / 'env.hdr'
+    env.write_bytes(b'#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 1 +X 2\n' + bytes([128, 128, 128, 129]) * 2)
+    scene.set_environment(str(env))
+    with pytest.raises(ValueError):
+        scene.set_environment(str(tmp_path / 'missing.hdr'))
+
+    timeline = tmp_path / 'timeline.toml'
+    scene.key_camera(0.0)
+    scene.save_timeline(str(timeline))
+    scene.set_timeline(str(timeline))
+    scene.apply_timeline(0.0)
+
+    world = tmp_path / 'world.toml'
+    world.write_text('[materials.light]\ntype = "emissive"\n'
+                     '[[spheres]]\ncenter = [0.0, 0.0, -3.0]\nradius = 1.0\nmaterial = "light"\n')
+    scene.load_world(str(world))
+    assert scene.world_objects == 1
+    with pytest.raises(ValueError):
+        scene.load_world(str(tmp_path / 'missing.toml'))
+    scene.render(0.0)
+
+    # Changed files are reloaded and start the accumulation over.
+    touch(image)
+    touch(world)
+    time.sleep(1.5)
+    assert scene.render(1.0)
+    assert scene.frame_nb == 1
+    scene.set_environment()
+    scene.load_world()
+    assert scene.world_objects == 9
+
+    # Remote control, from another thread as the commands only run when the
+    # scene handles them on this one.
+    port = scene.serve()
+    responses = []
+
+    def client():
+        with socket.create_connection(('127.0.0.1', port)) as sock:
+            request = {'jsonrpc': '2.0', 'id': 1, 'method': 'set_camera', 'params': {'aperture': 0.5}}
+            sock.sendall((json.dumps(request) + '\n').encode())
+            responses.append(json.loads(sock.makefile('r').readline()))
+
+    thread = threading.Thread(target=client)
+    thread.start()
+    while thread.is_alive():
+        scene.handle_remote()
+        time.sleep(0.01)
+    thread.join()
+    assert responses[0]['result']['aperture'] == 0.5
+    scene.serve(None)
+
+    # OSC.
+    port = scene.listen_osc()
+    with socket.socket(socket.AF_INET, socket.SOCK_DGRAM) as sock:
+        sock.sendto(osc_message('/stuffy/camera/aperture', 0.25), ('127.0.0.1', port))
+        time.sleep(0.05)
+    assert scene.handle_osc()
+    assert scene.camera()[4] == 0.25
+    scene.listen_osc(None)
@@ -1,2 +1,2 @@
 #! /bin/bash
-rustup run beta python3 editor.py
+maturin develop -m peglrs_py/Cargo.toml && python3 editor.py